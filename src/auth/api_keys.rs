@@ -0,0 +1,185 @@
+// src/auth/api_keys.rs
+use super::sessions::hash_token;
+use super::Claims;
+use crate::models::ApiKey;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use bson::doc;
+use log::warn;
+use mongodb::{Client, Collection};
+use rand::{rngs::OsRng, RngCore};
+use std::collections::HashMap;
+use std::env;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+// Keys look like `brg_<prefix>_<secret>`; the prefix is 8 hex characters
+const KEY_TAG: &str = "brg";
+const PREFIX_LEN: usize = 8;
+// `last_used_at` is only written when it is older than this
+const LAST_USED_RESOLUTION_MILLIS: i64 = 60 * 1000;
+
+pub fn api_keys(db_client: &Client) -> Collection<ApiKey> {
+    db_client.database("rustkeeper").collection("api_keys")
+}
+
+// A new random key, and the prefix that identifies it
+pub fn generate_key() -> (String, String) {
+    let mut id = [0u8; PREFIX_LEN / 2];
+    OsRng.fill_bytes(&mut id);
+    let mut secret = [0u8; 32];
+    OsRng.fill_bytes(&mut secret);
+
+    let prefix = hex::encode(id);
+    let key = format!("{}_{}_{}", KEY_TAG, prefix, URL_SAFE_NO_PAD.encode(secret));
+    (key, prefix)
+}
+
+// The prefix of a key presented by a client, if it is well formed
+pub fn parse_prefix(key: &str) -> Option<&str> {
+    let mut parts = key.splitn(3, '_');
+    if parts.next()? != KEY_TAG {
+        return None;
+    }
+    let prefix = parts.next()?;
+    let secret = parts.next()?;
+    let valid = prefix.len() == PREFIX_LEN
+        && prefix.bytes().all(|b| b.is_ascii_hexdigit())
+        && !secret.is_empty();
+    valid.then_some(prefix)
+}
+
+// The claims handlers see for a request made with `key`. The subject names
+// the key so that audit fields such as `created_by` identify it.
+pub fn key_claims(key: &ApiKey) -> Claims {
+    let role = key
+        .scopes
+        .iter()
+        .max()
+        .map(|scope| scope.role())
+        .unwrap_or_default();
+    let exp = key.expires_at.map_or(0, |expires_at| {
+        (expires_at.timestamp_millis() / 1000).max(0) as usize
+    });
+    Claims {
+        sub: format!("api-key:{}", key.prefix),
+        exp,
+        role,
+        jti: String::new(),
+        sid: String::new(),
+    }
+}
+
+// Recently used keys by prefix, so that busy callers do not cost a MongoDB
+// round trip per request. Changes made on another server take effect once
+// the entry ages out (`API_KEY_CACHE_SECS`, default 30).
+pub struct ApiKeyCache {
+    entries: RwLock<HashMap<String, (ApiKey, Instant)>>,
+    ttl: Duration,
+}
+
+impl ApiKeyCache {
+    pub fn new() -> Self {
+        let ttl = env::var("API_KEY_CACHE_SECS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(30);
+        ApiKeyCache {
+            entries: RwLock::new(HashMap::new()),
+            ttl: Duration::from_secs(ttl),
+        }
+    }
+
+    fn get(&self, prefix: &str) -> Option<ApiKey> {
+        let entries = self.entries.read().unwrap_or_else(|e| e.into_inner());
+        entries
+            .get(prefix)
+            .filter(|(_, loaded_at)| loaded_at.elapsed() < self.ttl)
+            .map(|(key, _)| key.clone())
+    }
+
+    fn put(&self, key: ApiKey) {
+        let mut entries = self.entries.write().unwrap_or_else(|e| e.into_inner());
+        let ttl = self.ttl;
+        entries.retain(|_, (_, loaded_at)| loaded_at.elapsed() < ttl);
+        entries.insert(key.prefix.clone(), (key, Instant::now()));
+    }
+
+    // Drop a key after it was changed or deleted
+    pub fn forget(&self, prefix: &str) {
+        let mut entries = self.entries.write().unwrap_or_else(|e| e.into_inner());
+        entries.remove(prefix);
+    }
+}
+
+impl Default for ApiKeyCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// The stored key matching `presented`, or `None` when it is unknown, wrong
+// or expired
+pub async fn authenticate(
+    db_client: &Client,
+    cache: Option<&ApiKeyCache>,
+    presented: &str,
+) -> mongodb::error::Result<Option<ApiKey>> {
+    let prefix = match parse_prefix(presented) {
+        Some(prefix) => prefix,
+        None => return Ok(None),
+    };
+    let (mut key, loaded) = match cache.and_then(|cache| cache.get(prefix)) {
+        Some(key) => (key, false),
+        None => match api_keys(db_client)
+            .find_one(doc! { "prefix": prefix }, None)
+            .await?
+        {
+            Some(key) => (key, true),
+            None => return Ok(None),
+        },
+    };
+
+    let valid = key.key_hash == hash_token(presented) && !key.is_expired();
+    let now = bson::DateTime::now();
+    let stale = key.last_used_at.is_none_or(|last_used_at| {
+        now.timestamp_millis() - last_used_at.timestamp_millis() >= LAST_USED_RESOLUTION_MILLIS
+    });
+    if valid && stale {
+        key.last_used_at = Some(now);
+        if let Err(e) = api_keys(db_client)
+            .update_one(
+                doc! { "_id": key._id },
+                doc! { "$set": { "last_used_at": now } },
+                None,
+            )
+            .await
+        {
+            warn!("Failed to record use of API key {}: {}", key.prefix, e);
+        }
+    }
+    if let Some(cache) = cache {
+        if loaded || (valid && stale) {
+            cache.put(key.clone());
+        }
+    }
+
+    Ok(valid.then_some(key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_keys_carry_their_prefix() {
+        let (key, prefix) = generate_key();
+        assert_eq!(parse_prefix(&key), Some(prefix.as_str()));
+        assert_ne!(generate_key().0, key);
+
+        assert_eq!(parse_prefix("brg_0123abcd_"), None);
+        assert_eq!(parse_prefix("brg_0123abc_secret"), None);
+        assert_eq!(parse_prefix("xyz_0123abcd_secret"), None);
+        // The base64url secret may itself contain underscores
+        assert_eq!(parse_prefix("brg_0123abcd_a_b"), Some("0123abcd"));
+    }
+}
@@ -0,0 +1,343 @@
+// src/auth/lockout.rs
+use crate::db::is_duplicate_key;
+use crate::models::{BlacklistedIp, EntryMetadata, SigninLockout};
+use crate::net::IpRange;
+use crate::ratelimit::keys::CounterKey;
+use bson::doc;
+use futures::stream::TryStreamExt;
+use mongodb::{
+    options::{FindOneAndUpdateOptions, ReturnDocument},
+    Client, Collection,
+};
+use std::env;
+use std::net::IpAddr;
+
+// Source recorded on blacklist entries created for repeat offenders
+pub const AUTO_ESCALATION_SOURCE: &str = "auto-escalation";
+
+// Lockout tuning, read from the environment:
+// - `SIGNIN_MAX_FAILURES`: failures in a row before a lockout (default 5)
+// - `SIGNIN_LOCKOUT_SECS`: first lockout; each further one doubles it (default 60)
+// - `SIGNIN_MAX_LOCKOUT_SECS`: upper bound for the backoff (default 86400)
+// - `SIGNIN_BLACKLIST_AFTER`: lockouts of one IP before it is blacklisted (default 3)
+// - `SIGNIN_BLACKLIST_SECS`: how long that blacklist entry lasts (default 86400)
+pub struct LockoutSettings {
+    pub max_failures: i32,
+    pub lockout_secs: i64,
+    pub max_lockout_secs: i64,
+    pub blacklist_after: i32,
+    pub blacklist_secs: i64,
+}
+
+impl LockoutSettings {
+    pub fn from_env() -> Self {
+        fn var<T: std::str::FromStr>(name: &str, default: T) -> T {
+            env::var(name)
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(default)
+        }
+        LockoutSettings {
+            max_failures: var("SIGNIN_MAX_FAILURES", 5).max(1),
+            lockout_secs: var("SIGNIN_LOCKOUT_SECS", 60).max(1),
+            max_lockout_secs: var("SIGNIN_MAX_LOCKOUT_SECS", 86400).max(1),
+            blacklist_after: var("SIGNIN_BLACKLIST_AFTER", 3).max(1),
+            blacklist_secs: var("SIGNIN_BLACKLIST_SECS", 86400).max(1),
+        }
+    }
+
+    // Exponential backoff: the n-th lockout (0-based) lasts `lockout_secs * 2^n`
+    pub fn lockout_duration(&self, previous_lockouts: i32) -> i64 {
+        let factor = 1_i64
+            .checked_shl(previous_lockouts.clamp(0, 62) as u32)
+            .unwrap_or(i64::MAX);
+        self.lockout_secs
+            .saturating_mul(factor)
+            .min(self.max_lockout_secs)
+    }
+}
+
+pub fn lockouts(db_client: &Client) -> Collection<SigninLockout> {
+    db_client
+        .database("rustkeeper")
+        .collection("signin_lockouts")
+}
+
+// Seconds left on the longest active lockout among `keys`, if any
+pub async fn locked_for(
+    db_client: &Client,
+    keys: &[&CounterKey],
+) -> mongodb::error::Result<Option<i64>> {
+    let now = bson::DateTime::now();
+    let keys: Vec<&str> = keys.iter().map(|key| key.key.as_str()).collect();
+    let active: Vec<SigninLockout> = lockouts(db_client)
+        .find(
+            doc! { "key": { "$in": keys }, "locked_until": { "$gt": now } },
+            None,
+        )
+        .await?
+        .try_collect()
+        .await?;
+
+    Ok(active
+        .iter()
+        .filter_map(|lockout| lockout.locked_until)
+        .map(|until| (until.timestamp_millis() - now.timestamp_millis() + 999) / 1000)
+        .max())
+}
+
+// Count a failed signin for `key`. Returns the record when this failure
+// started a new lockout.
+pub async fn record_failure(
+    db_client: &Client,
+    key: &CounterKey,
+    settings: &LockoutSettings,
+) -> mongodb::error::Result<Option<SigninLockout>> {
+    let collection = lockouts(db_client);
+    let now = chrono::Utc::now();
+    let forget_at =
+        bson::DateTime::from_millis((now + chrono::Duration::days(1)).timestamp_millis());
+
+    let update = doc! {
+        "$inc": { "failures": 1 },
+        "$set": {
+            "last_failure_at": bson::to_bson(&now)?,
+            "expires_at": forget_at,
+        },
+        "$setOnInsert": { "kind": &key.dimension, "lockouts": 0, "locked_until": null },
+    };
+    let options = FindOneAndUpdateOptions::builder()
+        .upsert(true)
+        .return_document(ReturnDocument::After)
+        .build();
+    // The unique index on `key` makes a racing first failure retry as an update
+    let record = loop {
+        match collection
+            .find_one_and_update(doc! { "key": &key.key }, update.clone(), options.clone())
+            .await
+        {
+            Ok(record) => break record,
+            Err(e) if is_duplicate_key(&e) => continue,
+            Err(e) => return Err(e),
+        }
+    };
+    let record = match record {
+        Some(record) if record.failures >= settings.max_failures => record,
+        _ => return Ok(None),
+    };
+
+    // Only the caller that still sees the failures it counted starts the
+    // lockout, so concurrent failures cannot lock twice
+    let duration = settings.lockout_duration(record.lockouts);
+    let locked_until = now + chrono::Duration::seconds(duration);
+    let forget_at = locked_until + chrono::Duration::days(1);
+    collection
+        .find_one_and_update(
+            doc! { "key": &key.key, "failures": record.failures },
+            doc! {
+                "$set": {
+                    "failures": 0,
+                    "locked_until": bson::DateTime::from_millis(locked_until.timestamp_millis()),
+                    "expires_at": bson::DateTime::from_millis(forget_at.timestamp_millis()),
+                },
+                "$inc": { "lockouts": 1 },
+            },
+            FindOneAndUpdateOptions::builder()
+                .return_document(ReturnDocument::After)
+                .build(),
+        )
+        .await
+}
+
+// Forget the failures of `key` after a successful signin
+pub async fn clear_failures(db_client: &Client, key: &CounterKey) -> mongodb::error::Result<()> {
+    lockouts(db_client)
+        .update_one(
+            doc! { "key": &key.key },
+            doc! { "$set": { "failures": 0 } },
+            None,
+        )
+        .await
+        .map(|_| ())
+}
+
+// Blacklist an IP that keeps getting locked out. Further lockouts extend
+// its auto-escalation entry rather than adding another one; the entry is
+// blocked again if it was lifted in the meantime.
+pub async fn escalate_ip(
+    db_client: &Client,
+    ip: &IpAddr,
+    lockouts: i32,
+    settings: &LockoutSettings,
+) -> mongodb::error::Result<Option<BlacklistedIp>> {
+    if lockouts < settings.blacklist_after {
+        return Ok(None);
+    }
+    let range = match IpRange::parse(&ip.to_string()) {
+        Ok(range) => range,
+        Err(_) => return Ok(None),
+    };
+
+    let expires_at = chrono::Utc::now() + chrono::Duration::seconds(settings.blacklist_secs);
+    let metadata = EntryMetadata::new(
+        Some(format!("{} signin lockouts", lockouts)),
+        vec!["signin".to_string()],
+        Some(AUTO_ESCALATION_SOURCE.to_string()),
+        None,
+    );
+    let entry = BlacklistedIp::new(range, Some(expires_at), metadata);
+    let update = escalation_update(&entry)?;
+
+    let options = FindOneAndUpdateOptions::builder()
+        .upsert(true)
+        .return_document(ReturnDocument::After)
+        .build();
+    let entry = db_client
+        .database("rustkeeper")
+        .collection::<BlacklistedIp>("blacklisted_ips")
+        .find_one_and_update(
+            doc! { "ip_address": &entry.ip_address, "source": AUTO_ESCALATION_SOURCE },
+            update,
+            options,
+        )
+        .await?;
+    println!("Blacklisted {} after {} signin lockouts", ip, lockouts);
+    Ok(entry)
+}
+
+// Upsert of an auto-escalation entry: the expiry only moves later, the status,
+// reason and update time are refreshed and everything else is kept.
+fn escalation_update(entry: &BlacklistedIp) -> bson::ser::Result<bson::Document> {
+    let mut on_insert = bson::to_document(entry)?;
+    // `to_document` renders the expiry as an RFC 3339 string, as for JSON
+    // responses; the TTL index and the expiry filters need a BSON date
+    on_insert.insert("expires_at", entry.expires_at);
+    let mut set = doc! {};
+    for field in ["status", "reason", "updated_at"] {
+        if let Some(value) = on_insert.remove(field) {
+            set.insert(field, value);
+        }
+    }
+    let extend = doc! { "expires_at": on_insert.remove("expires_at") };
+    Ok(doc! { "$set": set, "$max": extend, "$setOnInsert": on_insert })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{BlacklistCache, Snapshot, SyncMode};
+    use crate::handlers::blacklist_handler::lookup_ip;
+
+    #[test]
+    fn lockouts_back_off_exponentially_up_to_the_cap() {
+        let settings = LockoutSettings {
+            max_failures: 5,
+            lockout_secs: 60,
+            max_lockout_secs: 3600,
+            blacklist_after: 3,
+            blacklist_secs: 86400,
+        };
+        let durations: Vec<i64> = (0..8).map(|n| settings.lockout_duration(n)).collect();
+        assert_eq!(durations, vec![60, 120, 240, 480, 960, 1920, 3600, 3600]);
+        assert_eq!(settings.lockout_duration(i32::MAX), 3600);
+    }
+
+    #[test]
+    fn escalation_expiry_is_stored_as_a_date() {
+        let expires_at = chrono::Utc::now() + chrono::Duration::hours(1);
+        let entry = BlacklistedIp::new(
+            IpRange::parse("203.0.113.7").unwrap(),
+            Some(expires_at),
+            EntryMetadata::new(None, vec![], Some(AUTO_ESCALATION_SOURCE.to_string()), None),
+        );
+        let update = escalation_update(&entry).unwrap();
+        assert_eq!(
+            update.get_document("$max").unwrap().get("expires_at"),
+            Some(&bson::Bson::DateTime(bson::DateTime::from_millis(
+                expires_at.timestamp_millis()
+            )))
+        );
+        let on_insert = update.get_document("$setOnInsert").unwrap();
+        assert_eq!(on_insert.get_str("source").unwrap(), AUTO_ESCALATION_SOURCE);
+        assert!(!on_insert.contains_key("expires_at"));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires MongoDB (set MONGODB_URI)"]
+    async fn repeat_escalations_extend_one_entry() {
+        let uri = std::env::var("MONGODB_URI").expect("MONGODB_URI must be set");
+        let db_client = Client::with_uri_str(&uri).await.unwrap();
+        // An address of its own, so parallel runs do not share the entry
+        let suffix = bson::oid::ObjectId::new().bytes();
+        let ip: IpAddr = format!(
+            "2001:db8::{:x}:{:x}",
+            u16::from_be_bytes([suffix[8], suffix[9]]),
+            u16::from_be_bytes([suffix[10], suffix[11]])
+        )
+        .parse()
+        .unwrap();
+        let mut settings = LockoutSettings {
+            max_failures: 5,
+            lockout_secs: 60,
+            max_lockout_secs: 3600,
+            blacklist_after: 3,
+            blacklist_secs: 60,
+        };
+
+        assert!(escalate_ip(&db_client, &ip, 2, &settings)
+            .await
+            .unwrap()
+            .is_none());
+        let first = escalate_ip(&db_client, &ip, 3, &settings)
+            .await
+            .unwrap()
+            .unwrap();
+        settings.blacklist_secs = 3600;
+        let second = escalate_ip(&db_client, &ip, 4, &settings)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(first._id, second._id);
+        assert!(second.expires_at > first.expires_at);
+        assert_eq!(second.metadata.reason.as_deref(), Some("4 signin lockouts"));
+        assert_eq!(second.metadata.source, AUTO_ESCALATION_SOURCE);
+        assert_eq!(second.created_at, first.created_at);
+
+        // Both the MongoDB lookup and the cache see the entry as blocking
+        let found = lookup_ip(&db_client, &BlacklistCache::new(), &ip)
+            .await
+            .unwrap()
+            .expect("the escalated IP is blacklisted");
+        assert_eq!(found._id, second._id);
+        let entries = db_client
+            .database("rustkeeper")
+            .collection::<BlacklistedIp>("blacklisted_ips");
+        let filter = doc! { "ip_address": ip.to_string() };
+        let stored = entries
+            .clone_with_type::<bson::Document>()
+            .find_one(filter.clone(), None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(
+            stored.get("expires_at"),
+            Some(bson::Bson::DateTime(_))
+        ));
+        let cache = BlacklistCache::new();
+        cache.replace(
+            Snapshot {
+                ips: vec![bson::from_document(stored).unwrap()],
+                ..Snapshot::default()
+            },
+            SyncMode::Polling,
+        );
+        assert_eq!(cache.find_ip(&ip).and_then(|entry| entry._id), second._id);
+
+        assert_eq!(
+            entries.count_documents(filter.clone(), None).await.unwrap(),
+            1
+        );
+        entries.delete_many(filter, None).await.unwrap();
+    }
+}
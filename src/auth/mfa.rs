@@ -0,0 +1,303 @@
+// src/auth/mfa.rs
+use super::sessions::hash_token;
+use super::totp;
+use crate::models::brigatory_users::MfaSettings;
+use crate::models::{BrigatoryUser, MfaChallenge, MfaPolicy};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use bson::{doc, oid::ObjectId};
+use data_encoding::BASE32_NOPAD;
+use mongodb::{options::ReplaceOptions, Client, Collection};
+use rand::{rngs::OsRng, RngCore};
+use std::env;
+
+// How long a signin may wait for its second factor, and how many wrong codes it may try
+const CHALLENGE_TTL_SECS: i64 = 5 * 60;
+const MAX_CHALLENGE_ATTEMPTS: i32 = 5;
+const RECOVERY_CODE_COUNT: usize = 10;
+
+fn users(db_client: &Client) -> Collection<BrigatoryUser> {
+    db_client
+        .database("rustkeeper")
+        .collection("brigatory_users")
+}
+
+fn challenges(db_client: &Client) -> Collection<MfaChallenge> {
+    db_client
+        .database("rustkeeper")
+        .collection("mfa_challenges")
+}
+
+fn settings(db_client: &Client) -> Collection<MfaPolicy> {
+    db_client.database("rustkeeper").collection("settings")
+}
+
+// Issuer shown in authenticator apps
+pub fn issuer() -> String {
+    env::var("MFA_ISSUER").unwrap_or_else(|_| "Ratna".to_string())
+}
+
+pub async fn load_policy(db_client: &Client) -> mongodb::error::Result<MfaPolicy> {
+    Ok(settings(db_client)
+        .find_one(doc! { "_id": "mfa_policy" }, None)
+        .await?
+        .unwrap_or_default())
+}
+
+pub async fn save_policy(db_client: &Client, policy: &MfaPolicy) -> mongodb::error::Result<()> {
+    let mut document = bson::to_document(policy)?;
+    document.insert("_id", "mfa_policy");
+    settings(db_client)
+        .clone_with_type::<bson::Document>()
+        .replace_one(
+            doc! { "_id": "mfa_policy" },
+            document,
+            ReplaceOptions::builder().upsert(true).build(),
+        )
+        .await
+        .map(|_| ())
+}
+
+pub fn requires_mfa(policy: &MfaPolicy, user: &BrigatoryUser) -> bool {
+    policy.required_roles.contains(&user.role)
+}
+
+// Start waiting for the second factor of `user_id`; returns the token the client presents
+pub async fn create_challenge(
+    db_client: &Client,
+    user_id: ObjectId,
+    enrollment: bool,
+) -> mongodb::error::Result<String> {
+    let mut secret = [0u8; 32];
+    OsRng.fill_bytes(&mut secret);
+    let token = URL_SAFE_NO_PAD.encode(secret);
+
+    let expires_at = bson::DateTime::from_millis(
+        bson::DateTime::now().timestamp_millis() + CHALLENGE_TTL_SECS * 1000,
+    );
+    challenges(db_client)
+        .insert_one(
+            MfaChallenge {
+                _id: None,
+                token_hash: hash_token(&token),
+                user_id,
+                enrollment,
+                attempts: 0,
+                expires_at,
+            },
+            None,
+        )
+        .await?;
+    Ok(token)
+}
+
+// The open challenge for `token`, if it exists and has not expired
+pub async fn find_challenge(
+    db_client: &Client,
+    token: &str,
+) -> mongodb::error::Result<Option<MfaChallenge>> {
+    challenges(db_client)
+        .find_one(
+            doc! {
+                "token_hash": hash_token(token),
+                "expires_at": { "$gt": bson::DateTime::now() },
+                "attempts": { "$lt": MAX_CHALLENGE_ATTEMPTS },
+            },
+            None,
+        )
+        .await
+}
+
+// Count a wrong code; the challenge stops working after too many
+pub async fn fail_challenge(
+    db_client: &Client,
+    challenge: &MfaChallenge,
+) -> mongodb::error::Result<()> {
+    challenges(db_client)
+        .update_one(
+            doc! { "_id": challenge._id },
+            doc! { "$inc": { "attempts": 1 } },
+            None,
+        )
+        .await
+        .map(|_| ())
+}
+
+// Use up a challenge; false when a concurrent request already did
+pub async fn consume_challenge(
+    db_client: &Client,
+    challenge: &MfaChallenge,
+) -> mongodb::error::Result<bool> {
+    Ok(challenges(db_client)
+        .delete_one(doc! { "_id": challenge._id }, None)
+        .await?
+        .deleted_count
+        == 1)
+}
+
+// New recovery codes: the codes to show the user once, and the hashes to store
+pub fn generate_recovery_codes() -> (Vec<String>, Vec<String>) {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let mut bytes = [0u8; 5];
+            OsRng.fill_bytes(&mut bytes);
+            let code = BASE32_NOPAD.encode(&bytes).to_lowercase();
+            let code = format!("{}-{}", &code[..4], &code[4..]);
+            let hash = hash_recovery_code(&code);
+            (code, hash)
+        })
+        .unzip()
+}
+
+// Recovery codes are compared without case or separators
+fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    hash_token(&normalized)
+}
+
+// Hand out a new secret for the user to add to an authenticator app. It only
+// takes effect once `confirm_enrollment` sees a valid code for it.
+pub async fn begin_enrollment(
+    db_client: &Client,
+    user: &BrigatoryUser,
+) -> mongodb::error::Result<(String, String)> {
+    let secret = totp::generate_secret();
+    users(db_client)
+        .update_one(
+            doc! { "_id": user._id },
+            doc! { "$set": { "mfa.pending_secret": &secret } },
+            None,
+        )
+        .await?;
+    let uri = totp::provisioning_uri(&issuer(), &user.email, &secret);
+    Ok((secret, uri))
+}
+
+// Activate the pending secret if `code` was generated from it. Returns the
+// new recovery codes, or `None` when the code is wrong.
+pub async fn confirm_enrollment(
+    db_client: &Client,
+    user: &BrigatoryUser,
+    code: &str,
+) -> mongodb::error::Result<Option<Vec<String>>> {
+    let secret = match &user.mfa.pending_secret {
+        Some(secret) => secret,
+        None => return Ok(None),
+    };
+    let now = chrono::Utc::now();
+    let step = match totp::verify(secret, code, now.timestamp(), 0) {
+        Some(step) => step,
+        None => return Ok(None),
+    };
+
+    let (codes, hashes) = generate_recovery_codes();
+    let result = users(db_client)
+        .update_one(
+            doc! { "_id": user._id, "mfa.pending_secret": secret },
+            doc! {
+                "$set": {
+                    "mfa.enabled": true,
+                    "mfa.totp_secret": secret,
+                    "mfa.recovery_codes": hashes,
+                    "mfa.last_used_step": step,
+                    "mfa.enabled_at": bson::to_bson(&now)?,
+                },
+                "$unset": { "mfa.pending_secret": "" },
+            },
+            None,
+        )
+        .await?;
+    Ok((result.matched_count == 1).then_some(codes))
+}
+
+// Check a TOTP code or a recovery code. Each succeeds at most once: the TOTP
+// step is recorded and recovery codes are removed as they are used.
+pub async fn verify_second_factor(
+    db_client: &Client,
+    user: &BrigatoryUser,
+    code: Option<&str>,
+    recovery_code: Option<&str>,
+) -> mongodb::error::Result<bool> {
+    if let (Some(code), Some(secret)) = (code, &user.mfa.totp_secret) {
+        let now = chrono::Utc::now().timestamp();
+        if let Some(step) = totp::verify(secret, code, now, user.mfa.last_used_step) {
+            // Conditional on the step so the same code cannot win twice concurrently
+            let result = users(db_client)
+                .update_one(
+                    doc! { "_id": user._id, "mfa.last_used_step": { "$lt": step } },
+                    doc! { "$set": { "mfa.last_used_step": step } },
+                    None,
+                )
+                .await?;
+            return Ok(result.modified_count == 1);
+        }
+    }
+
+    if let Some(recovery_code) = recovery_code {
+        let hash = hash_recovery_code(recovery_code);
+        let result = users(db_client)
+            .update_one(
+                doc! { "_id": user._id, "mfa.recovery_codes": &hash },
+                doc! { "$pull": { "mfa.recovery_codes": &hash } },
+                None,
+            )
+            .await?;
+        return Ok(result.modified_count == 1);
+    }
+
+    Ok(false)
+}
+
+// Replace the recovery codes; returns the new codes
+pub async fn regenerate_recovery_codes(
+    db_client: &Client,
+    user: &BrigatoryUser,
+) -> mongodb::error::Result<Vec<String>> {
+    let (codes, hashes) = generate_recovery_codes();
+    users(db_client)
+        .update_one(
+            doc! { "_id": user._id },
+            doc! { "$set": { "mfa.recovery_codes": hashes } },
+            None,
+        )
+        .await?;
+    Ok(codes)
+}
+
+// Remove the second factor entirely
+pub async fn disable(db_client: &Client, user: &BrigatoryUser) -> mongodb::error::Result<()> {
+    users(db_client)
+        .update_one(
+            doc! { "_id": user._id },
+            doc! { "$set": { "mfa": bson::to_bson(&MfaSettings::default())? } },
+            None,
+        )
+        .await
+        .map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recovery_codes_are_unique_and_match_loosely() {
+        let (codes, hashes) = generate_recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        let mut unique = codes.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), codes.len());
+
+        let code = &codes[0];
+        assert_eq!(code.len(), 9);
+        assert_eq!(hash_recovery_code(code), hashes[0]);
+        assert_eq!(
+            hash_recovery_code(&code.to_uppercase().replace('-', " ")),
+            hashes[0]
+        );
+    }
+}
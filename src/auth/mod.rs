@@ -1,29 +1,56 @@
-use jsonwebtoken::{encode, EncodingKey, Header};
+pub mod api_keys;
+pub mod keys;
+pub mod lockout;
+pub mod mfa;
+pub mod password;
+pub mod roles;
+pub mod sessions;
+pub mod totp;
+pub use keys::KeyStore;
+pub use roles::Role;
+
 use serde::{Deserialize, Serialize};
+use std::env;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Claims {
     // Make the Claims struct public
     pub sub: String,
     pub exp: usize,
+    #[serde(default)]
+    pub role: Role,
+    pub jti: String, // Unique token id, checked against the revocation list
+    pub sid: String, // Signin session the token belongs to
+}
+
+// Access tokens are short-lived; clients renew them with a refresh token
+pub fn access_token_ttl() -> chrono::Duration {
+    let seconds = env::var("ACCESS_TOKEN_TTL_SECS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(15 * 60);
+    chrono::Duration::seconds(seconds)
 }
 
-pub fn generate_jwt(email: &str) -> Result<String, jsonwebtoken::errors::Error> {
+pub fn generate_jwt(
+    keys: &KeyStore,
+    email: &str,
+    role: Role,
+    session_id: &str,
+) -> Result<(String, Claims), jsonwebtoken::errors::Error> {
     let expiration = chrono::Utc::now()
-        .checked_add_signed(chrono::Duration::days(1))
+        .checked_add_signed(access_token_ttl())
         .expect("valid timestamp")
         .timestamp();
 
     let claims = Claims {
         sub: email.to_owned(),
         exp: expiration as usize,
+        role,
+        jti: uuid::Uuid::new_v4().to_string(),
+        sid: session_id.to_owned(),
     };
 
-    let token = encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret("your_secret_key".as_ref()),
-    )?;
-
-    Ok(token)
+    let token = keys.sign(&claims)?;
+    Ok((token, claims))
 }
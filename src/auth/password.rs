@@ -0,0 +1,454 @@
+// src/auth/password.rs
+use super::sessions::hash_token;
+use crate::models::brigatory_users::PasswordScheme;
+use crate::models::{BrigatoryUser, PasswordReset};
+use crate::notify::Notification;
+use argon2::password_hash::{self, PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use bson::{doc, oid::ObjectId};
+use mongodb::{Client, Collection};
+use rand::{rngs::OsRng, RngCore};
+use sha1::{Digest, Sha1};
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+
+// Rules for new passwords, read from the environment:
+// - `PASSWORD_MIN_LENGTH`: minimum number of characters (default 12)
+// - `PASSWORD_MAX_LENGTH`: maximum number of characters (default 128)
+// - `PASSWORD_HISTORY`: earlier passwords, besides the current one, that
+//   cannot be used again (default 5)
+// - `BREACHED_PASSWORDS_FILE`: known breached passwords, one per line, either
+//   in plain text or as SHA-1 hex in the "Have I Been Pwned" `HASH:COUNT` format
+// - `PASSWORD_HASH_MEMORY_KIB`, `PASSWORD_HASH_ITERATIONS` and
+//   `PASSWORD_HASH_PARALLELISM`: Argon2id parameters for new hashes (default
+//   19456, 2 and 1, the OWASP recommendation)
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub max_length: usize,
+    pub history: usize,
+    // Upper-case SHA-1 hex of every breached password
+    breached: HashSet<String>,
+    argon2: Params,
+}
+
+impl PasswordPolicy {
+    pub fn from_env() -> Result<Self, String> {
+        fn var(name: &str, default: usize) -> usize {
+            env::var(name)
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(default)
+        }
+        let breached = match env::var("BREACHED_PASSWORDS_FILE") {
+            Ok(path) => fs::read_to_string(&path)
+                .map_err(|e| format!("Cannot read breached password list {}: {}", path, e))?,
+            Err(_) => String::new(),
+        };
+        let argon2 = Params::new(
+            var("PASSWORD_HASH_MEMORY_KIB", 19456) as u32,
+            var("PASSWORD_HASH_ITERATIONS", 2) as u32,
+            var("PASSWORD_HASH_PARALLELISM", 1) as u32,
+            None,
+        )
+        .map_err(|e| format!("Invalid Argon2 parameters: {}", e))?;
+        let min_length = var("PASSWORD_MIN_LENGTH", 12).max(1);
+        Ok(PasswordPolicy {
+            min_length,
+            max_length: var("PASSWORD_MAX_LENGTH", 128).max(min_length),
+            history: var("PASSWORD_HISTORY", 5),
+            breached: parse_breached(&breached),
+            argon2,
+        })
+    }
+
+    // The scheme new hashes are created with
+    pub fn scheme(&self) -> PasswordScheme {
+        PasswordScheme::Argon2id {
+            memory_kib: self.argon2.m_cost(),
+            iterations: self.argon2.t_cost(),
+            parallelism: self.argon2.p_cost(),
+        }
+    }
+
+    // Hash a new password with Argon2id and a random salt
+    pub fn hash(&self, password: &str) -> Result<(String, PasswordScheme), String> {
+        let salt = SaltString::generate(&mut OsRng);
+        let hash = Argon2::new(Algorithm::Argon2id, Version::V0x13, self.argon2.clone())
+            .hash_password(password.as_bytes(), &salt)
+            .map_err(|e| e.to_string())?;
+        Ok((hash.to_string(), self.scheme()))
+    }
+
+    // Whether a hash was made with another algorithm or other parameters than
+    // the configured ones, and should be replaced at the next chance
+    pub fn needs_rehash(&self, hash: &str) -> bool {
+        PasswordScheme::of_hash(hash) != Some(self.scheme())
+    }
+
+    // Problems with `password` as a new password for `email`; empty when it is acceptable
+    pub fn check(&self, password: &str, email: &str) -> Vec<String> {
+        let mut problems = Vec::new();
+        let length = password.chars().count();
+        if length < self.min_length {
+            problems.push(format!(
+                "Password must be at least {} characters long",
+                self.min_length
+            ));
+        }
+        if length > self.max_length {
+            problems.push(format!(
+                "Password must be at most {} characters long",
+                self.max_length
+            ));
+        }
+        if password.eq_ignore_ascii_case(email) {
+            problems.push("Password must not be the same as the email".to_string());
+        }
+        if self.is_breached(password) {
+            problems.push("Password appears in a list of breached passwords".to_string());
+        }
+        problems
+    }
+
+    pub fn is_breached(&self, password: &str) -> bool {
+        !self.breached.is_empty() && self.breached.contains(&sha1_hex(password))
+    }
+
+    // Whether `password` is the user's current password or one of the earlier ones
+    pub fn is_reused(&self, password: &str, user: &BrigatoryUser) -> bool {
+        let recent = user.password_history.len().saturating_sub(self.history);
+        std::iter::once(&user.password)
+            .chain(&user.password_history[recent..])
+            .any(|previous| verify_password(password, previous).unwrap_or(false))
+    }
+}
+
+fn sha1_hex(password: &str) -> String {
+    hex::encode_upper(Sha1::digest(password.as_bytes()))
+}
+
+fn parse_breached(list: &str) -> HashSet<String> {
+    list.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let hash = line.split(':').next().unwrap_or_default();
+            if hash.len() == 40 && hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+                hash.to_ascii_uppercase()
+            } else {
+                sha1_hex(line)
+            }
+        })
+        .collect()
+}
+
+// Check a password against an Argon2 or bcrypt hash. Errors mean the hash
+// itself is unusable.
+pub fn verify_password(password: &str, hashed: &str) -> Result<bool, String> {
+    if hashed.starts_with("$argon2") {
+        let parsed = PasswordHash::new(hashed).map_err(|e| e.to_string())?;
+        // The algorithm and parameters are taken from the hash
+        match Argon2::default().verify_password(password.as_bytes(), &parsed) {
+            Ok(()) => Ok(true),
+            Err(password_hash::Error::Password) => Ok(false),
+            Err(e) => Err(e.to_string()),
+        }
+    } else {
+        bcrypt::verify(password, hashed).map_err(|e| e.to_string())
+    }
+}
+
+fn users(db_client: &Client) -> Collection<BrigatoryUser> {
+    db_client
+        .database("rustkeeper")
+        .collection("brigatory_users")
+}
+
+fn resets(db_client: &Client) -> Collection<PasswordReset> {
+    db_client
+        .database("rustkeeper")
+        .collection("password_resets")
+}
+
+// Replace the user's password with `new_hash`, keeping the old one in the
+// history. Returns false when the password changed concurrently.
+pub async fn set_password(
+    db_client: &Client,
+    user: &BrigatoryUser,
+    new_hash: &str,
+    scheme: PasswordScheme,
+    policy: &PasswordPolicy,
+) -> mongodb::error::Result<bool> {
+    let mut history = user.password_history.clone();
+    history.push(user.password.clone());
+    let expired = history.len().saturating_sub(policy.history);
+    history.drain(..expired);
+
+    let now = bson::to_bson(&chrono::Utc::now())?;
+    let result = users(db_client)
+        .update_one(
+            doc! { "_id": user._id, "password": &user.password },
+            doc! {
+                "$set": {
+                    "password": new_hash,
+                    "password_scheme": bson::to_bson(&scheme)?,
+                    "password_history": history,
+                    "password_changed_at": &now,
+                    "updated_at": &now,
+                }
+            },
+            None,
+        )
+        .await?;
+    Ok(result.modified_count == 1)
+}
+
+// After a successful signin, replace a bcrypt or outdated Argon2 hash of the
+// verified `password` with one using the current parameters. Users migrate
+// this way without having to reset their passwords.
+pub async fn upgrade_hash(
+    db_client: &Client,
+    user: &BrigatoryUser,
+    password: &str,
+    policy: &PasswordPolicy,
+) -> Result<(), String> {
+    if !policy.needs_rehash(&user.password) {
+        return Ok(());
+    }
+    let (new_hash, scheme) = policy.hash(password)?;
+    let scheme = bson::to_bson(&scheme).map_err(|e| e.to_string())?;
+    // Conditional on the old hash, so a concurrent password change wins
+    users(db_client)
+        .update_one(
+            doc! { "_id": user._id, "password": &user.password },
+            doc! { "$set": { "password": new_hash, "password_scheme": scheme } },
+            None,
+        )
+        .await
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+// How long a reset token works (`PASSWORD_RESET_TTL_SECS`, default one hour)
+pub fn reset_token_ttl() -> chrono::Duration {
+    let seconds = env::var("PASSWORD_RESET_TTL_SECS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(60 * 60);
+    chrono::Duration::seconds(seconds)
+}
+
+// Start a reset for `user_id`, replacing any earlier one. Returns the token to send.
+pub async fn create_reset(
+    db_client: &Client,
+    user_id: ObjectId,
+    requested_by: &str,
+) -> mongodb::error::Result<String> {
+    let mut secret = [0u8; 32];
+    OsRng.fill_bytes(&mut secret);
+    let token = URL_SAFE_NO_PAD.encode(secret);
+
+    let collection = resets(db_client);
+    collection
+        .delete_many(doc! { "user_id": user_id }, None)
+        .await?;
+    let now = chrono::Utc::now();
+    collection
+        .insert_one(
+            PasswordReset {
+                _id: None,
+                token_hash: hash_token(&token),
+                user_id,
+                requested_by: requested_by.to_string(),
+                expires_at: bson::DateTime::from_millis(
+                    (now + reset_token_ttl()).timestamp_millis(),
+                ),
+                created_at: now,
+            },
+            None,
+        )
+        .await?;
+    Ok(token)
+}
+
+// The open reset for `token`, if it exists and has not expired
+pub async fn find_reset(
+    db_client: &Client,
+    token: &str,
+) -> mongodb::error::Result<Option<PasswordReset>> {
+    resets(db_client)
+        .find_one(
+            doc! {
+                "token_hash": hash_token(token),
+                "expires_at": { "$gt": bson::DateTime::now() },
+            },
+            None,
+        )
+        .await
+}
+
+// Use up a reset; false when a concurrent request already did
+pub async fn consume_reset(
+    db_client: &Client,
+    reset: &PasswordReset,
+) -> mongodb::error::Result<bool> {
+    Ok(resets(db_client)
+        .delete_one(doc! { "_id": reset._id }, None)
+        .await?
+        .deleted_count
+        == 1)
+}
+
+// The message carrying a reset token. With `PASSWORD_RESET_URL` set (e.g.
+// "https://ratna.example/reset?token="), the token is appended to it.
+pub fn reset_notification(email: &str, token: &str) -> Notification {
+    let link = match env::var("PASSWORD_RESET_URL") {
+        Ok(base) => format!("{}{}", base, token),
+        Err(_) => format!("Reset token: {}", token),
+    };
+    Notification {
+        to: email.to_string(),
+        subject: "Reset your password".to_string(),
+        body: format!(
+            "A password reset was requested for your account.\n\n{}\n\nThe link works once and expires in {} minutes. If you did not ask for this, you can ignore this message.",
+            link,
+            reset_token_ttl().num_minutes()
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(breached: &str) -> PasswordPolicy {
+        PasswordPolicy {
+            min_length: 12,
+            max_length: 64,
+            history: 2,
+            breached: parse_breached(breached),
+            // Small parameters to keep the tests fast
+            argon2: Params::new(256, 1, 1, None).unwrap(),
+        }
+    }
+
+    #[test]
+    fn check_enforces_length_email_and_breached_list() {
+        let policy = policy(
+            "# common passwords\ncorrecthorsebattery\n5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:3861493\n",
+        );
+        assert!(policy
+            .check("a long enough passphrase", "ops@example.com")
+            .is_empty());
+        assert_eq!(policy.check("", "ops@example.com").len(), 1);
+        assert_eq!(policy.check(&"x".repeat(65), "ops@example.com").len(), 1);
+        assert_eq!(policy.check("Ops@Example.com", "ops@example.com").len(), 1);
+        // Plain-text entry
+        assert!(policy.is_breached("correcthorsebattery"));
+        // SHA-1 of "password", in HIBP format
+        assert!(policy.is_breached("password"));
+        assert!(!policy.is_breached("Password"));
+    }
+
+    #[test]
+    fn is_reused_checks_current_and_recent_passwords() {
+        let policy = PasswordPolicy {
+            history: 1,
+            ..policy("")
+        };
+        let mut user = BrigatoryUser::new(
+            "Ops".to_string(),
+            "ops@example.com".to_string(),
+            policy.hash("current password").unwrap().0,
+        );
+        user.password_history = vec![
+            bcrypt::hash("oldest password", 4).unwrap(),
+            bcrypt::hash("previous password", 4).unwrap(),
+        ];
+        assert!(policy.is_reused("current password", &user));
+        assert!(policy.is_reused("previous password", &user));
+        assert!(!policy.is_reused("oldest password", &user));
+    }
+
+    #[test]
+    fn bcrypt_and_weaker_argon2_hashes_need_a_rehash() {
+        let policy = policy("");
+        let (hash, scheme) = policy.hash("a long enough passphrase").unwrap();
+        assert!(hash.starts_with("$argon2id$v=19$m=256,t=1,p=1$"));
+        assert_eq!(PasswordScheme::of_hash(&hash), Some(scheme));
+        assert_eq!(verify_password("a long enough passphrase", &hash), Ok(true));
+        assert_eq!(verify_password("another passphrase", &hash), Ok(false));
+        assert!(!policy.needs_rehash(&hash));
+
+        let legacy = bcrypt::hash("a long enough passphrase", 4).unwrap();
+        assert_eq!(
+            PasswordScheme::of_hash(&legacy),
+            Some(PasswordScheme::Bcrypt { cost: 4 })
+        );
+        assert_eq!(
+            verify_password("a long enough passphrase", &legacy),
+            Ok(true)
+        );
+        assert!(policy.needs_rehash(&legacy));
+
+        let stronger = PasswordPolicy {
+            argon2: Params::new(512, 2, 1, None).unwrap(),
+            ..policy
+        };
+        assert!(stronger.needs_rehash(&hash));
+        assert!(verify_password("anything", "not a hash").is_err());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires MongoDB (set MONGODB_URI)"]
+    async fn reset_tokens_work_once_and_expire() {
+        let uri = std::env::var("MONGODB_URI").expect("MONGODB_URI must be set");
+        let db_client = Client::with_uri_str(&uri).await.unwrap();
+        let user_id = ObjectId::new();
+
+        let token = create_reset(&db_client, user_id, "ops@example.com")
+            .await
+            .unwrap();
+        let reset = find_reset(&db_client, &token).await.unwrap().unwrap();
+        assert_eq!(reset.user_id, user_id);
+        assert_ne!(reset.token_hash, token);
+        assert!(find_reset(&db_client, "not-a-token")
+            .await
+            .unwrap()
+            .is_none());
+
+        // A newer reset replaces the outstanding one
+        let newer = create_reset(&db_client, user_id, "ops@example.com")
+            .await
+            .unwrap();
+        assert!(find_reset(&db_client, &token).await.unwrap().is_none());
+        let reset = find_reset(&db_client, &newer).await.unwrap().unwrap();
+
+        assert!(consume_reset(&db_client, &reset).await.unwrap());
+        assert!(!consume_reset(&db_client, &reset).await.unwrap());
+        assert!(find_reset(&db_client, &newer).await.unwrap().is_none());
+
+        // Expired tokens are refused even before the TTL index removes them
+        let expiring = create_reset(&db_client, user_id, "ops@example.com")
+            .await
+            .unwrap();
+        resets(&db_client)
+            .update_one(
+                doc! { "user_id": user_id },
+                doc! { "$set": { "expires_at": bson::DateTime::from_millis(
+                    bson::DateTime::now().timestamp_millis() - 1000
+                ) } },
+                None,
+            )
+            .await
+            .unwrap();
+        assert!(find_reset(&db_client, &expiring).await.unwrap().is_none());
+
+        resets(&db_client)
+            .delete_many(doc! { "user_id": user_id }, None)
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,58 @@
+// src/auth/roles.rs
+use serde::{Deserialize, Serialize};
+
+// What a user (and the tokens issued to them) may do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    // Everything, including user and policy management
+    Admin,
+    // Manage blacklist entries
+    Operator,
+    // View entries and settings
+    #[default]
+    ReadOnly,
+    // Call the check endpoints only, e.g. from an edge proxy
+    Checker,
+}
+
+// Roles allowed on each class of endpoint
+pub const CHECK_ROLES: &[Role] = &[Role::Admin, Role::Operator, Role::ReadOnly, Role::Checker];
+pub const READ_ROLES: &[Role] = &[Role::Admin, Role::Operator, Role::ReadOnly];
+pub const WRITE_ROLES: &[Role] = &[Role::Admin, Role::Operator];
+pub const ADMIN_ROLES: &[Role] = &[Role::Admin];
+
+// What an API key may do. Keys are for services such as edge proxies, which
+// should not sign in as a user.
+// Ordered from least to most access.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiKeyScope {
+    // Call the check endpoints only
+    CheckOnly,
+    // Also view and manage blacklist entries
+    WriteBlacklist,
+    // Also manage rate limit policies and view the cache and signin lockouts
+    Admin,
+}
+
+impl ApiKeyScope {
+    // The role requests made with the key act as
+    pub fn role(self) -> Role {
+        match self {
+            ApiKeyScope::CheckOnly => Role::Checker,
+            ApiKeyScope::WriteBlacklist => Role::Operator,
+            ApiKeyScope::Admin => Role::Admin,
+        }
+    }
+}
+
+// Scopes accepted on each class of endpoint. Account, session and key
+// management never accept API keys.
+pub const CHECK_SCOPES: &[ApiKeyScope] = &[
+    ApiKeyScope::CheckOnly,
+    ApiKeyScope::WriteBlacklist,
+    ApiKeyScope::Admin,
+];
+pub const BLACKLIST_SCOPES: &[ApiKeyScope] = &[ApiKeyScope::WriteBlacklist, ApiKeyScope::Admin];
+pub const ADMIN_SCOPES: &[ApiKeyScope] = &[ApiKeyScope::Admin];
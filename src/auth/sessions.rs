@@ -0,0 +1,261 @@
+// src/auth/sessions.rs
+use super::{generate_jwt, KeyStore};
+use crate::db::is_duplicate_key;
+use crate::models::{BrigatoryUser, RefreshToken, RevokedToken};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use bson::{doc, oid::ObjectId, Document};
+use futures::stream::TryStreamExt;
+use mongodb::{options::InsertManyOptions, Client, Collection};
+use rand::{rngs::OsRng, RngCore};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::env;
+
+// Token pair returned by signin and refresh
+#[derive(Debug, Serialize)]
+pub struct TokenPair {
+    pub token: String, // Access token
+    pub refresh_token: String,
+    pub expires_in: i64, // Seconds until the access token expires
+}
+
+#[derive(Debug)]
+pub enum SessionError {
+    // Unknown, expired or revoked refresh token, or the user can no longer sign in
+    Invalid,
+    // A refresh token was presented a second time; the whole session is revoked
+    Reused,
+    Token(jsonwebtoken::errors::Error),
+    Database(mongodb::error::Error),
+}
+
+impl std::fmt::Display for SessionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SessionError::Invalid => write!(f, "Invalid refresh token"),
+            SessionError::Reused => write!(f, "Refresh token reuse detected; session revoked"),
+            SessionError::Token(e) => write!(f, "Error generating token: {}", e),
+            SessionError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+impl From<mongodb::error::Error> for SessionError {
+    fn from(e: mongodb::error::Error) -> Self {
+        SessionError::Database(e)
+    }
+}
+
+impl From<jsonwebtoken::errors::Error> for SessionError {
+    fn from(e: jsonwebtoken::errors::Error) -> Self {
+        SessionError::Token(e)
+    }
+}
+
+fn refresh_tokens(db_client: &Client) -> Collection<RefreshToken> {
+    db_client
+        .database("rustkeeper")
+        .collection("refresh_tokens")
+}
+
+fn revoked_tokens(db_client: &Client) -> Collection<RevokedToken> {
+    db_client
+        .database("rustkeeper")
+        .collection("revoked_tokens")
+}
+
+fn refresh_token_ttl() -> chrono::Duration {
+    let seconds = env::var("REFRESH_TOKEN_TTL_SECS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(30 * 24 * 60 * 60);
+    chrono::Duration::seconds(seconds)
+}
+
+fn to_bson_date(dt: chrono::DateTime<chrono::Utc>) -> bson::DateTime {
+    bson::DateTime::from_millis(dt.timestamp_millis())
+}
+
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+// Start a new session for a user who just signed in
+pub async fn start_session(
+    db_client: &Client,
+    keys: &KeyStore,
+    user: &BrigatoryUser,
+) -> Result<TokenPair, SessionError> {
+    let session_id = uuid::Uuid::new_v4().to_string();
+    issue_tokens(db_client, keys, user, &session_id).await
+}
+
+async fn issue_tokens(
+    db_client: &Client,
+    keys: &KeyStore,
+    user: &BrigatoryUser,
+    session_id: &str,
+) -> Result<TokenPair, SessionError> {
+    let user_id = user._id.ok_or(SessionError::Invalid)?;
+    let (token, claims) = generate_jwt(keys, &user.email, user.role, session_id)?;
+
+    let mut secret = [0u8; 32];
+    OsRng.fill_bytes(&mut secret);
+    let refresh_token = URL_SAFE_NO_PAD.encode(secret);
+
+    let now = chrono::Utc::now();
+    let record = RefreshToken {
+        _id: None,
+        token_hash: hash_token(&refresh_token),
+        session_id: session_id.to_string(),
+        user_id,
+        access_jti: claims.jti,
+        access_expires_at: bson::DateTime::from_millis(claims.exp as i64 * 1000),
+        expires_at: to_bson_date(now + refresh_token_ttl()),
+        used_at: None,
+        revoked_at: None,
+        created_at: now,
+    };
+    refresh_tokens(db_client).insert_one(record, None).await?;
+
+    Ok(TokenPair {
+        token,
+        refresh_token,
+        expires_in: claims.exp as i64 - now.timestamp(),
+    })
+}
+
+// Exchange a refresh token for a new token pair. The presented token is
+// consumed; presenting it again revokes the whole session, since it means
+// the token was copied.
+pub async fn refresh_session(
+    db_client: &Client,
+    keys: &KeyStore,
+    refresh_token: &str,
+) -> Result<TokenPair, SessionError> {
+    let collection = refresh_tokens(db_client);
+    let token_hash = hash_token(refresh_token);
+    let now = bson::DateTime::now();
+
+    // Claim the token atomically so two concurrent refreshes cannot both succeed
+    let claimed = collection
+        .find_one_and_update(
+            doc! {
+                "token_hash": &token_hash,
+                "used_at": null,
+                "revoked_at": null,
+                "expires_at": { "$gt": now },
+            },
+            doc! { "$set": { "used_at": now } },
+            None,
+        )
+        .await?;
+
+    let record = match claimed {
+        Some(record) => record,
+        None => {
+            return match collection
+                .find_one(doc! { "token_hash": &token_hash }, None)
+                .await?
+            {
+                Some(record) if record.used_at.is_some() && record.revoked_at.is_none() => {
+                    revoke_where(db_client, doc! { "session_id": &record.session_id }).await?;
+                    Err(SessionError::Reused)
+                }
+                _ => Err(SessionError::Invalid),
+            };
+        }
+    };
+
+    // Pick up role changes, and stop users who may no longer sign in
+    let user = db_client
+        .database("rustkeeper")
+        .collection::<BrigatoryUser>("brigatory_users")
+        .find_one(doc! { "_id": record.user_id }, None)
+        .await?;
+    match user {
+        Some(user) if user.can_sign_in() => {
+            issue_tokens(db_client, keys, &user, &record.session_id).await
+        }
+        _ => {
+            revoke_where(db_client, doc! { "session_id": &record.session_id }).await?;
+            Err(SessionError::Invalid)
+        }
+    }
+}
+
+// End one session: its refresh tokens stop working and its access tokens are revoked
+pub async fn revoke_session(db_client: &Client, session_id: &str) -> mongodb::error::Result<()> {
+    revoke_where(db_client, doc! { "session_id": session_id })
+        .await
+        .map(|_| ())
+}
+
+// End every session of a user; returns how many sessions were still active
+pub async fn revoke_user_sessions(
+    db_client: &Client,
+    user_id: ObjectId,
+) -> mongodb::error::Result<usize> {
+    revoke_where(db_client, doc! { "user_id": user_id }).await
+}
+
+async fn revoke_where(db_client: &Client, filter: Document) -> mongodb::error::Result<usize> {
+    let collection = refresh_tokens(db_client);
+    let mut active = filter.clone();
+    active.insert("revoked_at", bson::Bson::Null);
+
+    let records: Vec<RefreshToken> = collection
+        .find(active.clone(), None)
+        .await?
+        .try_collect()
+        .await?;
+    if records.is_empty() {
+        return Ok(0);
+    }
+
+    let now = chrono::Utc::now();
+    collection
+        .update_many(
+            active,
+            doc! { "$set": { "revoked_at": to_bson_date(now) } },
+            None,
+        )
+        .await?;
+
+    // Access tokens that have not expired yet must be rejected from now on
+    let revoked: Vec<RevokedToken> = records
+        .iter()
+        .filter(|record| record.access_expires_at > to_bson_date(now))
+        .map(|record| RevokedToken {
+            jti: record.access_jti.clone(),
+            expires_at: record.access_expires_at,
+            revoked_at: now,
+        })
+        .collect();
+    if !revoked.is_empty() {
+        let options = InsertManyOptions::builder().ordered(false).build();
+        match revoked_tokens(db_client)
+            .insert_many(revoked, options)
+            .await
+        {
+            Ok(_) => {}
+            // Already revoked by a concurrent call
+            Err(e) if is_duplicate_key(&e) => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    let sessions: HashSet<&str> = records
+        .iter()
+        .map(|record| record.session_id.as_str())
+        .collect();
+    Ok(sessions.len())
+}
+
+pub async fn is_revoked(db_client: &Client, jti: &str) -> mongodb::error::Result<bool> {
+    Ok(revoked_tokens(db_client)
+        .find_one(doc! { "jti": jti }, None)
+        .await?
+        .is_some())
+}
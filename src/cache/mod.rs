@@ -0,0 +1,764 @@
+// src/cache/mod.rs
+use crate::models::{AllowlistedIp, BlacklistedIp, MaliciousUrl, PolicyRule};
+use crate::net::{IpRange, UrlPattern};
+use bson::{doc, oid::ObjectId, Document};
+use chrono::{DateTime, Utc};
+use futures::stream::StreamExt;
+use log::{info, warn};
+use mongodb::{
+    change_stream::event::{ChangeStreamEvent, OperationType},
+    error::ErrorKind,
+    options::{ChangeStreamOptions, FullDocumentType},
+    Client, Collection,
+};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
+use std::time::Duration;
+
+const IP_COLLECTION: &str = "blacklisted_ips";
+const URL_COLLECTION: &str = "malicious_urls";
+const ALLOWLIST_COLLECTION: &str = "allowlisted_ips";
+const RULE_COLLECTION: &str = "policy_rules";
+
+// How the cache is currently kept up to date
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncMode {
+    Starting,
+    ChangeStream,
+    Polling,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CacheStatus {
+    pub ready: bool,
+    pub mode: SyncMode,
+    pub generation: u64,
+    pub last_sync: Option<DateTime<Utc>>,
+    pub ip_entries: usize,
+    pub url_entries: usize,
+    pub allowlist_entries: usize,
+    pub rule_entries: usize,
+}
+
+// In-process copy of `blacklisted_ips`, `malicious_urls`, `allowlisted_ips`
+// and `policy_rules`, so that the check endpoints and `/decide` can answer
+// without a MongoDB round trip.
+pub struct BlacklistCache {
+    state: RwLock<CacheState>,
+    generation: AtomicU64,
+}
+
+#[derive(Default)]
+struct CacheState {
+    ready: bool,
+    mode: Option<SyncMode>,
+    last_sync: Option<DateTime<Utc>>,
+    ips: HashMap<ObjectId, BlacklistedIp>,
+    ip_index: RangeIndex,
+    // Entries with their compiled patterns; unusable patterns never match
+    urls: HashMap<ObjectId, (MaliciousUrl, Option<UrlPattern>)>,
+    allowlist: HashMap<ObjectId, AllowlistedIp>,
+    allowlist_index: RangeIndex,
+    rules: HashMap<ObjectId, PolicyRule>,
+}
+
+// Entry ids by the range they cover, probed once per prefix length
+#[derive(Default)]
+struct RangeIndex(HashMap<IpRange, HashSet<ObjectId>>);
+
+impl RangeIndex {
+    fn insert(&mut self, range: IpRange, id: ObjectId) {
+        self.0.entry(range).or_default().insert(id);
+    }
+
+    fn remove(&mut self, range: &IpRange, id: &ObjectId) {
+        if let Some(ids) = self.0.get_mut(range) {
+            ids.remove(id);
+            if ids.is_empty() {
+                self.0.remove(range);
+            }
+        }
+    }
+
+    // Ids of the ranges containing `ip`, most specific first
+    fn containing<'a>(&'a self, ip: &IpAddr) -> impl Iterator<Item = &'a ObjectId> + 'a {
+        let ip = *ip;
+        let max_len = if ip.is_ipv4() { 32 } else { 128 };
+        (0..=max_len)
+            .rev()
+            .filter_map(move |len| self.0.get(&IpRange::covering(ip, len)))
+            .flatten()
+    }
+}
+
+// Everything the cache holds, as read from MongoDB
+#[derive(Default)]
+pub(crate) struct Snapshot {
+    pub(crate) ips: Vec<BlacklistedIp>,
+    pub(crate) urls: Vec<MaliciousUrl>,
+    pub(crate) allowlist: Vec<AllowlistedIp>,
+    pub(crate) rules: Vec<PolicyRule>,
+}
+
+impl CacheState {
+    fn insert_url(&mut self, id: ObjectId, entry: MaliciousUrl) {
+        let pattern = match entry.pattern() {
+            Ok(pattern) => Some(pattern),
+            Err(e) => {
+                warn!("Cached URL entry {} has an invalid pattern: {}", id, e);
+                None
+            }
+        };
+        self.urls.insert(id, (entry, pattern));
+    }
+
+    fn insert_ip(&mut self, id: ObjectId, entry: BlacklistedIp) {
+        self.remove_ip(&id);
+        match IpRange::parse(&entry.ip_address) {
+            Ok(range) => self.ip_index.insert(range, id),
+            Err(e) => warn!(
+                "Cached blacklist entry {} has an invalid address: {}",
+                id, e
+            ),
+        }
+        self.ips.insert(id, entry);
+    }
+
+    fn remove_ip(&mut self, id: &ObjectId) {
+        if let Some(old) = self.ips.remove(id) {
+            if let Ok(range) = IpRange::parse(&old.ip_address) {
+                self.ip_index.remove(&range, id);
+            }
+        }
+    }
+
+    fn insert_allowlisted(&mut self, id: ObjectId, entry: AllowlistedIp) {
+        self.remove_allowlisted(&id);
+        match IpRange::parse(&entry.ip_address) {
+            Ok(range) => self.allowlist_index.insert(range, id),
+            Err(e) => warn!(
+                "Cached allowlist entry {} has an invalid address: {}",
+                id, e
+            ),
+        }
+        self.allowlist.insert(id, entry);
+    }
+
+    fn remove_allowlisted(&mut self, id: &ObjectId) {
+        if let Some(old) = self.allowlist.remove(id) {
+            if let Ok(range) = IpRange::parse(&old.ip_address) {
+                self.allowlist_index.remove(&range, id);
+            }
+        }
+    }
+}
+
+impl Default for BlacklistCache {
+    fn default() -> Self {
+        BlacklistCache::new()
+    }
+}
+
+impl BlacklistCache {
+    pub fn new() -> Self {
+        BlacklistCache {
+            state: RwLock::new(CacheState::default()),
+            generation: AtomicU64::new(0),
+        }
+    }
+
+    // False until the first full load has completed
+    pub fn is_ready(&self) -> bool {
+        self.state.read().map(|state| state.ready).unwrap_or(false)
+    }
+
+    // Most specific active entry blocking `ip`, if any
+    pub fn find_ip(&self, ip: &IpAddr) -> Option<BlacklistedIp> {
+        let state = self.state.read().ok()?;
+        let now = bson::DateTime::now();
+        let found = state
+            .ip_index
+            .containing(ip)
+            .filter_map(|id| state.ips.get(id))
+            .find(|entry| entry.status == "blocked" && !is_expired(entry.expires_at, now))
+            .cloned();
+        found
+    }
+
+    // Most specific active allowlist entry covering `ip`, if any
+    pub fn find_allowlisted(&self, ip: &IpAddr) -> Option<AllowlistedIp> {
+        let state = self.state.read().ok()?;
+        let now = bson::DateTime::now();
+        let found = state
+            .allowlist_index
+            .containing(ip)
+            .filter_map(|id| state.allowlist.get(id))
+            .find(|entry| !is_expired(entry.expires_at, now))
+            .cloned();
+        found
+    }
+
+    // Enabled policy rules, in no particular order; `rules::evaluate` sorts them
+    pub fn enabled_rules(&self) -> Vec<PolicyRule> {
+        match self.state.read() {
+            Ok(state) => state
+                .rules
+                .values()
+                .filter(|rule| rule.enabled)
+                .cloned()
+                .collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    // Most specific active entry whose pattern matches `url`; the oldest one
+    // when several are equally specific, so the answer does not depend on
+    // the map's iteration order
+    pub fn find_url(&self, url: &str) -> Option<MaliciousUrl> {
+        let state = self.state.read().ok()?;
+        let found = state
+            .urls
+            .values()
+            .filter(|(entry, _)| entry.status == "blocked")
+            .filter_map(|(entry, pattern)| {
+                pattern
+                    .as_ref()
+                    .filter(|p| p.matches(url))
+                    .map(|p| (entry, p))
+            })
+            .min_by_key(|(entry, pattern)| (Reverse(pattern.specificity()), entry._id))
+            .map(|(entry, _)| entry.clone());
+        found
+    }
+
+    pub fn status(&self) -> CacheStatus {
+        let generation = self.generation.load(Ordering::SeqCst);
+        match self.state.read() {
+            Ok(state) => CacheStatus {
+                ready: state.ready,
+                mode: state.mode.unwrap_or(SyncMode::Starting),
+                generation,
+                last_sync: state.last_sync,
+                ip_entries: state.ips.len(),
+                url_entries: state.urls.len(),
+                allowlist_entries: state.allowlist.len(),
+                rule_entries: state.rules.len(),
+            },
+            Err(_) => CacheStatus {
+                ready: false,
+                mode: SyncMode::Starting,
+                generation,
+                last_sync: None,
+                ip_entries: 0,
+                url_entries: 0,
+                allowlist_entries: 0,
+                rule_entries: 0,
+            },
+        }
+    }
+
+    // Keep the cache in sync for the lifetime of the process. Change streams
+    // are preferred; deployments without a replica set fall back to polling.
+    pub async fn run_sync(&self, db_client: Client) {
+        let poll_interval = env::var("CACHE_POLL_INTERVAL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(5);
+        let poll_interval = Duration::from_secs(poll_interval);
+
+        loop {
+            match self.watch(&db_client).await {
+                Ok(()) => {}
+                Err(e) if change_streams_unsupported(&e) => {
+                    warn!(
+                        "Change streams unavailable ({}), polling every {:?}",
+                        e, poll_interval
+                    );
+                    self.poll(&db_client, poll_interval).await;
+                }
+                Err(e) => {
+                    // Likely transient: keep the cache fresh by polling once,
+                    // then try the change stream again
+                    warn!(
+                        "Blacklist cache change stream failed, retrying in {:?}: {}",
+                        poll_interval, e
+                    );
+                    if let Err(e) = self.reload(&db_client, SyncMode::Polling).await {
+                        warn!("Blacklist cache poll failed: {}", e);
+                    }
+                }
+            }
+            tokio::time::sleep(poll_interval).await;
+        }
+    }
+
+    // Open a change stream, take a full snapshot, then apply events until the stream ends
+    async fn watch(&self, db_client: &Client) -> mongodb::error::Result<()> {
+        let db = db_client.database("rustkeeper");
+        let pipeline = [doc! {
+            "$match": {
+                "ns.coll": {
+                    "$in": [IP_COLLECTION, URL_COLLECTION, ALLOWLIST_COLLECTION, RULE_COLLECTION]
+                }
+            }
+        }];
+        let options = ChangeStreamOptions::builder()
+            .full_document(Some(FullDocumentType::UpdateLookup))
+            .build();
+
+        // The stream is opened before the snapshot so no write can fall between the two
+        let mut stream = db.watch(pipeline, options).await?;
+        self.reload(db_client, SyncMode::ChangeStream).await?;
+        info!("Blacklist cache following change streams");
+
+        while let Some(event) = stream.next().await {
+            let event = event?;
+            self.apply_event(event);
+        }
+        Ok(())
+    }
+
+    // Re-read the collections on an interval. A full re-read is used rather
+    // than an `updated_at` watermark because it also notices deleted entries.
+    async fn poll(&self, db_client: &Client, interval: Duration) {
+        loop {
+            if let Err(e) = self.reload(db_client, SyncMode::Polling).await {
+                warn!("Blacklist cache poll failed: {}", e);
+            }
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    async fn reload(&self, db_client: &Client, mode: SyncMode) -> mongodb::error::Result<()> {
+        let db = db_client.database("rustkeeper");
+        let snapshot = Snapshot {
+            ips: load_all(&db.collection(IP_COLLECTION)).await?,
+            urls: load_all(&db.collection(URL_COLLECTION)).await?,
+            allowlist: load_all(&db.collection(ALLOWLIST_COLLECTION)).await?,
+            rules: load_all(&db.collection(RULE_COLLECTION)).await?,
+        };
+        self.replace(snapshot, mode);
+        Ok(())
+    }
+
+    // Swap in a full snapshot and mark the cache ready
+    pub(crate) fn replace(&self, snapshot: Snapshot, mode: SyncMode) {
+        let mut fresh = CacheState {
+            ready: true,
+            mode: Some(mode),
+            last_sync: Some(Utc::now()),
+            ..CacheState::default()
+        };
+        for entry in snapshot.ips {
+            if let Some(id) = entry._id {
+                fresh.insert_ip(id, entry);
+            }
+        }
+        for entry in snapshot.urls {
+            if let Some(id) = entry._id {
+                fresh.insert_url(id, entry);
+            }
+        }
+        for entry in snapshot.allowlist {
+            if let Some(id) = entry._id {
+                fresh.insert_allowlisted(id, entry);
+            }
+        }
+        for rule in snapshot.rules {
+            if let Some(id) = rule._id {
+                fresh.rules.insert(id, rule);
+            }
+        }
+
+        if let Ok(mut state) = self.state.write() {
+            *state = fresh;
+        }
+        self.generation.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn apply_event(&self, event: ChangeStreamEvent<Document>) {
+        let collection = match event.ns.as_ref().and_then(|ns| ns.coll.as_deref()) {
+            Some(coll) => coll.to_string(),
+            None => return,
+        };
+        let id = match event
+            .document_key
+            .as_ref()
+            .and_then(|key| key.get_object_id("_id").ok())
+        {
+            Some(id) => id,
+            None => return,
+        };
+
+        let mut state = match self.state.write() {
+            Ok(state) => state,
+            Err(_) => return,
+        };
+        match event.operation_type {
+            OperationType::Insert | OperationType::Update | OperationType::Replace => {
+                // `full_document` is missing if the entry was deleted before the lookup ran
+                match (collection.as_str(), event.full_document) {
+                    (IP_COLLECTION, Some(document)) => {
+                        if let Some(entry) = decode::<BlacklistedIp>(document) {
+                            state.insert_ip(id, entry);
+                        }
+                    }
+                    (URL_COLLECTION, Some(document)) => {
+                        if let Some(entry) = decode::<MaliciousUrl>(document) {
+                            state.insert_url(id, entry);
+                        }
+                    }
+                    (ALLOWLIST_COLLECTION, Some(document)) => {
+                        if let Some(entry) = decode::<AllowlistedIp>(document) {
+                            state.insert_allowlisted(id, entry);
+                        }
+                    }
+                    (RULE_COLLECTION, Some(document)) => {
+                        if let Some(rule) = decode::<PolicyRule>(document) {
+                            state.rules.insert(id, rule);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            OperationType::Delete => match collection.as_str() {
+                IP_COLLECTION => state.remove_ip(&id),
+                URL_COLLECTION => {
+                    state.urls.remove(&id);
+                }
+                ALLOWLIST_COLLECTION => state.remove_allowlisted(&id),
+                RULE_COLLECTION => {
+                    state.rules.remove(&id);
+                }
+                _ => {}
+            },
+            _ => return,
+        }
+        state.last_sync = Some(Utc::now());
+        drop(state);
+        self.generation.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+// Server error codes meaning change streams will never work on this
+// deployment, e.g. a standalone server, as opposed to a transient failure
+const CHANGE_STREAMS_UNSUPPORTED: [i32; 2] = [
+    40573, // "The $changeStream stage is only supported on replica sets"
+    40324, // "Unrecognized pipeline stage name: '$changeStream'", before MongoDB 3.6
+];
+
+fn change_streams_unsupported(error: &mongodb::error::Error) -> bool {
+    matches!(
+        error.kind.as_ref(),
+        ErrorKind::Command(command) if CHANGE_STREAMS_UNSUPPORTED.contains(&command.code)
+    )
+}
+
+fn is_expired(expires_at: Option<bson::DateTime>, now: bson::DateTime) -> bool {
+    matches!(expires_at, Some(expires_at) if expires_at <= now)
+}
+
+// Load a whole collection, skipping (and logging) documents that do not decode
+async fn load_all<T: DeserializeOwned>(
+    collection: &Collection<Document>,
+) -> mongodb::error::Result<Vec<T>> {
+    let mut cursor = collection.find(None, None).await?;
+    let mut results = Vec::new();
+    while let Some(document) = cursor.next().await {
+        if let Some(entry) = decode(document?) {
+            results.push(entry);
+        }
+    }
+    Ok(results)
+}
+
+fn decode<T: DeserializeOwned>(document: Document) -> Option<T> {
+    let id = document.get_object_id("_id").ok();
+    match bson::from_document(document) {
+        Ok(entry) => Some(entry),
+        Err(e) => {
+            warn!("Skipping undecodable cache entry {:?}: {}", id, e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::EntryMetadata;
+    use crate::net::UrlMatchType;
+    use crate::rules::{RuleAction, RuleConditions};
+    use chrono::Duration as ChronoDuration;
+
+    fn ip(input: &str) -> IpAddr {
+        input.parse().unwrap()
+    }
+
+    fn metadata() -> EntryMetadata {
+        EntryMetadata::new(None, vec![], None, None)
+    }
+
+    fn blocked(range: &str, expires_at: Option<DateTime<Utc>>) -> BlacklistedIp {
+        let mut entry = BlacklistedIp::new(IpRange::parse(range).unwrap(), expires_at, metadata());
+        entry._id = Some(ObjectId::new());
+        entry
+    }
+
+    fn cache_with(ips: Vec<BlacklistedIp>) -> BlacklistCache {
+        let cache = BlacklistCache::new();
+        cache.replace(
+            Snapshot {
+                ips,
+                ..Snapshot::default()
+            },
+            SyncMode::Polling,
+        );
+        cache
+    }
+
+    // A change stream event as MongoDB sends it
+    fn event(
+        operation: &str,
+        collection: &str,
+        id: ObjectId,
+        full: Option<Document>,
+    ) -> ChangeStreamEvent<Document> {
+        let mut event = doc! {
+            "_id": { "_data": "token" },
+            "operationType": operation,
+            "ns": { "db": "rustkeeper", "coll": collection },
+            "documentKey": { "_id": id },
+        };
+        if let Some(mut full) = full {
+            full.insert("_id", id);
+            event.insert("fullDocument", full);
+        }
+        bson::from_document(event).unwrap()
+    }
+
+    fn document<T: Serialize>(entry: &T) -> Document {
+        bson::to_document(entry).unwrap()
+    }
+
+    #[test]
+    fn index_entries_follow_inserts_updates_and_removals() {
+        let mut state = CacheState::default();
+        let id = ObjectId::new();
+        state.insert_ip(id, blocked("203.0.113.0/24", None));
+        assert!(state
+            .ip_index
+            .containing(&ip("203.0.113.7"))
+            .any(|found| *found == id));
+
+        // Re-inserting under the same id moves the entry to its new range
+        state.insert_ip(id, blocked("198.51.100.0/24", None));
+        assert_eq!(state.ip_index.containing(&ip("203.0.113.7")).count(), 0);
+        assert_eq!(state.ip_index.containing(&ip("198.51.100.7")).count(), 1);
+        assert_eq!(state.ip_index.0.len(), 1);
+
+        // Two entries may share a range; removing one keeps the other indexed
+        let other = ObjectId::new();
+        state.insert_ip(other, blocked("198.51.100.0/24", None));
+        state.remove_ip(&id);
+        assert_eq!(
+            state
+                .ip_index
+                .containing(&ip("198.51.100.7"))
+                .collect::<Vec<_>>(),
+            vec![&other]
+        );
+
+        state.remove_ip(&other);
+        assert!(state.ips.is_empty());
+        assert!(state.ip_index.0.is_empty());
+
+        // Entries with unparsable addresses are kept but never indexed
+        let mut invalid = blocked("192.0.2.1", None);
+        invalid.ip_address = "not-an-ip".to_string();
+        state.insert_ip(id, invalid);
+        assert_eq!(state.ips.len(), 1);
+        assert!(state.ip_index.0.is_empty());
+        state.remove_ip(&id);
+        assert!(state.ips.is_empty());
+    }
+
+    #[test]
+    fn the_most_specific_active_entry_wins() {
+        let wide = blocked("203.0.0.0/16", None);
+        let narrow = blocked("203.0.113.0/24", None);
+        let expired = blocked("203.0.113.7", Some(Utc::now() - ChronoDuration::minutes(1)));
+        let mut unblocked = blocked("203.0.113.8", None);
+        unblocked.status = "unblocked".to_string();
+        let cache = cache_with(vec![wide.clone(), narrow.clone(), expired, unblocked]);
+
+        let found = |addr: &str| cache.find_ip(&ip(addr)).and_then(|entry| entry._id);
+        assert_eq!(found("203.0.113.9"), narrow._id);
+        // Expired and unblocked entries are passed over for the next range out
+        assert_eq!(found("203.0.113.7"), narrow._id);
+        assert_eq!(found("203.0.113.8"), narrow._id);
+        assert_eq!(found("203.0.5.1"), wide._id);
+        assert_eq!(found("198.51.100.1"), None);
+        assert_eq!(found("::ffff:cb00:7109"), None);
+
+        let temporary = blocked("192.0.2.1", Some(Utc::now() + ChronoDuration::minutes(1)));
+        let cache = cache_with(vec![temporary.clone()]);
+        assert_eq!(
+            cache.find_ip(&ip("192.0.2.1")).and_then(|entry| entry._id),
+            temporary._id
+        );
+    }
+
+    #[test]
+    fn the_most_specific_url_entry_wins() {
+        let url = |match_type, pattern: &str| {
+            let compiled = UrlPattern::new(match_type, pattern, false).unwrap();
+            let mut entry = MaliciousUrl::new(
+                &compiled,
+                pattern.to_string(),
+                match_type,
+                false,
+                metadata(),
+            );
+            entry._id = Some(ObjectId::new());
+            entry
+        };
+        let glob = url(UrlMatchType::Glob, "*evil*");
+        let short = url(UrlMatchType::Prefix, "http://evil.com/");
+        let long = url(UrlMatchType::Prefix, "http://evil.com/download");
+        let twin = url(UrlMatchType::Prefix, "https://evil.com/download");
+        let mut lifted = url(UrlMatchType::Exact, "http://evil.com/download/x.exe");
+        lifted.status = "unblocked".to_string();
+
+        let cache = BlacklistCache::new();
+        cache.replace(
+            Snapshot {
+                urls: vec![twin, lifted, glob.clone(), short, long.clone()],
+                ..Snapshot::default()
+            },
+            SyncMode::Polling,
+        );
+        let found = |checked: &str| cache.find_url(checked).and_then(|entry| entry._id);
+        for _ in 0..10 {
+            assert_eq!(found("http://evil.com/download/x.exe"), long._id);
+        }
+        assert_eq!(found("ftp://evil.example/"), glob._id);
+
+        // Equally specific entries resolve to the oldest
+        let first = url(UrlMatchType::Suffix, ".exe");
+        let second = url(UrlMatchType::Suffix, ".EXE");
+        let cache = BlacklistCache::new();
+        cache.replace(
+            Snapshot {
+                urls: vec![second, first.clone()],
+                ..Snapshot::default()
+            },
+            SyncMode::Polling,
+        );
+        assert_eq!(
+            cache
+                .find_url("http://a.example/x.exe")
+                .and_then(|entry| entry._id),
+            first._id
+        );
+    }
+
+    #[test]
+    fn only_unsupported_deployments_fall_back_to_polling() {
+        let command_error = |code: i32| -> mongodb::error::Error {
+            let error: mongodb::error::CommandError =
+                bson::from_document(doc! { "code": code, "errmsg": "test" }).unwrap();
+            ErrorKind::Command(error).into()
+        };
+        assert!(change_streams_unsupported(&command_error(40573)));
+        assert!(change_streams_unsupported(&command_error(40324)));
+        // Not primary, network trouble and the like are worth retrying
+        assert!(!change_streams_unsupported(&command_error(10107)));
+        assert!(!change_streams_unsupported(&mongodb::error::Error::custom(
+            "connection reset"
+        )));
+    }
+
+    #[test]
+    fn lookups_wait_for_the_first_load() {
+        let cache = BlacklistCache::new();
+        assert!(!cache.is_ready());
+        assert!(cache.find_ip(&ip("203.0.113.7")).is_none());
+
+        let cache = cache_with(vec![blocked("203.0.113.7", None)]);
+        let status = cache.status();
+        assert!(status.ready);
+        assert_eq!(status.mode, SyncMode::Polling);
+        assert_eq!(status.ip_entries, 1);
+    }
+
+    #[test]
+    fn change_events_update_every_collection() {
+        let cache = cache_with(vec![]);
+        let generation = cache.status().generation;
+
+        let id = ObjectId::new();
+        let entry = blocked("203.0.113.0/24", None);
+        cache.apply_event(event("insert", IP_COLLECTION, id, Some(document(&entry))));
+        assert_eq!(
+            cache
+                .find_ip(&ip("203.0.113.7"))
+                .and_then(|entry| entry._id),
+            Some(id)
+        );
+
+        let mut unblocked = entry.clone();
+        unblocked.status = "unblocked".to_string();
+        cache.apply_event(event(
+            "update",
+            IP_COLLECTION,
+            id,
+            Some(document(&unblocked)),
+        ));
+        assert!(cache.find_ip(&ip("203.0.113.7")).is_none());
+        assert_eq!(cache.status().ip_entries, 1);
+
+        cache.apply_event(event("delete", IP_COLLECTION, id, None));
+        assert_eq!(cache.status().ip_entries, 0);
+
+        let allowed =
+            AllowlistedIp::new(IpRange::parse("198.51.100.0/24").unwrap(), None, metadata());
+        cache.apply_event(event(
+            "insert",
+            ALLOWLIST_COLLECTION,
+            id,
+            Some(document(&allowed)),
+        ));
+        assert!(cache.find_allowlisted(&ip("198.51.100.7")).is_some());
+        cache.apply_event(event("delete", ALLOWLIST_COLLECTION, id, None));
+        assert!(cache.find_allowlisted(&ip("198.51.100.7")).is_none());
+
+        let rule = PolicyRule::new(
+            "deny-admin".to_string(),
+            None,
+            10,
+            RuleAction::Deny,
+            RuleConditions::default(),
+            None,
+        );
+        cache.apply_event(event("insert", RULE_COLLECTION, id, Some(document(&rule))));
+        assert_eq!(cache.enabled_rules().len(), 1);
+        cache.apply_event(event("delete", RULE_COLLECTION, id, None));
+        assert!(cache.enabled_rules().is_empty());
+
+        // Updates whose document was deleted before the lookup, and other
+        // collections, leave the cache alone
+        cache.apply_event(event("update", IP_COLLECTION, id, None));
+        cache.apply_event(event("insert", "users", id, Some(document(&entry))));
+        assert_eq!(cache.status().ip_entries, 0);
+
+        assert!(cache.status().generation > generation);
+    }
+}
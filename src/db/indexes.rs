@@ -0,0 +1,217 @@
+// src/db/indexes.rs
+use mongodb::{bson::doc, options::IndexOptions, Client, Database, IndexModel};
+use std::time::Duration;
+
+// Create the indexes the handlers rely on. Safe to run on every startup.
+pub async fn ensure_indexes(db_client: &Client) -> mongodb::error::Result<()> {
+    let db = db_client.database("rustkeeper");
+
+    // TTL index: MongoDB purges temporary bans shortly after `expires_at`.
+    // Entries with no `expires_at` (permanent bans) are never touched.
+    db.collection::<mongodb::bson::Document>("blacklisted_ips")
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "expires_at": 1 })
+                .options(
+                    IndexOptions::builder()
+                        .expire_after(Duration::from_secs(0))
+                        .build(),
+                )
+                .build(),
+            None,
+        )
+        .await?;
+
+    // Allowlist lookups match on the range bounds; temporary entries are
+    // purged once they expire, like temporary bans
+    db.collection::<mongodb::bson::Document>("allowlisted_ips")
+        .create_indexes(
+            vec![
+                IndexModel::builder()
+                    .keys(doc! { "range_start": 1, "range_end": 1 })
+                    .build(),
+                IndexModel::builder()
+                    .keys(doc! { "expires_at": 1 })
+                    .options(
+                        IndexOptions::builder()
+                            .expire_after(Duration::from_secs(0))
+                            .build(),
+                    )
+                    .build(),
+            ],
+            None,
+        )
+        .await?;
+
+    // `/decide` loads the enabled rules in priority order until the cache is ready
+    db.collection::<mongodb::bson::Document>("policy_rules")
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "enabled": 1, "priority": -1 })
+                .build(),
+            None,
+        )
+        .await?;
+
+    ensure_rate_limit_indexes(&db).await?;
+    ensure_session_indexes(&db).await?;
+    ensure_lockout_indexes(&db).await?;
+
+    Ok(())
+}
+
+// Refresh tokens are looked up by hash and revoked by session or user; both
+// collections expire their documents once the tokens could no longer be used.
+pub async fn ensure_session_indexes(db: &Database) -> mongodb::error::Result<()> {
+    let expire_now = || {
+        IndexOptions::builder()
+            .expire_after(Duration::from_secs(0))
+            .build()
+    };
+
+    let refresh_tokens = db.collection::<mongodb::bson::Document>("refresh_tokens");
+    refresh_tokens
+        .create_indexes(
+            vec![
+                IndexModel::builder()
+                    .keys(doc! { "token_hash": 1 })
+                    .options(IndexOptions::builder().unique(true).build())
+                    .build(),
+                IndexModel::builder().keys(doc! { "session_id": 1 }).build(),
+                IndexModel::builder().keys(doc! { "user_id": 1 }).build(),
+                IndexModel::builder()
+                    .keys(doc! { "expires_at": 1 })
+                    .options(expire_now())
+                    .build(),
+            ],
+            None,
+        )
+        .await?;
+
+    // Signins waiting for their second factor
+    db.collection::<mongodb::bson::Document>("mfa_challenges")
+        .create_indexes(
+            vec![
+                IndexModel::builder()
+                    .keys(doc! { "token_hash": 1 })
+                    .options(IndexOptions::builder().unique(true).build())
+                    .build(),
+                IndexModel::builder()
+                    .keys(doc! { "expires_at": 1 })
+                    .options(expire_now())
+                    .build(),
+            ],
+            None,
+        )
+        .await?;
+
+    // Outstanding password resets, removed once they expire
+    db.collection::<mongodb::bson::Document>("password_resets")
+        .create_indexes(
+            vec![
+                IndexModel::builder()
+                    .keys(doc! { "token_hash": 1 })
+                    .options(IndexOptions::builder().unique(true).build())
+                    .build(),
+                IndexModel::builder()
+                    .keys(doc! { "expires_at": 1 })
+                    .options(expire_now())
+                    .build(),
+            ],
+            None,
+        )
+        .await?;
+
+    // API keys are looked up by prefix
+    db.collection::<mongodb::bson::Document>("api_keys")
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "prefix": 1 })
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+            None,
+        )
+        .await?;
+
+    db.collection::<mongodb::bson::Document>("revoked_tokens")
+        .create_indexes(
+            vec![
+                IndexModel::builder()
+                    .keys(doc! { "jti": 1 })
+                    .options(IndexOptions::builder().unique(true).build())
+                    .build(),
+                IndexModel::builder()
+                    .keys(doc! { "expires_at": 1 })
+                    .options(expire_now())
+                    .build(),
+            ],
+            None,
+        )
+        .await?;
+
+    Ok(())
+}
+
+// One counter document per key and policy; concurrent upserts for a new
+// counter rely on this index to collapse into a single document.
+pub async fn ensure_rate_limit_indexes(db: &Database) -> mongodb::error::Result<()> {
+    let rate_limits = db.collection::<mongodb::bson::Document>("rate_limits");
+
+    // Counters used to be keyed on `ip`; those unique indexes would reject
+    // documents without an `ip` field. They may not exist, so errors are ignored.
+    let _ = rate_limits.drop_index("ip_1", None).await;
+    let _ = rate_limits.drop_index("ip_1_policy_1", None).await;
+    // The old counters have no `key` or `policy` and would all index as
+    // (null, null), failing the unique index below. They are short-lived
+    // window counts, so they are discarded rather than migrated.
+    rate_limits
+        .delete_many(doc! { "key": { "$exists": false } }, None)
+        .await?;
+
+    rate_limits
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "key": 1, "policy": 1 })
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+            None,
+        )
+        .await?;
+
+    db.collection::<mongodb::bson::Document>("rate_limit_policies")
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "name": 1 })
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+            None,
+        )
+        .await?;
+
+    Ok(())
+}
+
+// One record per email or IP, forgotten a day after its last failure or lockout
+pub async fn ensure_lockout_indexes(db: &Database) -> mongodb::error::Result<()> {
+    db.collection::<mongodb::bson::Document>("signin_lockouts")
+        .create_indexes(
+            vec![
+                IndexModel::builder()
+                    .keys(doc! { "key": 1 })
+                    .options(IndexOptions::builder().unique(true).build())
+                    .build(),
+                IndexModel::builder()
+                    .keys(doc! { "expires_at": 1 })
+                    .options(
+                        IndexOptions::builder()
+                            .expire_after(Duration::from_secs(0))
+                            .build(),
+                    )
+                    .build(),
+            ],
+            None,
+        )
+        .await?;
+
+    Ok(())
+}
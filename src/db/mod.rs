@@ -1,2 +1,21 @@
 // src/db/mod.rs
+pub mod indexes;
 pub mod seed;
+
+use mongodb::error::{Error, ErrorKind, WriteFailure};
+
+// True when a write failed because it violated a unique index
+pub fn is_duplicate_key(error: &Error) -> bool {
+    match error.kind.as_ref() {
+        ErrorKind::Command(e) => e.code == 11000,
+        ErrorKind::Write(WriteFailure::WriteError(e)) => e.code == 11000,
+        // An unordered insert_many where every failure was a duplicate
+        ErrorKind::BulkWrite(e) => {
+            e.write_concern_error.is_none()
+                && e.write_errors
+                    .as_ref()
+                    .is_some_and(|errors| errors.iter().all(|e| e.code == 11000))
+        }
+        _ => false,
+    }
+}
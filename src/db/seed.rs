@@ -1,11 +1,15 @@
 // src/db/seed.rs
+use crate::auth::password::PasswordPolicy;
+use crate::auth::Role;
 use crate::models::BrigatoryUser;
 use actix_web::web::Data;
-use bcrypt::hash;
 use mongodb::{bson::doc, Client, Collection};
 use std::env;
 
-pub async fn seed_admin(db_client: Data<Client>) -> Result<(), Box<dyn std::error::Error>> {
+pub async fn seed_admin(
+    db_client: Data<Client>,
+    policy: &PasswordPolicy,
+) -> Result<(), Box<dyn std::error::Error>> {
     let collection: Collection<BrigatoryUser> = db_client
         .database("rustkeeper")
         .collection("brigatory_users");
@@ -22,7 +26,7 @@ pub async fn seed_admin(db_client: Data<Client>) -> Result<(), Box<dyn std::erro
 
     if !admin_exists {
         // Hash the default admin password
-        let hashed_password = hash(&admin_password, bcrypt::DEFAULT_COST)?;
+        let (hashed_password, scheme) = policy.hash(&admin_password)?;
 
         // Create the admin user
         let admin_user = BrigatoryUser {
@@ -30,13 +34,28 @@ pub async fn seed_admin(db_client: Data<Client>) -> Result<(), Box<dyn std::erro
             full_name: "Admin".to_string(),
             email: admin_email,
             password: hashed_password,
+            password_scheme: Some(scheme),
+            password_history: Vec::new(),
+            password_changed_at: Some(chrono::Utc::now()),
             status: "approved".to_string(),
+            role: Role::Admin,
+            history: Vec::new(),
+            mfa: Default::default(),
             created_at: chrono::Utc::now(),
             updated_at: chrono::Utc::now(),
         };
 
         // Insert the admin user into the database
         collection.insert_one(admin_user, None).await?;
+    } else {
+        // Admins seeded before roles existed would otherwise default to read-only
+        collection
+            .update_one(
+                doc! { "email": &admin_email, "role": { "$exists": false } },
+                doc! { "$set": { "role": "admin" } },
+                None,
+            )
+            .await?;
     }
 
     Ok(())
@@ -0,0 +1,224 @@
+// src/handlers/allowlist_handler.rs
+use crate::auth::Claims;
+use crate::cache::BlacklistCache;
+use crate::handlers::validation::{expiry_from_duration, validation_error, FieldError};
+use crate::models::entry_metadata::{apply_metadata_filter, normalize_tags};
+use crate::models::{blacklisted_ip::not_expired_filter, AllowlistedIp, EntryMetadata};
+use crate::net::{cidr::ip_key, IpRange};
+use actix_web::{web, HttpResponse, Responder};
+use futures::stream::TryStreamExt;
+use mongodb::{
+    bson::{self, doc, oid::ObjectId},
+    options::FindOptions,
+    Client, Collection,
+};
+use serde::Deserialize;
+use std::net::IpAddr;
+
+#[derive(Debug, Deserialize)]
+pub struct AllowlistInput {
+    pub ip_address: String,
+    pub duration: Option<String>, // e.g. "7d" for a temporary entry; omit for a permanent one
+    pub reason: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub source: Option<String>, // Defaults to "manual"
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListQuery {
+    pub tag: Option<String>,
+    pub source: Option<String>,
+}
+
+fn allowlist(db_client: &Client) -> Collection<AllowlistedIp> {
+    db_client
+        .database("rustkeeper")
+        .collection("allowlisted_ips")
+}
+
+// Parse and validate an entry; returns the range and expiry to store
+fn validate(
+    data: &AllowlistInput,
+) -> Result<(IpRange, Option<chrono::DateTime<chrono::Utc>>), HttpResponse> {
+    let mut errors = Vec::new();
+    let range = match IpRange::parse(&data.ip_address) {
+        Ok(range) => Some(range),
+        Err(e) => {
+            errors.push(FieldError::new("ip_address", &data.ip_address, e));
+            None
+        }
+    };
+    let expires_at = expiry_from_duration(data.duration.as_deref(), &mut errors);
+    match range {
+        Some(range) if errors.is_empty() => Ok((range, expires_at)),
+        _ => Err(validation_error(errors)),
+    }
+}
+
+// Find an active entry covering `ip`, if any. Checked before the blacklist
+// and the rate limiter, which an allowlisted address bypasses.
+pub async fn lookup_allowlisted_ip(
+    db_client: &Client,
+    cache: &BlacklistCache,
+    ip: &IpAddr,
+) -> mongodb::error::Result<Option<AllowlistedIp>> {
+    // Answer from memory once the cache has loaded; query MongoDB until then
+    if cache.is_ready() {
+        Ok(cache.find_allowlisted(ip))
+    } else {
+        find_allowlisted_ip(db_client, ip).await
+    }
+}
+
+async fn find_allowlisted_ip(
+    db_client: &Client,
+    ip: &IpAddr,
+) -> mongodb::error::Result<Option<AllowlistedIp>> {
+    let key = ip_key(ip);
+    let mut filter = not_expired_filter();
+    filter.insert("range_start", doc! { "$lte": &key });
+    filter.insert("range_end", doc! { "$gte": &key });
+    allowlist(db_client).find_one(filter, None).await
+}
+
+// Add an address or CIDR range to the allowlist
+pub async fn add_allowlist_ip(
+    db_client: web::Data<Client>,
+    claims: web::ReqData<Claims>,
+    data: web::Json<AllowlistInput>,
+) -> impl Responder {
+    let (range, expires_at) = match validate(&data) {
+        Ok(valid) => valid,
+        Err(response) => return response,
+    };
+
+    let data = data.into_inner();
+    let metadata = EntryMetadata::new(
+        data.reason,
+        data.tags,
+        data.source,
+        Some(claims.sub.clone()),
+    );
+    let mut entry = AllowlistedIp::new(range, expires_at, metadata);
+
+    match allowlist(&db_client).insert_one(&entry, None).await {
+        Ok(result) => {
+            entry._id = result.inserted_id.as_object_id();
+            HttpResponse::Created().json(entry)
+        }
+        Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
+    }
+}
+
+// List active entries, optionally filtered by tag and source
+pub async fn get_all_allowlist_ip(
+    db_client: web::Data<Client>,
+    query: web::Query<ListQuery>,
+) -> impl Responder {
+    let mut filter = not_expired_filter();
+    apply_metadata_filter(&mut filter, query.tag.as_deref(), query.source.as_deref());
+    let find_options = FindOptions::builder()
+        .sort(doc! { "created_at": -1 })
+        .build();
+    let found: Result<Vec<AllowlistedIp>, _> =
+        match allowlist(&db_client).find(filter, find_options).await {
+            Ok(cursor) => cursor.try_collect().await,
+            Err(e) => Err(e),
+        };
+    match found {
+        Ok(entries) => HttpResponse::Ok().json(entries),
+        Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
+    }
+}
+
+pub async fn get_allowlist_ip_by_id(
+    db_client: web::Data<Client>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let oid = match ObjectId::parse_str(path.into_inner()) {
+        Ok(oid) => oid,
+        Err(_) => return HttpResponse::BadRequest().body("Invalid ID format"),
+    };
+
+    match allowlist(&db_client)
+        .find_one(doc! { "_id": oid }, None)
+        .await
+    {
+        Ok(Some(entry)) => HttpResponse::Ok().json(entry),
+        Ok(None) => HttpResponse::NotFound().body("No entry found with the provided ID"),
+        Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
+    }
+}
+
+// Replace an entry's address, expiry, reason and tags
+pub async fn edit_allowlist_ip_by_id(
+    db_client: web::Data<Client>,
+    path: web::Path<String>,
+    data: web::Json<AllowlistInput>,
+) -> impl Responder {
+    let oid = match ObjectId::parse_str(path.into_inner()) {
+        Ok(oid) => oid,
+        Err(_) => return HttpResponse::BadRequest().body("Invalid ID format"),
+    };
+    let (range, expires_at) = match validate(&data) {
+        Ok(valid) => valid,
+        Err(response) => return response,
+    };
+
+    // Same format as `AllowlistedIp::new`
+    let updated_at = match bson::to_bson(&chrono::Utc::now()) {
+        Ok(updated_at) => updated_at,
+        Err(e) => return HttpResponse::InternalServerError().json(e.to_string()),
+    };
+    let mut set = doc! {
+        "ip_address": range.to_string(),
+        "prefix_len": range.prefix_len() as i32,
+        "range_start": range.start_key(),
+        "range_end": range.end_key(),
+        "expires_at": expires_at.map(|dt| bson::DateTime::from_millis(dt.timestamp_millis())),
+        "reason": data.reason.as_deref().filter(|r| !r.trim().is_empty()),
+        "tags": normalize_tags(data.tags.clone()),
+        "updated_at": updated_at,
+    };
+    if let Some(source) = data
+        .source
+        .as_deref()
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+    {
+        set.insert("source", source);
+    }
+
+    match allowlist(&db_client)
+        .update_one(doc! { "_id": oid }, doc! { "$set": set }, None)
+        .await
+    {
+        Ok(result) if result.matched_count == 1 => {
+            HttpResponse::Ok().json("Allowlisted IP successfully updated")
+        }
+        Ok(_) => HttpResponse::NotFound().body("No entry found with the provided ID"),
+        Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
+    }
+}
+
+pub async fn delete_allowlist_ip_by_id(
+    db_client: web::Data<Client>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let oid = match ObjectId::parse_str(path.into_inner()) {
+        Ok(oid) => oid,
+        Err(_) => return HttpResponse::BadRequest().body("Invalid ID format"),
+    };
+
+    match allowlist(&db_client)
+        .delete_one(doc! { "_id": oid }, None)
+        .await
+    {
+        Ok(result) if result.deleted_count == 1 => {
+            HttpResponse::Ok().json("Allowlisted IP successfully deleted")
+        }
+        Ok(_) => HttpResponse::NotFound().body("No entry found with the provided ID"),
+        Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
+    }
+}
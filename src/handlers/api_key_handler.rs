@@ -0,0 +1,245 @@
+// src/handlers/api_key_handler.rs
+use crate::auth::api_keys::{api_keys, generate_key, ApiKeyCache};
+use crate::auth::roles::ApiKeyScope;
+use crate::auth::sessions::hash_token;
+use crate::auth::Claims;
+use crate::handlers::validation::{expiry_from_duration, validation_error, FieldError};
+use crate::models::blacklisted_ip::serialize_expiry;
+use crate::models::ApiKey;
+use actix_web::{web, HttpResponse, Responder};
+use chrono::{DateTime, Utc};
+use futures::stream::TryStreamExt;
+use mongodb::{
+    bson::{self, doc, oid::ObjectId},
+    options::{FindOneAndUpdateOptions, FindOptions, ReturnDocument},
+    Client,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct ApiKeyInput {
+    pub name: String,
+    pub scopes: Vec<ApiKeyScope>,
+    pub duration: Option<String>, // e.g. "90d"; omit for a key that does not expire
+}
+
+// An API key as returned to clients, without its hash
+#[derive(Debug, Serialize)]
+struct ApiKeyInfo {
+    id: String,
+    name: String,
+    prefix: String,
+    scopes: Vec<ApiKeyScope>,
+    #[serde(serialize_with = "serialize_expiry")]
+    expires_at: Option<bson::DateTime>,
+    expired: bool,
+    #[serde(serialize_with = "serialize_expiry")]
+    last_used_at: Option<bson::DateTime>,
+    created_by: String,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+impl From<ApiKey> for ApiKeyInfo {
+    fn from(key: ApiKey) -> Self {
+        ApiKeyInfo {
+            id: key._id.map(|id| id.to_hex()).unwrap_or_default(),
+            expired: key.is_expired(),
+            name: key.name,
+            prefix: key.prefix,
+            scopes: key.scopes,
+            expires_at: key.expires_at,
+            last_used_at: key.last_used_at,
+            created_by: key.created_by,
+            created_at: key.created_at,
+            updated_at: key.updated_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct CreatedApiKey {
+    #[serde(flatten)]
+    info: ApiKeyInfo,
+    key: String,
+}
+
+// Validate a create or update request; returns the expiry to store
+fn validate(data: &ApiKeyInput) -> Result<Option<bson::DateTime>, HttpResponse> {
+    let mut errors = Vec::new();
+    if data.name.trim().is_empty() {
+        errors.push(FieldError::new(
+            "name",
+            &data.name,
+            "Name must not be empty",
+        ));
+    }
+    if data.scopes.is_empty() {
+        errors.push(FieldError::new(
+            "scopes",
+            "[]",
+            "At least one scope is required",
+        ));
+    }
+    let expires_at = expiry_from_duration(data.duration.as_deref(), &mut errors);
+    if !errors.is_empty() {
+        return Err(validation_error(errors));
+    }
+    Ok(expires_at.map(|dt| bson::DateTime::from_millis(dt.timestamp_millis())))
+}
+
+fn sorted_scopes(scopes: &[ApiKeyScope]) -> Vec<ApiKeyScope> {
+    let mut scopes = scopes.to_vec();
+    scopes.sort();
+    scopes.dedup();
+    scopes
+}
+
+// Create a key. The response is the only time the key itself is shown.
+pub async fn create_api_key(
+    db_client: web::Data<Client>,
+    claims: web::ReqData<Claims>,
+    data: web::Json<ApiKeyInput>,
+) -> impl Responder {
+    let expires_at = match validate(&data) {
+        Ok(expires_at) => expires_at,
+        Err(response) => return response,
+    };
+
+    let (secret, prefix) = generate_key();
+    let now = Utc::now();
+    let mut key = ApiKey {
+        _id: None,
+        name: data.name.trim().to_string(),
+        prefix,
+        key_hash: hash_token(&secret),
+        scopes: sorted_scopes(&data.scopes),
+        expires_at,
+        last_used_at: None,
+        created_by: claims.sub.clone(),
+        created_at: now,
+        updated_at: now,
+    };
+
+    match api_keys(&db_client).insert_one(&key, None).await {
+        Ok(result) => {
+            key._id = result.inserted_id.as_object_id();
+            HttpResponse::Created().json(CreatedApiKey {
+                info: ApiKeyInfo::from(key),
+                key: secret,
+            })
+        }
+        Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
+    }
+}
+
+// List keys, newest first
+pub async fn get_all_api_keys(db_client: web::Data<Client>) -> impl Responder {
+    let find_options = FindOptions::builder()
+        .sort(doc! { "created_at": -1 })
+        .build();
+    let found: Result<Vec<ApiKey>, _> = match api_keys(&db_client).find(doc! {}, find_options).await
+    {
+        Ok(cursor) => cursor.try_collect().await,
+        Err(e) => Err(e),
+    };
+    match found {
+        Ok(found) => {
+            HttpResponse::Ok().json(found.into_iter().map(ApiKeyInfo::from).collect::<Vec<_>>())
+        }
+        Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
+    }
+}
+
+pub async fn get_api_key_by_id(
+    db_client: web::Data<Client>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let oid = match ObjectId::parse_str(path.into_inner()) {
+        Ok(oid) => oid,
+        Err(_) => return HttpResponse::BadRequest().body("Invalid ID format"),
+    };
+
+    match api_keys(&db_client)
+        .find_one(doc! { "_id": oid }, None)
+        .await
+    {
+        Ok(Some(key)) => HttpResponse::Ok().json(ApiKeyInfo::from(key)),
+        Ok(None) => HttpResponse::NotFound().body("No API key found with the provided ID"),
+        Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
+    }
+}
+
+// Rename a key, change its scopes or set a new expiry. The key itself stays the same.
+pub async fn edit_api_key_by_id(
+    db_client: web::Data<Client>,
+    cache: web::Data<ApiKeyCache>,
+    path: web::Path<String>,
+    data: web::Json<ApiKeyInput>,
+) -> impl Responder {
+    let oid = match ObjectId::parse_str(path.into_inner()) {
+        Ok(oid) => oid,
+        Err(_) => return HttpResponse::BadRequest().body("Invalid ID format"),
+    };
+    let expires_at = match validate(&data) {
+        Ok(expires_at) => expires_at,
+        Err(response) => return response,
+    };
+
+    let (scopes, updated_at) = match (
+        bson::to_bson(&sorted_scopes(&data.scopes)),
+        bson::to_bson(&Utc::now()),
+    ) {
+        (Ok(scopes), Ok(updated_at)) => (scopes, updated_at),
+        (Err(e), _) | (_, Err(e)) => {
+            return HttpResponse::InternalServerError().json(e.to_string())
+        }
+    };
+    let update = doc! {
+        "$set": {
+            "name": data.name.trim(),
+            "scopes": scopes,
+            "expires_at": expires_at,
+            "updated_at": updated_at,
+        }
+    };
+    let options = FindOneAndUpdateOptions::builder()
+        .return_document(ReturnDocument::After)
+        .build();
+    match api_keys(&db_client)
+        .find_one_and_update(doc! { "_id": oid }, update, options)
+        .await
+    {
+        Ok(Some(key)) => {
+            cache.forget(&key.prefix);
+            HttpResponse::Ok().json(ApiKeyInfo::from(key))
+        }
+        Ok(None) => HttpResponse::NotFound().body("No API key found with the provided ID"),
+        Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
+    }
+}
+
+// Delete a key; it stops working at once on this server and within the
+// cache lifetime on others
+pub async fn delete_api_key_by_id(
+    db_client: web::Data<Client>,
+    cache: web::Data<ApiKeyCache>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let oid = match ObjectId::parse_str(path.into_inner()) {
+        Ok(oid) => oid,
+        Err(_) => return HttpResponse::BadRequest().body("Invalid ID format"),
+    };
+
+    match api_keys(&db_client)
+        .find_one_and_delete(doc! { "_id": oid }, None)
+        .await
+    {
+        Ok(Some(key)) => {
+            cache.forget(&key.prefix);
+            HttpResponse::Ok().json("API key successfully deleted")
+        }
+        Ok(None) => HttpResponse::NotFound().body("No API key found with the provided ID"),
+        Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
+    }
+}
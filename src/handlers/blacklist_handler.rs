@@ -1,4 +1,10 @@
-use crate::models::BlacklistedIp;
+use crate::auth::Claims;
+use crate::cache::BlacklistCache;
+use crate::handlers::allowlist_handler::lookup_allowlisted_ip;
+use crate::handlers::validation::{expiry_from_duration, validation_error, FieldError};
+use crate::models::entry_metadata::{apply_metadata_filter, normalize_tags};
+use crate::models::{blacklisted_ip::not_expired_filter, BlacklistedIp, EntryMetadata};
+use crate::net::{cidr::ip_key, parse_ip, IpRange};
 use actix_web::{web, HttpResponse, Responder};
 use futures::stream::StreamExt;
 use mongodb::{
@@ -8,40 +14,85 @@ use mongodb::{
 };
 
 use serde::Deserialize;
+use serde_json::json;
+use std::net::IpAddr;
 
 // Define a helper struct for deserializing incoming data
 #[derive(Debug, Deserialize)]
 pub struct InputData {
     pub ip_address: String,
+    pub duration: Option<String>, // e.g. "15m", "24h", "30d"; omit for a permanent ban
+    pub reason: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub source: Option<String>, // Defaults to "manual"
 }
 
 // Post request handler to add a new IP to the blacklist
 pub async fn add_blacklist_ip(
     db_client: web::Data<Client>,
     data: web::Json<InputData>,
+    claims: Option<web::ReqData<Claims>>,
 ) -> impl Responder {
     let collection: Collection<BlacklistedIp> = db_client
         .database("rustkeeper")
         .collection("blacklisted_ips");
 
+    let mut errors = Vec::new();
+
+    // Accept a single address or a CIDR range and store it in canonical form
+    let range = match IpRange::parse(&data.ip_address) {
+        Ok(range) => Some(range),
+        Err(e) => {
+            errors.push(FieldError::new("ip_address", &data.ip_address, e));
+            None
+        }
+    };
+    let expires_at = expiry_from_duration(data.duration.as_deref(), &mut errors);
+    let range = match range {
+        Some(range) if errors.is_empty() => range,
+        _ => return validation_error(errors),
+    };
+
+    let data = data.into_inner();
+    let metadata = EntryMetadata::new(
+        data.reason,
+        data.tags,
+        data.source,
+        claims.map(|claims| claims.sub.clone()),
+    );
+
     // Create a new BlacklistedIp using the helper method that sets timestamps and default status
-    let new_ip = BlacklistedIp::new(data.ip_address.clone());
+    let mut new_ip = BlacklistedIp::new(range, expires_at, metadata);
 
-    match collection.insert_one(new_ip, None).await {
-        Ok(_) => {
-            HttpResponse::Created().json("IP successfully added to blacklist with status 'blocked'")
+    match collection.insert_one(&new_ip, None).await {
+        Ok(result) => {
+            new_ip._id = result.inserted_id.as_object_id();
+            HttpResponse::Created().json(new_ip.with_remaining())
         }
         Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
     }
 }
 
-// Get all blocked IPs
-pub async fn get_all_blacklist_ip(db_client: web::Data<Client>) -> impl Responder {
+#[derive(Debug, Deserialize)]
+pub struct ListQuery {
+    pub tag: Option<String>,
+    pub source: Option<String>,
+}
+
+// Get all blocked IPs, optionally filtered by tag and source
+pub async fn get_all_blacklist_ip(
+    db_client: web::Data<Client>,
+    query: web::Query<ListQuery>,
+) -> impl Responder {
     let collection: Collection<BlacklistedIp> = db_client
         .database("rustkeeper")
         .collection("blacklisted_ips");
 
-    let filter = bson::doc! { "status": "blocked" };
+    // Expired temporary bans are no longer listed
+    let mut filter = not_expired_filter();
+    filter.insert("status", "blocked");
+    apply_metadata_filter(&mut filter, query.tag.as_deref(), query.source.as_deref());
     let find_options = FindOptions::builder()
         .sort(bson::doc! { "created_at": -1 })
         .build();
@@ -53,7 +104,7 @@ pub async fn get_all_blacklist_ip(db_client: web::Data<Client>) -> impl Responde
     let mut results: Vec<BlacklistedIp> = Vec::new();
     while let Some(result) = cursor.next().await {
         match result {
-            Ok(document) => results.push(document),
+            Ok(document) => results.push(document.with_remaining()),
             Err(e) => return HttpResponse::InternalServerError().json(e.to_string()),
         }
     }
@@ -78,7 +129,7 @@ pub async fn get_blacklist_ip_by_id(
 
     let filter = doc! { "_id": oid };
     match collection.find_one(filter, None).await {
-        Ok(Some(blacklisted_ip)) => HttpResponse::Ok().json(blacklisted_ip),
+        Ok(Some(blacklisted_ip)) => HttpResponse::Ok().json(blacklisted_ip.with_remaining()),
         Ok(None) => HttpResponse::NotFound().body("No entry found with the provided ID"),
         Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
     }
@@ -116,6 +167,9 @@ pub async fn delete_blacklist_ip_by_id(
 pub struct UpdateInputData {
     pub ip_address: String,
     pub status: String,
+    pub duration: Option<String>, // Restarts the ban with a new duration when present
+    pub reason: Option<String>,
+    pub tags: Option<Vec<String>>,
 }
 
 // Update a blacklisted IP by ID
@@ -134,13 +188,54 @@ pub async fn edit_blacklist_ip_by_id(
         Err(_) => return HttpResponse::BadRequest().body("Invalid ID format"),
     };
 
-    let update = doc! {
-        "$set": {
-            "ip_address": &data.ip_address,
-            "status": &data.status,
-            "updated_at": bson::DateTime::now(),  // Automatically update the 'updated_at' field
+    // Validate every field before touching the database
+    let mut errors = Vec::new();
+    let range = match IpRange::parse(&data.ip_address) {
+        Ok(range) => Some(range),
+        Err(e) => {
+            errors.push(FieldError::new("ip_address", &data.ip_address, e));
+            None
         }
     };
+    if data.status.trim().is_empty() {
+        errors.push(FieldError::new(
+            "status",
+            &data.status,
+            "status must not be empty",
+        ));
+    }
+    let expires_at = expiry_from_duration(data.duration.as_deref(), &mut errors);
+    let range = match range {
+        Some(range) if errors.is_empty() => range,
+        _ => return validation_error(errors),
+    };
+
+    // Same format as `BlacklistedIp::new`
+    let updated_at = match bson::to_bson(&chrono::Utc::now()) {
+        Ok(updated_at) => updated_at,
+        Err(e) => return HttpResponse::InternalServerError().json(e.to_string()),
+    };
+    let mut set = doc! {
+        "ip_address": range.to_string(),
+        "prefix_len": range.prefix_len() as i32,
+        "range_start": range.start_key(),
+        "range_end": range.end_key(),
+        "status": &data.status,
+        "updated_at": updated_at,
+    };
+    if let Some(expires_at) = expires_at {
+        set.insert(
+            "expires_at",
+            bson::DateTime::from_millis(expires_at.timestamp_millis()),
+        );
+    }
+    if let Some(reason) = &data.reason {
+        set.insert("reason", reason);
+    }
+    if let Some(tags) = &data.tags {
+        set.insert("tags", normalize_tags(tags.clone()));
+    }
+    let update = doc! { "$set": set };
 
     match collection
         .update_one(doc! { "_id": oid }, update, None)
@@ -166,26 +261,55 @@ pub struct CheckIpInput {
 // Check if IP is in the blacklist
 pub async fn is_blacklist_ip(
     db_client: web::Data<Client>,
+    cache: web::Data<BlacklistCache>,
     data: web::Json<CheckIpInput>,
 ) -> impl Responder {
     println!("Received request to check IP: {:?}", data.ip_address); // Add logging
 
-    let collection: Collection<BlacklistedIp> = db_client
-        .database("rustkeeper")
-        .collection("blacklisted_ips");
-
-    let filter = doc! { "ip_address": &data.ip_address, "status": "blocked" };
+    let ip = match parse_ip(&data.ip_address) {
+        Ok(ip) => ip,
+        Err(e) => {
+            return validation_error(vec![FieldError::new("ip_address", &data.ip_address, e)])
+        }
+    };
 
-    println!("Query filter: {:?}", filter); // Add logging
+    // Allowlisted addresses are never reported as blacklisted
+    match lookup_allowlisted_ip(&db_client, &cache, &ip).await {
+        Ok(Some(entry)) => {
+            return HttpResponse::Ok().json(json!({
+                "blacklisted": false,
+                "allowlisted": true,
+                "ip_address": ip.to_string(),
+                "matched": entry.ip_address,
+            }))
+        }
+        Ok(None) => {}
+        Err(e) => {
+            println!("Error checking allowlist: {}", e); // Add logging
+            return HttpResponse::InternalServerError().json(e.to_string());
+        }
+    }
 
-    match collection.find_one(filter, None).await {
+    match lookup_ip(&db_client, &cache, &ip).await {
         Ok(Some(result)) => {
             println!("IP is blacklisted: {:?}", result); // Add logging
-            HttpResponse::Ok().json(true) // IP is blacklisted
+            let result = result.with_remaining();
+            HttpResponse::Ok().json(json!({
+                "blacklisted": true,
+                "allowlisted": false,
+                "ip_address": ip.to_string(),
+                "matched": result.ip_address,
+                "expires_at": result.expires_at.and_then(|dt| dt.try_to_rfc3339_string().ok()),
+                "remaining_seconds": result.remaining_seconds,
+            })) // IP is blacklisted
         }
         Ok(None) => {
             println!("IP is not blacklisted: {:?}", data.ip_address); // Add logging
-            HttpResponse::Ok().json(false) // IP is not blacklisted
+            HttpResponse::Ok().json(json!({
+                "blacklisted": false,
+                "allowlisted": false,
+                "ip_address": ip.to_string(),
+            })) // IP is not blacklisted
         }
         Err(e) => {
             println!("Error checking blacklist: {}", e); // Add logging
@@ -193,3 +317,47 @@ pub async fn is_blacklist_ip(
         }
     }
 }
+
+// Find the active entry blocking `ip`, if any
+pub async fn lookup_ip(
+    db_client: &Client,
+    cache: &BlacklistCache,
+    ip: &IpAddr,
+) -> mongodb::error::Result<Option<BlacklistedIp>> {
+    // Answer from memory once the cache has loaded; query MongoDB until then
+    if cache.is_ready() {
+        Ok(cache.find_ip(ip))
+    } else {
+        find_blacklisted_ip(db_client, ip).await
+    }
+}
+
+// Look up an active entry blocking `ip` directly in MongoDB
+async fn find_blacklisted_ip(
+    db_client: &Client,
+    ip: &IpAddr,
+) -> mongodb::error::Result<Option<BlacklistedIp>> {
+    let collection: Collection<BlacklistedIp> = db_client
+        .database("rustkeeper")
+        .collection("blacklisted_ips");
+    let key = ip_key(ip);
+
+    // Match an exact entry or any stored range that contains the address,
+    // skipping temporary bans that have already expired
+    let filter = doc! {
+        "status": "blocked",
+        "$and": [
+            {
+                "$or": [
+                    { "ip_address": ip.to_string() },
+                    { "range_start": { "$lte": &key }, "range_end": { "$gte": &key } },
+                ]
+            },
+            not_expired_filter(),
+        ],
+    };
+
+    println!("Query filter: {:?}", filter); // Add logging
+
+    collection.find_one(filter, None).await
+}
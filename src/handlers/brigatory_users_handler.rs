@@ -1,10 +1,29 @@
-use crate::auth::generate_jwt;
-use crate::models::BrigatoryUser;
-use actix_web::{web, HttpResponse, Responder};
-use bcrypt::{hash, verify, DEFAULT_COST};
+use crate::auth::lockout::{
+    clear_failures, escalate_ip, locked_for, record_failure, LockoutSettings,
+};
+use crate::auth::mfa::{
+    begin_enrollment, confirm_enrollment, consume_challenge, create_challenge, fail_challenge,
+    find_challenge, load_policy, requires_mfa, verify_second_factor,
+};
+use crate::auth::password::{upgrade_hash, verify_password, PasswordPolicy};
+use crate::auth::sessions::{start_session, TokenPair};
+use crate::auth::{KeyStore, Role};
+use crate::cache::BlacklistCache;
+use crate::handlers::allowlist_handler::lookup_allowlisted_ip;
+use crate::handlers::blacklist_handler::lookup_ip;
+use crate::handlers::check_rate_limit_handler::insert_rate_limit_headers;
+use crate::handlers::password_handler::password_errors;
+use crate::handlers::rate_limit_policy_handler::find_policy;
+use crate::models::rate_limit_policy::SIGNIN_POLICY;
+use crate::models::{BrigatoryUser, MfaChallenge, RateLimitEntry, RateLimitPolicy};
+use crate::ratelimit::keys::{CounterKey, Dimension};
+use crate::ratelimit::{store, SystemClock};
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
 use mongodb::{bson::doc, Client, Collection};
 use serde::Deserialize;
 use serde::Serialize;
+use serde_json::json;
+use std::net::IpAddr;
 
 #[derive(Debug, Deserialize)]
 pub struct SignupData {
@@ -21,29 +40,74 @@ pub struct SigninData {
 
 #[derive(Debug, Serialize)]
 struct SigninResponse {
-    token: String,
+    status: &'static str, // "authenticated"; see `mfa_pending` for the other states
+    #[serde(flatten)]
+    tokens: TokenPair,
     user: UserInfo,
+    // Shown once, when the second factor was enrolled during this signin
+    #[serde(skip_serializing_if = "Option::is_none")]
+    recovery_codes: Option<Vec<String>>,
 }
 
+// Second step of a signin that needs a second factor
+#[derive(Debug, Deserialize)]
+pub struct MfaSigninData {
+    pub mfa_token: String,
+    pub code: Option<String>,
+    pub recovery_code: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MfaTokenData {
+    pub mfa_token: String,
+}
+
+// A user as returned to clients; never includes the password hash
 #[derive(Debug, Serialize)]
-struct UserInfo {
+pub struct UserInfo {
     _id: Option<bson::oid::ObjectId>,
     full_name: String,
     email: String,
     status: String,
+    role: Role,
+    mfa_enabled: bool,
     created_at: chrono::DateTime<chrono::Utc>,
     updated_at: chrono::DateTime<chrono::Utc>,
 }
 
+impl From<&BrigatoryUser> for UserInfo {
+    fn from(user: &BrigatoryUser) -> Self {
+        UserInfo {
+            _id: user._id,
+            full_name: user.full_name.clone(),
+            email: user.email.clone(),
+            status: user.status.clone(),
+            role: user.role,
+            mfa_enabled: user.mfa.enabled,
+            created_at: user.created_at,
+            updated_at: user.updated_at,
+        }
+    }
+}
+
 // Handler for user signup
-pub async fn signup(db_client: web::Data<Client>, data: web::Json<SignupData>) -> impl Responder {
+pub async fn signup(
+    db_client: web::Data<Client>,
+    policy: web::Data<PasswordPolicy>,
+    data: web::Json<SignupData>,
+) -> impl Responder {
     let collection: Collection<BrigatoryUser> = db_client
         .database("rustkeeper")
         .collection("brigatory_users");
 
+    let problems = policy.check(&data.password, &data.email);
+    if !problems.is_empty() {
+        return password_errors("password", problems);
+    }
+
     // Hash the password
-    let hashed_password = match hash(&data.password, DEFAULT_COST) {
-        Ok(hashed) => hashed,
+    let hashed_password = match policy.hash(&data.password) {
+        Ok((hashed, _)) => hashed,
         Err(_) => return HttpResponse::InternalServerError().body("Error hashing password"),
     };
 
@@ -59,11 +123,26 @@ pub async fn signup(db_client: web::Data<Client>, data: web::Json<SignupData>) -
 
 // Handler for user signin
 // Handler for user signin
-pub async fn signin(db_client: web::Data<Client>, data: web::Json<SigninData>) -> impl Responder {
+pub async fn signin(
+    req: HttpRequest,
+    db_client: web::Data<Client>,
+    keys: web::Data<KeyStore>,
+    cache: web::Data<BlacklistCache>,
+    password_policy: web::Data<PasswordPolicy>,
+    data: web::Json<SigninData>,
+) -> impl Responder {
     let collection: Collection<BrigatoryUser> = db_client
         .database("rustkeeper")
         .collection("brigatory_users");
 
+    // Brute-force protection applies to the email and to the connecting IP.
+    // The peer address is used rather than forwarding headers, which clients control.
+    let ip = req.peer_addr().map(|addr| addr.ip());
+    let subjects = signin_subjects(&data.email, ip);
+    if let Err(response) = guard_signin(&db_client, &cache, ip, &subjects).await {
+        return response;
+    }
+
     // Find the user by email
     let filter = doc! { "email": &data.email };
     let user = match collection.find_one(filter, None).await {
@@ -74,42 +153,434 @@ pub async fn signin(db_client: web::Data<Client>, data: web::Json<SigninData>) -
     // Check if the user exists
     let user = match user {
         Some(user) => user,
-        None => return HttpResponse::Unauthorized().body("Invalid email or password"),
+        None => {
+            record_failed_signin(&db_client, &cache, ip, &subjects).await;
+            return HttpResponse::Unauthorized().body("Invalid email or password");
+        }
     };
 
-    // Check if the user status is not pending
-    if user.status == "pending" {
-        return HttpResponse::Unauthorized().body("Account is pending approval");
-    }
-
-    // Verify the password
-    match verify(&data.password, &user.password) {
+    // Verify the password. The account status is only revealed to someone
+    // who knows it, so the response cannot be used to probe accounts.
+    match verify_password(&data.password, &user.password) {
         Ok(true) => {
-            // Generate the JWT token
-            match generate_jwt(&user.email) {
-                Ok(token) => {
-                    // Create the user info response
-                    let user_info = UserInfo {
-                        _id: user._id.clone(),
-                        full_name: user.full_name.clone(),
-                        email: user.email.clone(),
-                        status: user.status.clone(),
-                        created_at: user.created_at,
-                        updated_at: user.updated_at,
-                    };
-
-                    // Create the signin response
-                    let response = SigninResponse {
-                        token,
-                        user: user_info,
-                    };
-
-                    HttpResponse::Ok().json(response)
+            // Check if the user status is not pending
+            if user.status == "pending" {
+                return HttpResponse::Unauthorized().body("Account is pending approval");
+            }
+            // Rejected and suspended accounts are refused as well
+            if !user.can_sign_in() {
+                return HttpResponse::Unauthorized().body(format!("Account is {}", user.status));
+            }
+            for subject in &subjects {
+                if let Err(e) = clear_failures(&db_client, subject).await {
+                    println!("Failed to clear signin failures: {}", e);
                 }
-                Err(_) => HttpResponse::InternalServerError().body("Error generating token"),
             }
+            // Move bcrypt and outdated hashes to the current Argon2id parameters
+            if let Err(e) = upgrade_hash(&db_client, &user, &data.password, &password_policy).await
+            {
+                println!("Failed to upgrade password hash of {}: {}", user.email, e);
+            }
+
+            // Users with a second factor, or whose role requires one, get a
+            // challenge to complete instead of tokens
+            let policy = match load_policy(&db_client).await {
+                Ok(policy) => policy,
+                Err(e) => return HttpResponse::InternalServerError().json(e.to_string()),
+            };
+            if user.mfa.enabled || requires_mfa(&policy, &user) {
+                return mfa_pending(&db_client, &user).await;
+            }
+
+            complete_signin(&db_client, &keys, &user, None).await
+        }
+        Ok(false) => {
+            record_failed_signin(&db_client, &cache, ip, &subjects).await;
+            HttpResponse::Unauthorized().body("Invalid email or password")
         }
-        Ok(false) => HttpResponse::Unauthorized().body("Invalid email or password"),
         Err(_) => HttpResponse::InternalServerError().body("Error verifying password"),
     }
 }
+
+// Tell the client to continue at `/signin/mfa` with the returned token
+async fn mfa_pending(db_client: &Client, user: &BrigatoryUser) -> HttpResponse {
+    let user_id = match user._id {
+        Some(user_id) => user_id,
+        None => return HttpResponse::InternalServerError().finish(),
+    };
+    let enrollment = !user.mfa.enabled;
+    match create_challenge(db_client, user_id, enrollment).await {
+        Ok(mfa_token) if enrollment => HttpResponse::Ok().json(json!({
+            "status": "mfa_enrollment_required",
+            "mfa_token": mfa_token,
+        })),
+        Ok(mfa_token) => HttpResponse::Ok().json(json!({
+            "status": "mfa_required",
+            "mfa_token": mfa_token,
+            "methods": ["totp", "recovery_code"],
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
+    }
+}
+
+// Start a session with an access and a refresh token
+async fn complete_signin(
+    db_client: &Client,
+    keys: &KeyStore,
+    user: &BrigatoryUser,
+    recovery_codes: Option<Vec<String>>,
+) -> HttpResponse {
+    match start_session(db_client, keys, user).await {
+        Ok(tokens) => HttpResponse::Ok().json(SigninResponse {
+            status: "authenticated",
+            tokens,
+            user: UserInfo::from(user),
+            recovery_codes,
+        }),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+// Load the user an MFA token was issued to, if the token is still open
+async fn mfa_signin_user(
+    db_client: &Client,
+    mfa_token: &str,
+) -> Result<(MfaChallenge, BrigatoryUser), HttpResponse> {
+    let invalid = || HttpResponse::Unauthorized().body("Invalid or expired MFA token");
+    let challenge = match find_challenge(db_client, mfa_token).await {
+        Ok(Some(challenge)) => challenge,
+        Ok(None) => return Err(invalid()),
+        Err(e) => return Err(HttpResponse::InternalServerError().json(e.to_string())),
+    };
+    let collection: Collection<BrigatoryUser> = db_client
+        .database("rustkeeper")
+        .collection("brigatory_users");
+    match collection
+        .find_one(doc! { "_id": challenge.user_id }, None)
+        .await
+    {
+        Ok(Some(user)) if user.can_sign_in() => Ok((challenge, user)),
+        Ok(_) => Err(invalid()),
+        Err(e) => Err(HttpResponse::InternalServerError().json(e.to_string())),
+    }
+}
+
+// Second signin step: a TOTP or recovery code, or for users who must enroll,
+// a code from the authenticator they just set up
+pub async fn signin_mfa(
+    req: HttpRequest,
+    db_client: web::Data<Client>,
+    keys: web::Data<KeyStore>,
+    cache: web::Data<BlacklistCache>,
+    data: web::Json<MfaSigninData>,
+) -> impl Responder {
+    let (challenge, user) = match mfa_signin_user(&db_client, &data.mfa_token).await {
+        Ok(found) => found,
+        Err(response) => return response,
+    };
+    let ip = req.peer_addr().map(|addr| addr.ip());
+    let subjects = signin_subjects(&user.email, ip);
+    if let Err(response) = guard_signin(&db_client, &cache, ip, &subjects).await {
+        return response;
+    }
+
+    let verified = if challenge.enrollment {
+        match data.code.as_deref() {
+            Some(code) => confirm_enrollment(&db_client, &user, code)
+                .await
+                .map(|codes| codes.map(Some)),
+            None => Ok(None),
+        }
+    } else {
+        verify_second_factor(
+            &db_client,
+            &user,
+            data.code.as_deref(),
+            data.recovery_code.as_deref(),
+        )
+        .await
+        .map(|verified| verified.then_some(None))
+    };
+
+    match verified {
+        Ok(Some(recovery_codes)) => match consume_challenge(&db_client, &challenge).await {
+            Ok(true) => complete_signin(&db_client, &keys, &user, recovery_codes).await,
+            Ok(false) => HttpResponse::Unauthorized().body("Invalid or expired MFA token"),
+            Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
+        },
+        Ok(None) => {
+            if let Err(e) = fail_challenge(&db_client, &challenge).await {
+                println!("Failed to count MFA attempt: {}", e);
+            }
+            record_failed_signin(&db_client, &cache, ip, &subjects).await;
+            HttpResponse::Unauthorized().body("Invalid code")
+        }
+        Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
+    }
+}
+
+// For users who must enroll before signing in: the secret and otpauth URI
+// to add to an authenticator app, confirmed through `/signin/mfa`
+pub async fn signin_mfa_enroll(
+    db_client: web::Data<Client>,
+    data: web::Json<MfaTokenData>,
+) -> impl Responder {
+    let (challenge, user) = match mfa_signin_user(&db_client, &data.mfa_token).await {
+        Ok(found) => found,
+        Err(response) => return response,
+    };
+    if !challenge.enrollment {
+        return HttpResponse::Conflict().body("Two-factor authentication is already enabled");
+    }
+
+    match begin_enrollment(&db_client, &user).await {
+        Ok((secret, uri)) => HttpResponse::Ok().json(json!({
+            "secret": secret,
+            "otpauth_uri": uri,
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
+    }
+}
+
+// Counter keys signin attempts are tracked under: the email and the source IP
+pub fn signin_subjects(email: &str, ip: Option<IpAddr>) -> Vec<CounterKey> {
+    let mut dimensions = vec![Dimension::single("email", &email.trim().to_lowercase())];
+    if let Some(ip) = ip {
+        dimensions.push(Dimension::single("ip", &ip.to_string()));
+    }
+    dimensions
+        .iter()
+        .filter_map(|dimension| dimension.to_counter_key().ok())
+        .collect()
+}
+
+// `ip` unless it is allowlisted. Allowlisted addresses, such as office or
+// monitoring ranges, are never refused, throttled or locked out by address;
+// the email subject still is.
+async fn unless_allowlisted(
+    db_client: &Client,
+    cache: &BlacklistCache,
+    ip: Option<IpAddr>,
+) -> mongodb::error::Result<Option<IpAddr>> {
+    match ip {
+        Some(ip)
+            if lookup_allowlisted_ip(db_client, cache, &ip)
+                .await?
+                .is_some() =>
+        {
+            Ok(None)
+        }
+        ip => Ok(ip),
+    }
+}
+
+// Refuse blacklisted IPs, throttle attempts with the "signin" rate limit
+// policy, and refuse subjects that are locked out
+pub async fn guard_signin(
+    db_client: &Client,
+    cache: &BlacklistCache,
+    ip: Option<IpAddr>,
+    subjects: &[CounterKey],
+) -> Result<(), HttpResponse> {
+    let internal_error = |e: mongodb::error::Error| {
+        println!("Database error while checking signin attempt: {}", e);
+        HttpResponse::InternalServerError().finish()
+    };
+
+    let ip = unless_allowlisted(db_client, cache, ip)
+        .await
+        .map_err(internal_error)?;
+    let subjects: Vec<&CounterKey> = subjects
+        .iter()
+        .filter(|subject| subject.ip.is_none() || ip.is_some())
+        .collect();
+
+    if let Some(ip) = ip {
+        if lookup_ip(db_client, cache, &ip)
+            .await
+            .map_err(internal_error)?
+            .is_some()
+        {
+            return Err(HttpResponse::Forbidden()
+                .json(json!({"error": "Forbidden", "message": "IP address is blacklisted"})));
+        }
+    }
+
+    let policy = find_policy(db_client, SIGNIN_POLICY)
+        .await
+        .map_err(internal_error)?
+        .unwrap_or_else(RateLimitPolicy::builtin_signin);
+    let rate_limits: Collection<RateLimitEntry> =
+        db_client.database("rustkeeper").collection("rate_limits");
+    for subject in &subjects {
+        let decision = store::hit(
+            &rate_limits,
+            &subject.key,
+            &subject.dimension,
+            &policy,
+            &SystemClock,
+        )
+        .await
+        .map_err(internal_error)?;
+        if !decision.allowed {
+            let mut response = HttpResponse::TooManyRequests();
+            insert_rate_limit_headers(&mut response, &decision);
+            return Err(response.json(json!({
+                "error": "Too many signin attempts",
+                "retry_after": decision.retry_after_secs(),
+            })));
+        }
+    }
+
+    if let Some(seconds) = locked_for(db_client, &subjects)
+        .await
+        .map_err(internal_error)?
+    {
+        return Err(HttpResponse::TooManyRequests()
+            .insert_header(("Retry-After", seconds.to_string()))
+            .json(json!({
+                "error": "Too many failed signins; temporarily locked",
+                "retry_after": seconds,
+            })));
+    }
+
+    Ok(())
+}
+
+// Count a failed signin against every subject, locking out those that reach
+// the limit and blacklisting IPs that keep getting locked out. Allowlisted
+// IPs are neither counted nor blacklisted.
+pub async fn record_failed_signin(
+    db_client: &Client,
+    cache: &BlacklistCache,
+    ip: Option<IpAddr>,
+    subjects: &[CounterKey],
+) {
+    let ip = match unless_allowlisted(db_client, cache, ip).await {
+        Ok(ip) => ip,
+        Err(e) => {
+            println!("Failed to check the allowlist: {}", e);
+            None
+        }
+    };
+    let settings = LockoutSettings::from_env();
+    for subject in subjects
+        .iter()
+        .filter(|subject| subject.ip.is_none() || ip.is_some())
+    {
+        match record_failure(db_client, subject, &settings).await {
+            Ok(Some(lockout)) => {
+                println!(
+                    "Signin locked for {} ({} lockouts)",
+                    subject.key, lockout.lockouts
+                );
+                if let (Some(ip), "ip") = (ip, subject.dimension.as_str()) {
+                    if let Err(e) = escalate_ip(db_client, &ip, lockout.lockouts, &settings).await {
+                        println!("Failed to blacklist {}: {}", ip, e);
+                    }
+                }
+            }
+            Ok(None) => {}
+            Err(e) => println!("Failed to record signin failure: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{Snapshot, SyncMode};
+    use crate::models::{AllowlistedIp, EntryMetadata};
+    use crate::net::IpRange;
+    use mongodb::bson::oid::ObjectId;
+
+    #[actix_web::test]
+    async fn allowlisted_ips_are_not_signin_subjects() {
+        let mut monitoring = AllowlistedIp::new(
+            IpRange::parse("198.51.100.0/24").unwrap(),
+            None,
+            EntryMetadata::new(None, vec![], None, None),
+        );
+        monitoring._id = Some(ObjectId::new());
+        let cache = BlacklistCache::new();
+        cache.replace(
+            Snapshot {
+                allowlist: vec![monitoring],
+                ..Snapshot::default()
+            },
+            SyncMode::Polling,
+        );
+        // Never contacted: a ready cache answers every allowlist lookup
+        let db_client = Client::with_uri_str("mongodb://127.0.0.1:1").await.unwrap();
+
+        let probe = Some("198.51.100.9".parse().unwrap());
+        let other = Some("203.0.113.7".parse().unwrap());
+        assert_eq!(
+            unless_allowlisted(&db_client, &cache, probe).await.unwrap(),
+            None
+        );
+        assert_eq!(
+            unless_allowlisted(&db_client, &cache, other).await.unwrap(),
+            other
+        );
+    }
+
+    #[actix_web::test]
+    #[ignore = "requires MongoDB (set MONGODB_URI)"]
+    async fn account_status_is_only_revealed_with_the_right_password() {
+        use actix_web::{test, App};
+
+        let uri = std::env::var("MONGODB_URI").expect("MONGODB_URI must be set");
+        let db_client = Client::with_uri_str(&uri).await.unwrap();
+        let policy = PasswordPolicy::from_env().unwrap();
+        let (hash, _) = policy.hash("correct horse battery").unwrap();
+        let email = format!("pending-{}@example.com", ObjectId::new().to_hex());
+        let now = chrono::Utc::now().to_rfc3339();
+        let users = db_client
+            .database("rustkeeper")
+            .collection::<mongodb::bson::Document>("brigatory_users");
+        users
+            .insert_one(
+                doc! {
+                    "full_name": "Pending Test",
+                    "email": &email,
+                    "password": hash,
+                    "status": "pending",
+                    "created_at": &now,
+                    "updated_at": &now,
+                },
+                None,
+            )
+            .await
+            .unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(db_client.clone()))
+                .app_data(web::Data::new(KeyStore::with_secret("test", b"secret")))
+                .app_data(web::Data::new(BlacklistCache::new()))
+                .app_data(web::Data::new(policy))
+                .route("/signin", web::post().to(signin)),
+        )
+        .await;
+        let attempt = |password: &str| {
+            test::TestRequest::post()
+                .uri("/signin")
+                .set_json(json!({ "email": &email, "password": password }))
+                .to_request()
+        };
+
+        let res = test::call_service(&app, attempt("wrong password")).await;
+        assert_eq!(res.status(), 401);
+        assert_eq!(test::read_body(res).await, "Invalid email or password");
+
+        let res = test::call_service(&app, attempt("correct horse battery")).await;
+        assert_eq!(res.status(), 401);
+        assert_eq!(test::read_body(res).await, "Account is pending approval");
+
+        users
+            .delete_one(doc! { "email": &email }, None)
+            .await
+            .unwrap();
+    }
+}
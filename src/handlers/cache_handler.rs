@@ -0,0 +1,8 @@
+// src/handlers/cache_handler.rs
+use crate::cache::BlacklistCache;
+use actix_web::{web, HttpResponse, Responder};
+
+// Report the blacklist cache generation, sync mode and last sync time
+pub async fn cache_status(cache: web::Data<BlacklistCache>) -> impl Responder {
+    HttpResponse::Ok().json(cache.status())
+}
@@ -1,88 +1,316 @@
 // src/handlers/check_rate_limit_handler.rs
-use actix_web::{web, HttpResponse, Responder};
-use bson::{doc, from_document, to_document, DateTime, Document};
+use actix_web::{web, HttpResponse, HttpResponseBuilder, Responder};
 use mongodb::{Client, Collection};
-use serde::Deserialize;
-use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
 
-use crate::models::RateLimitEntry;
+use crate::cache::BlacklistCache;
+use crate::handlers::allowlist_handler::lookup_allowlisted_ip;
+use crate::handlers::rate_limit_policy_handler::find_policy;
+use crate::handlers::validation::{validation_error, FieldError};
+use crate::models::{
+    rate_limit_policy::DEFAULT_POLICY, AllowlistedIp, RateLimitEntry, RateLimitPolicy,
+};
+use crate::ratelimit::keys::{CounterKey, Dimension};
+use crate::ratelimit::{store, Decision, SystemClock};
+use serde_json::json;
+use std::net::IpAddr;
 
 #[derive(Deserialize)]
 pub struct RateLimitCheck {
-    pub ip_address: String,
+    // Shorthand for a single "ip" dimension
+    pub ip_address: Option<String>,
+    // Shorthand for a single "key" dimension, e.g. an API key or tenant id
+    pub key: Option<String>,
+    // Every dimension is counted; the request is rejected if any of them trips
+    #[serde(default)]
+    pub dimensions: Vec<Dimension>,
+    pub policy: Option<String>, // Name of a stored policy; "default" when omitted
 }
 
-pub async fn check_rate_limit(
-    db_client: web::Data<Client>,
-    req: web::Json<RateLimitCheck>,
-) -> impl Responder {
-    let collection: Collection<Document> =
-        db_client.database("rustkeeper").collection("rate_limits");
+impl RateLimitCheck {
+    // All dimensions of the request as counter keys, or the fields that were invalid
+    fn counter_keys(&self) -> Result<Vec<CounterKey>, Vec<FieldError>> {
+        let mut dimensions: Vec<(String, Dimension)> = Vec::new();
+        if let Some(ip) = &self.ip_address {
+            dimensions.push(("ip_address".to_string(), Dimension::single("ip", ip)));
+        }
+        if let Some(key) = &self.key {
+            dimensions.push(("key".to_string(), Dimension::single("key", key)));
+        }
+        for (i, dimension) in self.dimensions.iter().enumerate() {
+            dimensions.push((format!("dimensions[{}]", i), dimension.clone()));
+        }
 
-    let filter = doc! { "ip": &req.ip_address };
-    let now = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs() as i64;
-
-    match collection.find_one(filter.clone(), None).await {
-        Ok(Some(raw_doc)) => {
-            println!("Raw document from DB: {:?}", raw_doc);
-
-            match from_document::<RateLimitEntry>(raw_doc) {
-                Ok(mut rate_limit_entry) => {
-                    let last_request_time =
-                        rate_limit_entry.last_request_time.timestamp_millis() / 1000;
-
-                    let elapsed_time = now - last_request_time;
-
-                    if elapsed_time <= 1 {
-                        if rate_limit_entry.request_count >= 10 {
-                            println!("Rate limit exceeded for IP: {}", req.ip_address);
-                            return HttpResponse::TooManyRequests().json("Rate limit exceeded");
-                        } else {
-                            rate_limit_entry.request_count += 1;
-                        }
-                    } else {
-                        rate_limit_entry.request_count = 1;
-                        rate_limit_entry.last_request_time = DateTime::from_millis(now * 1000);
-                    }
-
-                    let update_doc = doc! {
-                        "$set": {
-                            "request_count": rate_limit_entry.request_count,
-                            "last_request_time": rate_limit_entry.last_request_time,
-                        }
-                    };
-
-                    collection
-                        .update_one(filter, update_doc, None)
-                        .await
-                        .unwrap();
-                }
+        if dimensions.is_empty() {
+            return Err(vec![FieldError::new(
+                "dimensions",
+                "",
+                "provide ip_address, key or at least one dimension",
+            )]);
+        }
+
+        let mut keys = Vec::new();
+        let mut errors = Vec::new();
+        for (field, dimension) in dimensions {
+            match dimension.to_counter_key() {
+                Ok(key) if !keys.contains(&key) => keys.push(key),
+                Ok(_) => {}
                 Err(e) => {
-                    println!("Deserialization error: {}", e);
-                    return HttpResponse::InternalServerError().json("Deserialization error");
+                    let value = dimension
+                        .parts
+                        .iter()
+                        .map(|(part, value)| format!("{}={}", part, value))
+                        .collect::<Vec<_>>()
+                        .join("&");
+                    errors.push(FieldError::new(&field, &value, e));
                 }
             }
         }
-        Ok(None) => {
-            let new_entry = RateLimitEntry {
-                id: None,
-                ip: req.ip_address.clone(),
-                request_count: 1,
-                last_request_time: DateTime::from_millis(now * 1000),
-            };
 
-            let new_entry_doc = to_document(&new_entry).unwrap();
+        if errors.is_empty() {
+            Ok(keys)
+        } else {
+            Err(errors)
+        }
+    }
+}
 
-            collection.insert_one(new_entry_doc, None).await.unwrap();
+// Split off the counters of allowlisted addresses, which are not counted.
+// Returns the counters left and the allowlist entry behind each one skipped.
+async fn skip_allowlisted(
+    db_client: &Client,
+    cache: &BlacklistCache,
+    keys: Vec<CounterKey>,
+) -> mongodb::error::Result<(Vec<CounterKey>, Vec<(IpAddr, AllowlistedIp)>)> {
+    let mut counted = Vec::with_capacity(keys.len());
+    let mut skipped = Vec::new();
+    for key in keys {
+        if let Some(ip) = key.ip {
+            if let Some(entry) = lookup_allowlisted_ip(db_client, cache, &ip).await? {
+                skipped.push((ip, entry));
+                continue;
+            }
         }
+        counted.push(key);
+    }
+    Ok((counted, skipped))
+}
+
+pub async fn check_rate_limit(
+    db_client: web::Data<Client>,
+    cache: web::Data<BlacklistCache>,
+    req: web::Json<RateLimitCheck>,
+) -> impl Responder {
+    let collection: Collection<RateLimitEntry> =
+        db_client.database("rustkeeper").collection("rate_limits");
+
+    // Counters are keyed on canonical values so that equivalent spellings share a limit
+    let keys = match req.counter_keys() {
+        Ok(keys) => keys,
+        Err(errors) => return validation_error(errors),
+    };
+
+    let policy_name = req.policy.as_deref().unwrap_or(DEFAULT_POLICY);
+
+    // Dimensions of allowlisted addresses are not counted; the others, such
+    // as an API key sent along with the address, still are
+    let (keys, skipped) = match skip_allowlisted(&db_client, &cache, keys).await {
+        Ok(split) => split,
+        Err(e) => return HttpResponse::InternalServerError().json(e.to_string()),
+    };
+    if let (true, Some((ip, entry))) = (keys.is_empty(), skipped.first()) {
+        return HttpResponse::Ok().json(json!({
+            "allowed": true,
+            "allowlisted": true,
+            "policy": policy_name,
+            "ip_address": ip.to_string(),
+            "matched": entry.ip_address,
+        }));
+    }
+
+    let policy = match find_policy(&db_client, policy_name).await {
+        Ok(Some(policy)) => policy,
+        Ok(None) => return HttpResponse::NotFound().body("No policy found with the provided name"),
+        Err(e) => return HttpResponse::InternalServerError().json(e.to_string()),
+    };
+
+    // Count every dimension; the most constrained one is reported
+    let results = match count_request(&collection, &keys, &policy).await {
+        Ok(results) => results,
         Err(e) => {
-            println!("Database error during find_one: {}", e);
+            println!("Database error while updating rate limit: {}", e);
             return HttpResponse::InternalServerError().finish();
         }
+    };
+
+    let binding = match most_constrained(&results) {
+        Some(result) => result,
+        None => return HttpResponse::InternalServerError().finish(),
+    };
+    let mut body = RateLimitStatus::new(&policy.name, binding, &results);
+    body.allowlisted = !skipped.is_empty();
+
+    let mut response = if binding.decision.allowed {
+        HttpResponse::Ok()
+    } else {
+        println!("Rate limit exceeded for {}", binding.key);
+        HttpResponse::TooManyRequests()
+    };
+    insert_rate_limit_headers(&mut response, &binding.decision);
+    response.json(body)
+}
+
+pub(crate) struct DimensionResult {
+    pub(crate) dimension: String,
+    pub(crate) key: String,
+    pub(crate) decision: Decision,
+}
+
+// Count one request against each counter key under `policy`
+pub(crate) async fn count_request(
+    collection: &Collection<RateLimitEntry>,
+    keys: &[CounterKey],
+    policy: &RateLimitPolicy,
+) -> mongodb::error::Result<Vec<DimensionResult>> {
+    let mut results = Vec::with_capacity(keys.len());
+    for counter in keys {
+        let decision = store::hit(
+            collection,
+            &counter.key,
+            &counter.dimension,
+            policy,
+            &SystemClock,
+        )
+        .await?;
+        results.push(DimensionResult {
+            dimension: counter.dimension.clone(),
+            key: counter.key.clone(),
+            decision,
+        });
+    }
+    Ok(results)
+}
+
+// The first rejected dimension, otherwise the one with the fewest requests left
+pub(crate) fn most_constrained(results: &[DimensionResult]) -> Option<&DimensionResult> {
+    results
+        .iter()
+        .find(|result| !result.decision.allowed)
+        .or_else(|| {
+            results
+                .iter()
+                .min_by_key(|result| (result.decision.remaining, -result.decision.reset_after_ms))
+        })
+}
+
+// Body returned for both allowed and rejected checks; mirrors the headers
+#[derive(Debug, Serialize)]
+pub struct RateLimitStatus {
+    pub allowed: bool,
+    pub allowlisted: bool, // True when the dimensions of an allowlisted address were skipped
+    pub policy: String,
+    pub dimension: String, // The dimension the figures below belong to
+    pub limit: i64,
+    pub remaining: i64,
+    pub reset: i64, // Seconds until the limit resets
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_after: Option<i64>, // Seconds to wait before retrying, when rejected
+    pub dimensions: Vec<DimensionStatus>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DimensionStatus {
+    pub dimension: String,
+    pub key: String,
+    pub allowed: bool,
+    pub remaining: i64,
+    pub reset: i64,
+}
+
+impl RateLimitStatus {
+    pub(crate) fn new(
+        policy: &str,
+        binding: &DimensionResult,
+        results: &[DimensionResult],
+    ) -> Self {
+        RateLimitStatus {
+            allowed: results.iter().all(|result| result.decision.allowed),
+            allowlisted: false,
+            policy: policy.to_string(),
+            dimension: binding.dimension.clone(),
+            limit: binding.decision.limit,
+            remaining: binding.decision.remaining,
+            reset: binding.decision.reset_after_secs(),
+            retry_after: binding.decision.retry_after_secs(),
+            dimensions: results
+                .iter()
+                .map(|result| DimensionStatus {
+                    dimension: result.dimension.clone(),
+                    key: result.key.clone(),
+                    allowed: result.decision.allowed,
+                    remaining: result.decision.remaining,
+                    reset: result.decision.reset_after_secs(),
+                })
+                .collect(),
+        }
     }
+}
 
-    HttpResponse::Ok().json("Request within rate limit")
+// IETF `RateLimit-*` headers, plus `Retry-After` on rejected requests
+pub fn insert_rate_limit_headers(response: &mut HttpResponseBuilder, decision: &Decision) {
+    response
+        .insert_header(("RateLimit-Limit", decision.limit.to_string()))
+        .insert_header(("RateLimit-Remaining", decision.remaining.to_string()))
+        .insert_header(("RateLimit-Reset", decision.reset_after_secs().to_string()));
+    if let Some(retry_after) = decision.retry_after_secs() {
+        response.insert_header(("Retry-After", retry_after.to_string()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{Snapshot, SyncMode};
+    use crate::models::EntryMetadata;
+    use crate::net::IpRange;
+    use mongodb::bson::oid::ObjectId;
+
+    #[actix_web::test]
+    async fn only_allowlisted_ip_dimensions_skip_counting() {
+        let check: RateLimitCheck = serde_json::from_value(json!({
+            "ip_address": "::ffff:203.0.113.7",
+            "key": "tenant-1",
+            "dimensions": [
+                { "parts": { "ip": "198.51.100.1", "route": "/login" } },
+            ],
+        }))
+        .unwrap();
+
+        let mut office = AllowlistedIp::new(
+            IpRange::parse("203.0.113.0/24").unwrap(),
+            None,
+            EntryMetadata::new(None, vec![], None, None),
+        );
+        office._id = Some(ObjectId::new());
+        let cache = BlacklistCache::new();
+        cache.replace(
+            Snapshot {
+                allowlist: vec![office],
+                ..Snapshot::default()
+            },
+            SyncMode::Polling,
+        );
+        // Never contacted: a ready cache answers every allowlist lookup
+        let db_client = Client::with_uri_str("mongodb://127.0.0.1:1").await.unwrap();
+
+        let keys = check.counter_keys().unwrap();
+        let (counted, skipped) = skip_allowlisted(&db_client, &cache, keys).await.unwrap();
+        let counted: Vec<&str> = counted.iter().map(|key| key.key.as_str()).collect();
+        assert_eq!(
+            counted,
+            vec!["key=tenant-1", "ip=198.51.100.1&route=/login"]
+        );
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].0.to_string(), "203.0.113.7");
+    }
 }
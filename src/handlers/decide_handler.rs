@@ -0,0 +1,449 @@
+// src/handlers/decide_handler.rs
+// One call that answers "should this request go through?" by checking the IP
+// blacklist, the URL blacklist and the rate limiter together.
+use crate::cache::BlacklistCache;
+use crate::handlers::allowlist_handler::lookup_allowlisted_ip;
+use crate::handlers::blacklist_handler::lookup_ip;
+use crate::handlers::check_rate_limit_handler::{
+    count_request, insert_rate_limit_headers, most_constrained, RateLimitStatus,
+};
+use crate::handlers::malicious_handler::lookup_url;
+use crate::handlers::rate_limit_policy_handler::find_policy;
+use crate::handlers::rule_handler::enabled_rules;
+use crate::handlers::validation::{validation_error, FieldError};
+use crate::models::{rate_limit_policy::DEFAULT_POLICY, EntryMetadata, PolicyRule, RateLimitEntry};
+use crate::net::ip::parse_ip;
+use crate::ratelimit::keys::Dimension;
+use crate::ratelimit::Decision;
+use crate::rules::{self, RequestFacts, RuleAction};
+use actix_web::{web, HttpResponse, Responder};
+use log::info;
+use mongodb::{Client, Collection};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+// Blacklist entries carrying this tag ask the caller to challenge the client
+// (e.g. with a CAPTCHA) instead of refusing it
+pub const CHALLENGE_TAG: &str = "challenge";
+
+// The request being decided, as seen by the proxy in front of it
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DecideRequest {
+    pub ip_address: String,
+    pub method: Option<String>,
+    pub path: Option<String>,
+    pub host: Option<String>,
+    // "http" or "https"; URL entries are checked with both when omitted
+    pub scheme: Option<String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub headers: HashMap<String, String>,
+    // ISO 3166-1 alpha-2 code, as resolved by the proxy or its CDN
+    pub country: Option<String>,
+    // Authenticated user or tenant; rate limited separately from the IP
+    pub user: Option<String>,
+    pub policy: Option<String>, // Rate limit policy; "default" when omitted
+}
+
+// Listed from least to most severe; the most severe matching rule wins
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Verdict {
+    Allow,
+    Challenge,
+    Throttle,
+    Deny,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MatchedRule {
+    pub source: String, // "allowlist", "rule", "ip_blacklist", "url_blacklist" or "rate_limit"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub pattern: String, // The entry or counter key that matched
+    pub action: Verdict,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DecideResponse {
+    pub verdict: Verdict,
+    pub ip_address: String,
+    pub allowlisted: bool,
+    pub matched_rules: Vec<MatchedRule>,
+    // Omitted when the request was denied before it was counted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_limit: Option<RateLimitStatus>,
+    // The figures behind `rate_limit`, for the response headers
+    #[serde(skip)]
+    pub binding: Option<Decision>,
+}
+
+pub enum DecideError {
+    Invalid(Vec<FieldError>),
+    UnknownPolicy,
+    Database(mongodb::error::Error),
+}
+
+impl From<mongodb::error::Error> for DecideError {
+    fn from(e: mongodb::error::Error) -> Self {
+        DecideError::Database(e)
+    }
+}
+
+// Deny, or challenge when the entry is tagged for it
+fn blacklist_action(metadata: &EntryMetadata) -> Verdict {
+    if metadata.tags.iter().any(|tag| tag == CHALLENGE_TAG) {
+        Verdict::Challenge
+    } else {
+        Verdict::Deny
+    }
+}
+
+// The URLs checked against the URL blacklist. Entries are stored as full
+// URLs ("http://evil.com/b"), without a scheme or as bare paths ("/admin"),
+// so the request is tried in each of those forms; with both schemes when the
+// caller did not say which one was used.
+fn request_urls(scheme: Option<&str>, host: Option<&str>, path: Option<&str>) -> Vec<String> {
+    let host = host.map(str::trim).filter(|h| !h.is_empty());
+    let path = path.map(str::trim).filter(|p| !p.is_empty()).map(|path| {
+        if path.starts_with('/') {
+            path.to_string()
+        } else {
+            format!("/{}", path)
+        }
+    });
+    let schemes = match scheme.map(str::trim).filter(|s| !s.is_empty()) {
+        Some(scheme) => vec![scheme],
+        None => vec!["http", "https"],
+    };
+
+    let mut urls = Vec::new();
+    if let Some(host) = host {
+        let path = path.as_deref().unwrap_or("/");
+        urls.extend(
+            schemes
+                .iter()
+                .map(|scheme| format!("{}://{}{}", scheme, host, path)),
+        );
+        urls.push(format!("{}{}", host, path));
+    }
+    urls.extend(path);
+    urls
+}
+
+fn request_facts(ip: IpAddr, request: &DecideRequest) -> RequestFacts {
+    let mut facts = RequestFacts::new(ip);
+    facts.path = request.path.clone();
+    facts.host = request.host.clone();
+    facts.method = request.method.clone();
+    facts.headers = request
+        .headers
+        .iter()
+        .map(|(name, value)| (name.to_lowercase(), value.clone()))
+        .collect();
+    facts.country = request.country.clone();
+    facts
+}
+
+// How a matching stored rule is reported
+fn rule_match(rule: &PolicyRule) -> MatchedRule {
+    let (action, note) = match &rule.action {
+        RuleAction::Allow => (Verdict::Allow, None),
+        RuleAction::Deny => (Verdict::Deny, None),
+        RuleAction::Challenge => (Verdict::Challenge, None),
+        RuleAction::RateLimit { policy } => (
+            Verdict::Allow,
+            Some(format!("Counted against rate limit policy '{}'", policy)),
+        ),
+        RuleAction::LogOnly => (Verdict::Allow, Some("Logged only".to_string())),
+    };
+    MatchedRule {
+        source: "rule".to_string(),
+        id: rule._id.map(|id| id.to_hex()),
+        pattern: rule.name.clone(),
+        action,
+        reason: rule.description.clone().or(note),
+    }
+}
+
+fn verdict_of(rules: &[MatchedRule]) -> Verdict {
+    rules
+        .iter()
+        .map(|rule| rule.action)
+        .max()
+        .unwrap_or(Verdict::Allow)
+}
+
+// Evaluate a request against the allowlist, the stored rules, the blacklists
+// and the rate limiter. A denied request is not counted against the rate limit.
+pub async fn evaluate(
+    db_client: &Client,
+    cache: &BlacklistCache,
+    request: &DecideRequest,
+) -> Result<DecideResponse, DecideError> {
+    let ip = parse_ip(&request.ip_address).map_err(|e| {
+        DecideError::Invalid(vec![FieldError::new("ip_address", &request.ip_address, e)])
+    })?;
+    let mut dimensions = vec![Dimension::single("ip", &ip.to_string())];
+    if let Some(user) = request.user.as_deref().filter(|u| !u.trim().is_empty()) {
+        dimensions.push(Dimension::single("user", user));
+    }
+    let keys = dimensions
+        .iter()
+        .map(Dimension::to_counter_key)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| DecideError::Invalid(vec![FieldError::new("user", "", e)]))?;
+
+    // Allowlisted addresses skip every other check, stored rules included
+    if let Some(entry) = lookup_allowlisted_ip(db_client, cache, &ip).await? {
+        return Ok(DecideResponse {
+            verdict: Verdict::Allow,
+            ip_address: ip.to_string(),
+            allowlisted: true,
+            matched_rules: vec![MatchedRule {
+                source: "allowlist".to_string(),
+                id: entry._id.map(|id| id.to_hex()),
+                pattern: entry.ip_address,
+                action: Verdict::Allow,
+                reason: entry.metadata.reason,
+            }],
+            rate_limit: None,
+            binding: None,
+        });
+    }
+
+    // Stored rules come next; an allow rule skips the blacklists and rate limits
+    let stored = enabled_rules(db_client, cache).await?;
+    let evaluation = rules::evaluate(&stored, &request_facts(ip, request));
+    for rule in &evaluation.matched {
+        if rule.action == RuleAction::LogOnly {
+            info!(
+                "Policy rule '{}' matched {} {} {}{}",
+                rule.name,
+                ip,
+                request.method.as_deref().unwrap_or("-"),
+                request.host.as_deref().unwrap_or(""),
+                request.path.as_deref().unwrap_or("")
+            );
+        }
+    }
+    let mut matched_rules: Vec<MatchedRule> = evaluation
+        .matched
+        .iter()
+        .map(|rule| rule_match(rule))
+        .collect();
+    let mut policy_name = request.policy.as_deref().unwrap_or(DEFAULT_POLICY);
+    match evaluation.decision.map(|rule| &rule.action) {
+        Some(RuleAction::Allow) => {
+            return Ok(DecideResponse {
+                verdict: Verdict::Allow,
+                ip_address: ip.to_string(),
+                allowlisted: false,
+                matched_rules,
+                rate_limit: None,
+                binding: None,
+            })
+        }
+        Some(RuleAction::RateLimit { policy }) => policy_name = policy,
+        _ => {}
+    }
+
+    if let Some(entry) = lookup_ip(db_client, cache, &ip).await? {
+        matched_rules.push(MatchedRule {
+            source: "ip_blacklist".to_string(),
+            id: entry._id.map(|id| id.to_hex()),
+            action: blacklist_action(&entry.metadata),
+            pattern: entry.ip_address,
+            reason: entry.metadata.reason,
+        });
+    }
+    for url in request_urls(
+        request.scheme.as_deref(),
+        request.host.as_deref(),
+        request.path.as_deref(),
+    ) {
+        if let Some(entry) = lookup_url(db_client, cache, &url).await? {
+            matched_rules.push(MatchedRule {
+                source: "url_blacklist".to_string(),
+                id: entry._id.map(|id| id.to_hex()),
+                action: blacklist_action(&entry.metadata),
+                pattern: entry.url,
+                reason: entry.metadata.reason,
+            });
+            break;
+        }
+    }
+
+    let mut rate_limit = None;
+    let mut binding = None;
+    if verdict_of(&matched_rules) != Verdict::Deny {
+        let policy = find_policy(db_client, policy_name)
+            .await?
+            .ok_or(DecideError::UnknownPolicy)?;
+        let collection: Collection<RateLimitEntry> =
+            db_client.database("rustkeeper").collection("rate_limits");
+        let results = count_request(&collection, &keys, &policy).await?;
+        if let Some(result) = most_constrained(&results) {
+            if !result.decision.allowed {
+                matched_rules.push(MatchedRule {
+                    source: "rate_limit".to_string(),
+                    id: policy._id.map(|id| id.to_hex()),
+                    pattern: result.key.clone(),
+                    action: Verdict::Throttle,
+                    reason: Some(format!("Rate limit policy '{}' exceeded", policy.name)),
+                });
+            }
+            rate_limit = Some(RateLimitStatus::new(&policy.name, result, &results));
+            binding = Some(result.decision);
+        }
+    }
+
+    Ok(DecideResponse {
+        verdict: verdict_of(&matched_rules),
+        ip_address: ip.to_string(),
+        allowlisted: false,
+        matched_rules,
+        rate_limit,
+        binding,
+    })
+}
+
+// Decide on a request in one call. The verdict is the answer, so the status is
+// 200 whatever it is; rate limit headers are set whenever the request was counted.
+pub async fn decide(
+    db_client: web::Data<Client>,
+    cache: web::Data<BlacklistCache>,
+    data: web::Json<DecideRequest>,
+) -> impl Responder {
+    match evaluate(&db_client, &cache, &data).await {
+        Ok(decision) => {
+            let mut response = HttpResponse::Ok();
+            if let Some(binding) = &decision.binding {
+                insert_rate_limit_headers(&mut response, binding);
+            }
+            response.json(decision)
+        }
+        Err(DecideError::Invalid(errors)) => validation_error(errors),
+        Err(DecideError::UnknownPolicy) => {
+            HttpResponse::NotFound().body("No policy found with the provided name")
+        }
+        Err(DecideError::Database(e)) => {
+            println!("Error deciding on request: {}", e);
+            HttpResponse::InternalServerError().json(e.to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{Snapshot, SyncMode};
+    use crate::models::MaliciousUrl;
+    use crate::net::{UrlMatchType, UrlPattern};
+    use mongodb::bson::oid::ObjectId;
+
+    fn rule(action: Verdict) -> MatchedRule {
+        MatchedRule {
+            source: "ip_blacklist".to_string(),
+            id: None,
+            pattern: "203.0.113.0/24".to_string(),
+            action,
+            reason: None,
+        }
+    }
+
+    #[test]
+    fn most_severe_rule_wins() {
+        assert_eq!(verdict_of(&[]), Verdict::Allow);
+        assert_eq!(
+            verdict_of(&[rule(Verdict::Challenge), rule(Verdict::Throttle)]),
+            Verdict::Throttle
+        );
+        assert_eq!(
+            verdict_of(&[rule(Verdict::Deny), rule(Verdict::Challenge)]),
+            Verdict::Deny
+        );
+    }
+
+    #[test]
+    fn challenge_tag_softens_blacklist_entries() {
+        let plain = EntryMetadata::new(None, vec![], None, None);
+        let tagged = EntryMetadata::new(None, vec!["Challenge".to_string()], None, None);
+        assert_eq!(blacklist_action(&plain), Verdict::Deny);
+        assert_eq!(blacklist_action(&tagged), Verdict::Challenge);
+    }
+
+    #[test]
+    fn request_urls_cover_every_stored_form() {
+        assert_eq!(
+            request_urls(None, Some("evil.com"), Some("/a")),
+            vec![
+                "http://evil.com/a",
+                "https://evil.com/a",
+                "evil.com/a",
+                "/a"
+            ]
+        );
+        assert_eq!(
+            request_urls(Some("https"), Some("evil.com"), Some("a")),
+            vec!["https://evil.com/a", "evil.com/a", "/a"]
+        );
+        assert_eq!(request_urls(None, None, Some("/a")), vec!["/a"]);
+        assert!(request_urls(None, Some(" "), None).is_empty());
+    }
+
+    fn blocked_url(match_type: UrlMatchType, url: &str) -> MaliciousUrl {
+        let pattern = UrlPattern::new(match_type, url, false).unwrap();
+        let metadata = EntryMetadata::new(None, vec![], None, None);
+        let mut entry = MaliciousUrl::new(&pattern, url.to_string(), match_type, false, metadata);
+        entry._id = Some(ObjectId::new());
+        entry
+    }
+
+    // A ready cache answers every lookup; the client fails fast if one
+    // reaches MongoDB, which a denied request never should
+    async fn cached(urls: Vec<MaliciousUrl>) -> (Client, BlacklistCache) {
+        let cache = BlacklistCache::new();
+        cache.replace(
+            Snapshot {
+                urls,
+                ..Snapshot::default()
+            },
+            SyncMode::Polling,
+        );
+        let db_client = Client::with_uri_str("mongodb://127.0.0.1:1/?serverSelectionTimeoutMS=100")
+            .await
+            .unwrap();
+        (db_client, cache)
+    }
+
+    #[actix_web::test]
+    async fn stored_prefix_and_path_entries_deny_through_decide() {
+        let (db_client, cache) = cached(vec![
+            blocked_url(UrlMatchType::Prefix, "HTTP://Evil.com:80/download"),
+            blocked_url(UrlMatchType::Prefix, "/admin"),
+        ])
+        .await;
+
+        for (host, path) in [
+            (Some("evil.com"), "/download/payload.exe"),
+            (Some("good.example"), "/admin/users"),
+            (None, "/admin"),
+        ] {
+            let request = DecideRequest {
+                ip_address: "198.51.100.1".to_string(),
+                host: host.map(str::to_string),
+                path: Some(path.to_string()),
+                ..DecideRequest::default()
+            };
+            let decision = match evaluate(&db_client, &cache, &request).await {
+                Ok(decision) => decision,
+                Err(_) => panic!("{:?} {} was not decided from the cache", host, path),
+            };
+            assert_eq!(decision.verdict, Verdict::Deny, "{:?} {}", host, path);
+            assert_eq!(decision.matched_rules[0].source, "url_blacklist");
+        }
+    }
+}
@@ -0,0 +1,10 @@
+// src/handlers/jwks_handler.rs
+use crate::auth::KeyStore;
+use actix_web::{web, HttpResponse, Responder};
+
+// Publish the public token verification keys so other services can check our tokens
+pub async fn jwks(keys: web::Data<KeyStore>) -> impl Responder {
+    HttpResponse::Ok()
+        .insert_header(("Cache-Control", "public, max-age=300"))
+        .json(keys.jwks())
+}
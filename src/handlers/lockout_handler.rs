@@ -0,0 +1,65 @@
+// src/handlers/lockout_handler.rs
+use crate::auth::lockout::lockouts;
+use crate::models::SigninLockout;
+use actix_web::{web, HttpResponse, Responder};
+use futures::stream::TryStreamExt;
+use mongodb::{
+    bson::{doc, oid::ObjectId},
+    options::FindOptions,
+    Client,
+};
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct LockoutQuery {
+    // Only subjects that are locked out right now
+    #[serde(default)]
+    pub locked: bool,
+}
+
+// List emails and IPs with failed signins, most recent failure first
+pub async fn get_all_signin_lockouts(
+    db_client: web::Data<Client>,
+    query: web::Query<LockoutQuery>,
+) -> impl Responder {
+    let filter = if query.locked {
+        doc! { "locked_until": { "$gt": bson::DateTime::now() } }
+    } else {
+        doc! {}
+    };
+    let find_options = FindOptions::builder()
+        .sort(doc! { "last_failure_at": -1 })
+        .build();
+
+    let found: Result<Vec<SigninLockout>, _> =
+        match lockouts(&db_client).find(filter, find_options).await {
+            Ok(cursor) => cursor.try_collect().await,
+            Err(e) => Err(e),
+        };
+    match found {
+        Ok(found) => HttpResponse::Ok().json(found),
+        Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
+    }
+}
+
+// Clear a lockout along with its failure history and backoff
+pub async fn delete_signin_lockout(
+    db_client: web::Data<Client>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let oid = match ObjectId::parse_str(path.into_inner()) {
+        Ok(oid) => oid,
+        Err(_) => return HttpResponse::BadRequest().body("Invalid ID format"),
+    };
+
+    match lockouts(&db_client)
+        .delete_one(doc! { "_id": oid }, None)
+        .await
+    {
+        Ok(result) if result.deleted_count == 1 => {
+            HttpResponse::Ok().json("Signin lockout successfully cleared")
+        }
+        Ok(_) => HttpResponse::NotFound().body("No entry found with the provided ID"),
+        Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
+    }
+}
@@ -1,6 +1,11 @@
 // src/handlers/malicious_handler.rs
 
-use crate::models::MaliciousUrl;
+use crate::auth::Claims;
+use crate::cache::BlacklistCache;
+use crate::handlers::validation::{validation_error, FieldError};
+use crate::models::entry_metadata::{apply_metadata_filter, normalize_tags};
+use crate::models::{EntryMetadata, MaliciousUrl};
+use crate::net::{normalize_url, UrlMatchType, UrlPattern};
 use actix_web::{web, HttpResponse, Responder};
 use futures::stream::StreamExt;
 use mongodb::{
@@ -10,24 +15,54 @@ use mongodb::{
 };
 
 use serde::Deserialize;
+use std::cmp::Reverse;
 
 // Define a helper struct for deserializing incoming data
 #[derive(Debug, Deserialize)]
 pub struct InputData {
     pub url: String,
+    #[serde(default)]
+    pub match_type: UrlMatchType, // "prefix" when omitted
+    #[serde(default)]
+    pub ignore_query: bool,
+    pub reason: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub source: Option<String>, // Defaults to "manual"
 }
 
 // Post request handler to add a new URL to the blacklist
 pub async fn add_blacklist_url(
     db_client: web::Data<Client>,
     data: web::Json<InputData>,
+    claims: Option<web::ReqData<Claims>>,
 ) -> impl Responder {
     let collection: Collection<MaliciousUrl> = db_client
         .database("rustkeeper")
         .collection("malicious_urls");
 
+    // Patterns are validated up front and matched in Rust, never handed to MongoDB
+    let pattern = match UrlPattern::new(data.match_type, &data.url, data.ignore_query) {
+        Ok(pattern) => pattern,
+        Err(e) => return validation_error(vec![FieldError::new("url", &data.url, e)]),
+    };
+
     // Create a new MaliciousUrl using the helper method that sets timestamps and default status
-    let new_url = MaliciousUrl::new(data.url.clone());
+    let data = data.into_inner();
+    let metadata = EntryMetadata::new(
+        data.reason,
+        data.tags,
+        data.source,
+        claims.map(|claims| claims.sub.clone()),
+    );
+    // The normalized pattern is stored for matching, the submitted URL for display
+    let new_url = MaliciousUrl::new(
+        &pattern,
+        data.url.trim().to_string(),
+        data.match_type,
+        data.ignore_query,
+        metadata,
+    );
 
     match collection.insert_one(new_url, None).await {
         Ok(_) => HttpResponse::Created()
@@ -36,13 +71,23 @@ pub async fn add_blacklist_url(
     }
 }
 
-// Get all blocked URLs
-pub async fn get_all_blacklist_url(db_client: web::Data<Client>) -> impl Responder {
+#[derive(Debug, Deserialize)]
+pub struct ListQuery {
+    pub tag: Option<String>,
+    pub source: Option<String>,
+}
+
+// Get all blocked URLs, optionally filtered by tag and source
+pub async fn get_all_blacklist_url(
+    db_client: web::Data<Client>,
+    query: web::Query<ListQuery>,
+) -> impl Responder {
     let collection: Collection<MaliciousUrl> = db_client
         .database("rustkeeper")
         .collection("malicious_urls");
 
-    let filter = bson::doc! { "status": "blocked" };
+    let mut filter = bson::doc! { "status": "blocked" };
+    apply_metadata_filter(&mut filter, query.tag.as_deref(), query.source.as_deref());
     let find_options = FindOptions::builder()
         .sort(bson::doc! { "created_at": -1 })
         .build();
@@ -117,7 +162,13 @@ pub async fn delete_blacklist_url_by_id(
 #[derive(Debug, Deserialize)]
 pub struct UpdateInputData {
     pub url: String,
+    #[serde(default)]
+    pub match_type: UrlMatchType,
+    #[serde(default)]
+    pub ignore_query: bool,
     pub status: String,
+    pub reason: Option<String>,
+    pub tags: Option<Vec<String>>,
 }
 
 // Update a malicious url by ID
@@ -136,13 +187,36 @@ pub async fn edit_blacklist_url_by_id(
         Err(_) => return HttpResponse::BadRequest().body("Invalid ID format"),
     };
 
-    let update = doc! {
-        "$set": {
-            "url": &data.url,
-            "status": &data.status,
-            "updated_at": bson::DateTime::now(),  // Automatically update the 'updated_at' field
+    let pattern = match UrlPattern::new(data.match_type, &data.url, data.ignore_query) {
+        Ok(pattern) => pattern,
+        Err(e) => return validation_error(vec![FieldError::new("url", &data.url, e)]),
+    };
+
+    // `updated_at` in the same format as `MaliciousUrl::new`
+    let (match_type, updated_at) = match (
+        bson::to_bson(&data.match_type),
+        bson::to_bson(&chrono::Utc::now()),
+    ) {
+        (Ok(match_type), Ok(updated_at)) => (match_type, updated_at),
+        (Err(e), _) | (_, Err(e)) => {
+            return HttpResponse::InternalServerError().json(e.to_string())
         }
     };
+    let mut set = doc! {
+        "url": pattern.pattern(),
+        "match_type": match_type,
+        "original_url": data.url.trim(),
+        "ignore_query": data.ignore_query,
+        "status": &data.status,
+        "updated_at": updated_at,
+    };
+    if let Some(reason) = &data.reason {
+        set.insert("reason", reason);
+    }
+    if let Some(tags) = &data.tags {
+        set.insert("tags", normalize_tags(tags.clone()));
+    }
+    let update = doc! { "$set": set };
 
     match collection
         .update_one(doc! { "_id": oid }, update, None)
@@ -160,36 +234,20 @@ pub async fn edit_blacklist_url_by_id(
     }
 }
 
+#[derive(Debug, Deserialize)]
+pub struct CheckUrlInput {
+    pub url: String,
+}
+
 // Check if URL is in the blacklist
 pub async fn is_blacklist_url(
     db_client: web::Data<Client>,
-    data: web::Json<InputData>,
+    cache: web::Data<BlacklistCache>,
+    data: web::Json<CheckUrlInput>,
 ) -> impl Responder {
     println!("Received request to check URL: {:?}", data.url); // Add logging
 
-    let collection: Collection<MaliciousUrl> = db_client
-        .database("rustkeeper")
-        .collection("malicious_urls");
-
-    // Create a different filter for the root URL
-    let filter = if data.url == "/" {
-        doc! {
-            "url": "/",
-            "status": "blocked"
-        }
-    } else {
-        doc! {
-            "$or": [
-                { "url": &data.url },
-                { "url": { "$regex": &format!("{}.*", &data.url), "$options": "i" } },
-            ],
-            "status": "blocked"
-        }
-    };
-
-    println!("Query filter: {:?}", filter); // Add logging
-
-    match collection.find_one(filter, None).await {
+    match lookup_url(&db_client, &cache, &data.url).await {
         Ok(Some(result)) => {
             println!("URL is blacklisted: {:?}", result); // Add logging
             HttpResponse::Ok().json(true) // URL is blacklisted
@@ -204,3 +262,52 @@ pub async fn is_blacklist_url(
         }
     }
 }
+
+// Find the entry blocking `url`, if any. The URL is normalized first, so
+// spellings such as "HTTP://Evil.com:80/a/../b" match an entry for
+// "http://evil.com/b".
+pub async fn lookup_url(
+    db_client: &Client,
+    cache: &BlacklistCache,
+    url: &str,
+) -> mongodb::error::Result<Option<MaliciousUrl>> {
+    let url = &normalize_url(url);
+    // Answer from memory once the cache has loaded; query MongoDB until then
+    if cache.is_ready() {
+        Ok(cache.find_url(url))
+    } else {
+        find_blacklisted_url(db_client, url).await
+    }
+}
+
+// Look up an entry matching `url` without the cache. Patterns are evaluated
+// here rather than in a MongoDB query, and the winner is chosen exactly as
+// the cache does.
+async fn find_blacklisted_url(
+    db_client: &Client,
+    url: &str,
+) -> mongodb::error::Result<Option<MaliciousUrl>> {
+    let collection: Collection<MaliciousUrl> = db_client
+        .database("rustkeeper")
+        .collection("malicious_urls");
+
+    let mut cursor = collection.find(doc! { "status": "blocked" }, None).await?;
+    let mut best: Option<(MaliciousUrl, (u8, usize))> = None;
+    while let Some(entry) = cursor.next().await {
+        let entry = entry?;
+        let specificity = match entry.pattern() {
+            Ok(pattern) if pattern.matches(url) => pattern.specificity(),
+            _ => continue,
+        };
+        let better = match &best {
+            Some((current, current_specificity)) => {
+                (Reverse(specificity), entry._id) < (Reverse(*current_specificity), current._id)
+            }
+            None => true,
+        };
+        if better {
+            best = Some((entry, specificity));
+        }
+    }
+    Ok(best.map(|(entry, _)| entry))
+}
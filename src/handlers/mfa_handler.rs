@@ -0,0 +1,185 @@
+// src/handlers/mfa_handler.rs
+use crate::auth::mfa::{self, requires_mfa};
+use crate::auth::{Claims, Role};
+use crate::models::{BrigatoryUser, MfaPolicy};
+use actix_web::{web, HttpResponse, Responder};
+use mongodb::{bson::doc, Client, Collection};
+use serde::Deserialize;
+use serde_json::json;
+
+#[derive(Debug, Deserialize)]
+pub struct CodeInput {
+    pub code: String,
+}
+
+// Either a code from the authenticator app or an unused recovery code
+#[derive(Debug, Deserialize)]
+pub struct SecondFactorInput {
+    pub code: Option<String>,
+    pub recovery_code: Option<String>,
+}
+
+// The signed-in user, or the response to send when it cannot be loaded
+async fn current_user(db_client: &Client, claims: &Claims) -> Result<BrigatoryUser, HttpResponse> {
+    let collection: Collection<BrigatoryUser> = db_client
+        .database("rustkeeper")
+        .collection("brigatory_users");
+    match collection
+        .find_one(doc! { "email": &claims.sub }, None)
+        .await
+    {
+        Ok(Some(user)) => Ok(user),
+        Ok(None) => Err(HttpResponse::NotFound().body("User no longer exists")),
+        Err(e) => Err(HttpResponse::InternalServerError().json(e.to_string())),
+    }
+}
+
+// Start TOTP enrollment: returns the secret and an otpauth URI to render as a QR code
+pub async fn enroll_totp(
+    db_client: web::Data<Client>,
+    claims: web::ReqData<Claims>,
+) -> impl Responder {
+    let user = match current_user(&db_client, &claims).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    if user.mfa.enabled {
+        return HttpResponse::Conflict().body("Two-factor authentication is already enabled");
+    }
+
+    match mfa::begin_enrollment(&db_client, &user).await {
+        Ok((secret, uri)) => HttpResponse::Ok().json(json!({
+            "secret": secret,
+            "otpauth_uri": uri,
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
+    }
+}
+
+// Finish enrollment with a code from the app; returns the recovery codes once
+pub async fn confirm_totp(
+    db_client: web::Data<Client>,
+    claims: web::ReqData<Claims>,
+    data: web::Json<CodeInput>,
+) -> impl Responder {
+    let user = match current_user(&db_client, &claims).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+
+    match mfa::confirm_enrollment(&db_client, &user, &data.code).await {
+        Ok(Some(recovery_codes)) => HttpResponse::Ok().json(json!({
+            "enabled": true,
+            "recovery_codes": recovery_codes,
+        })),
+        Ok(None) => HttpResponse::BadRequest().body("Invalid code or no enrollment in progress"),
+        Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
+    }
+}
+
+// Turn two-factor authentication off, unless the user's role requires it
+pub async fn disable_totp(
+    db_client: web::Data<Client>,
+    claims: web::ReqData<Claims>,
+    data: web::Json<SecondFactorInput>,
+) -> impl Responder {
+    let user = match current_user(&db_client, &claims).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    if !user.mfa.enabled {
+        return HttpResponse::Conflict().body("Two-factor authentication is not enabled");
+    }
+    match mfa::load_policy(&db_client).await {
+        Ok(policy) if requires_mfa(&policy, &user) => {
+            return HttpResponse::Forbidden()
+                .body("Two-factor authentication is required for your role")
+        }
+        Ok(_) => {}
+        Err(e) => return HttpResponse::InternalServerError().json(e.to_string()),
+    }
+
+    let verified = mfa::verify_second_factor(
+        &db_client,
+        &user,
+        data.code.as_deref(),
+        data.recovery_code.as_deref(),
+    )
+    .await;
+    match verified {
+        Ok(true) => match mfa::disable(&db_client, &user).await {
+            Ok(()) => HttpResponse::Ok().json("Two-factor authentication disabled"),
+            Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
+        },
+        Ok(false) => HttpResponse::Unauthorized().body("Invalid code"),
+        Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
+    }
+}
+
+// Replace all recovery codes, e.g. after some were used
+pub async fn new_recovery_codes(
+    db_client: web::Data<Client>,
+    claims: web::ReqData<Claims>,
+    data: web::Json<SecondFactorInput>,
+) -> impl Responder {
+    let user = match current_user(&db_client, &claims).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    if !user.mfa.enabled {
+        return HttpResponse::Conflict().body("Two-factor authentication is not enabled");
+    }
+
+    let verified = mfa::verify_second_factor(
+        &db_client,
+        &user,
+        data.code.as_deref(),
+        data.recovery_code.as_deref(),
+    )
+    .await;
+    match verified {
+        Ok(true) => match mfa::regenerate_recovery_codes(&db_client, &user).await {
+            Ok(codes) => HttpResponse::Ok().json(json!({ "recovery_codes": codes })),
+            Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
+        },
+        Ok(false) => HttpResponse::Unauthorized().body("Invalid code"),
+        Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
+    }
+}
+
+pub async fn get_mfa_policy(db_client: web::Data<Client>) -> impl Responder {
+    match mfa::load_policy(&db_client).await {
+        Ok(policy) => HttpResponse::Ok().json(policy),
+        Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MfaPolicyInput {
+    pub required_roles: Vec<Role>,
+}
+
+// Choose the roles that must sign in with a second factor. Affected users
+// without one are asked to enroll at their next signin.
+pub async fn update_mfa_policy(
+    db_client: web::Data<Client>,
+    claims: web::ReqData<Claims>,
+    data: web::Json<MfaPolicyInput>,
+) -> impl Responder {
+    let mut required_roles: Vec<Role> = Vec::new();
+    for role in data.into_inner().required_roles {
+        if !required_roles.contains(&role) {
+            required_roles.push(role);
+        }
+    }
+    let policy = MfaPolicy {
+        required_roles,
+        updated_by: Some(claims.sub.clone()),
+        updated_at: Some(chrono::Utc::now()),
+    };
+
+    match mfa::save_policy(&db_client, &policy).await {
+        Ok(()) => HttpResponse::Ok().json(policy),
+        Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
+    }
+}
@@ -4,6 +4,12 @@ pub use blacklist_handler::{
     get_blacklist_ip_by_id, is_blacklist_ip,
 };
 
+pub mod allowlist_handler;
+pub use allowlist_handler::{
+    add_allowlist_ip, delete_allowlist_ip_by_id, edit_allowlist_ip_by_id, get_all_allowlist_ip,
+    get_allowlist_ip_by_id,
+};
+
 pub mod malicious_handler;
 pub use malicious_handler::{
     add_blacklist_url, delete_blacklist_url_by_id, edit_blacklist_url_by_id, get_all_blacklist_url,
@@ -11,7 +17,54 @@ pub use malicious_handler::{
 };
 
 pub mod brigatory_users_handler;
-pub use brigatory_users_handler::{signin, signup};
+pub use brigatory_users_handler::{signin, signin_mfa, signin_mfa_enroll, signup};
 
 pub mod check_rate_limit_handler; // Add this line to include the rate_limit_handler module
 pub use check_rate_limit_handler::check_rate_limit; // Add this line to
+
+pub mod decide_handler;
+pub use decide_handler::decide;
+
+pub mod rate_limit_policy_handler;
+pub use rate_limit_policy_handler::{
+    add_rate_limit_policy, delete_rate_limit_policy, edit_rate_limit_policy,
+    get_all_rate_limit_policies, get_rate_limit_policy,
+};
+
+pub mod cache_handler;
+pub use cache_handler::cache_status;
+
+pub mod user_admin_handler;
+pub use user_admin_handler::{
+    approve_user, change_user_role, delete_user_by_id, get_all_users, get_user_by_id,
+    reactivate_user, reject_user, reset_user_mfa, reset_user_password, suspend_user,
+};
+
+pub mod lockout_handler;
+pub use lockout_handler::{delete_signin_lockout, get_all_signin_lockouts};
+
+pub mod mfa_handler;
+pub use mfa_handler::{
+    confirm_totp, disable_totp, enroll_totp, get_mfa_policy, new_recovery_codes, update_mfa_policy,
+};
+
+pub mod password_handler;
+pub use password_handler::{change_password, confirm_password_reset, request_password_reset};
+
+pub mod token_handler;
+pub use token_handler::{refresh_token, revoke_all_sessions, signout};
+
+pub mod api_key_handler;
+pub use api_key_handler::{
+    create_api_key, delete_api_key_by_id, edit_api_key_by_id, get_all_api_keys, get_api_key_by_id,
+};
+
+pub mod jwks_handler;
+pub use jwks_handler::jwks;
+
+pub mod rule_handler;
+pub use rule_handler::{
+    add_rule, delete_rule_by_id, edit_rule_by_id, get_all_rules, get_rule_by_id,
+};
+
+pub mod validation;
@@ -0,0 +1,289 @@
+// src/handlers/password_handler.rs
+use crate::auth::lockout::clear_failures;
+use crate::auth::password::{
+    consume_reset, create_reset, find_reset, reset_notification, set_password, verify_password,
+    PasswordPolicy,
+};
+use crate::auth::sessions::revoke_user_sessions;
+use crate::auth::Claims;
+use crate::cache::BlacklistCache;
+use crate::handlers::brigatory_users_handler::{
+    guard_signin, record_failed_signin, signin_subjects,
+};
+use crate::handlers::validation::{validation_error, FieldError};
+use crate::models::BrigatoryUser;
+use crate::notify::Notifier;
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use log::error;
+use mongodb::{bson::doc, Client, Collection};
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct ChangePasswordInput {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResetRequestInput {
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResetConfirmInput {
+    pub token: String,
+    pub new_password: String,
+}
+
+fn users(db_client: &Client) -> Collection<BrigatoryUser> {
+    db_client
+        .database("rustkeeper")
+        .collection("brigatory_users")
+}
+
+// 400 listing the policy problems with a new password. The password itself
+// is never echoed back.
+pub fn password_errors(field: &str, problems: Vec<String>) -> HttpResponse {
+    validation_error(
+        problems
+            .into_iter()
+            .map(|problem| FieldError::new(field, "", problem))
+            .collect(),
+    )
+}
+
+// Check `new_password` against the policy, including reuse
+fn check_new_password(
+    policy: &PasswordPolicy,
+    user: &BrigatoryUser,
+    new_password: &str,
+) -> Result<(), HttpResponse> {
+    let problems = policy.check(new_password, &user.email);
+    if !problems.is_empty() {
+        return Err(password_errors("new_password", problems));
+    }
+    if policy.is_reused(new_password, user) {
+        return Err(password_errors(
+            "new_password",
+            vec!["Password must not be one of your recent passwords".to_string()],
+        ));
+    }
+    Ok(())
+}
+
+// Store a checked new password. Sessions are revoked so that whoever knew the
+// old password is signed out.
+async fn replace_password(
+    db_client: &Client,
+    policy: &PasswordPolicy,
+    user: &BrigatoryUser,
+    new_password: &str,
+) -> HttpResponse {
+    let (new_hash, scheme) = match policy.hash(new_password) {
+        Ok(hashed) => hashed,
+        Err(_) => return HttpResponse::InternalServerError().body("Error hashing password"),
+    };
+    match set_password(db_client, user, &new_hash, scheme, policy).await {
+        Ok(true) => {}
+        Ok(false) => return HttpResponse::Conflict().body("Password was changed concurrently"),
+        Err(e) => return HttpResponse::InternalServerError().json(e.to_string()),
+    }
+    if let Some(user_id) = user._id {
+        if let Err(e) = revoke_user_sessions(db_client, user_id).await {
+            return HttpResponse::InternalServerError().json(e.to_string());
+        }
+    }
+    HttpResponse::Ok().json("Password changed; please sign in again")
+}
+
+// Change the signed-in user's password
+pub async fn change_password(
+    req: HttpRequest,
+    db_client: web::Data<Client>,
+    cache: web::Data<BlacklistCache>,
+    policy: web::Data<PasswordPolicy>,
+    claims: web::ReqData<Claims>,
+    data: web::Json<ChangePasswordInput>,
+) -> impl Responder {
+    // Wrong current passwords count as failed signins, so a stolen session
+    // cannot be used to guess the password
+    let ip = req.peer_addr().map(|addr| addr.ip());
+    let subjects = signin_subjects(&claims.sub, ip);
+    if let Err(response) = guard_signin(&db_client, &cache, ip, &subjects).await {
+        return response;
+    }
+
+    let user = match users(&db_client)
+        .find_one(doc! { "email": &claims.sub }, None)
+        .await
+    {
+        Ok(Some(user)) => user,
+        Ok(None) => return HttpResponse::NotFound().body("User no longer exists"),
+        Err(e) => return HttpResponse::InternalServerError().json(e.to_string()),
+    };
+    if !verify_password(&data.current_password, &user.password).unwrap_or(false) {
+        record_failed_signin(&db_client, &cache, ip, &subjects).await;
+        return HttpResponse::Unauthorized().body("Current password is incorrect");
+    }
+    for subject in &subjects {
+        if let Err(e) = clear_failures(&db_client, subject).await {
+            println!("Failed to clear signin failures: {}", e);
+        }
+    }
+    if let Err(response) = check_new_password(&policy, &user, &data.new_password) {
+        return response;
+    }
+
+    replace_password(&db_client, &policy, &user, &data.new_password).await
+}
+
+// Self-service reset: send a reset token to the account's email. The
+// response is the same whether or not the account exists.
+pub async fn request_password_reset(
+    req: HttpRequest,
+    db_client: web::Data<Client>,
+    cache: web::Data<BlacklistCache>,
+    notifier: web::Data<dyn Notifier>,
+    data: web::Json<ResetRequestInput>,
+) -> impl Responder {
+    // Throttled like signin, so the endpoint cannot be used to flood inboxes
+    let ip = req.peer_addr().map(|addr| addr.ip());
+    let subjects = signin_subjects(&data.email, ip);
+    if let Err(response) = guard_signin(&db_client, &cache, ip, &subjects).await {
+        return response;
+    }
+
+    let accepted = HttpResponse::Accepted()
+        .json("If the account exists, a reset link has been sent to its email");
+    let user = match users(&db_client)
+        .find_one(doc! { "email": &data.email }, None)
+        .await
+    {
+        Ok(Some(user)) => user,
+        Ok(None) => return accepted,
+        Err(e) => return HttpResponse::InternalServerError().json(e.to_string()),
+    };
+    let user_id = match user._id {
+        Some(user_id) => user_id,
+        None => return accepted,
+    };
+
+    let token = match create_reset(&db_client, user_id, &user.email).await {
+        Ok(token) => token,
+        Err(e) => return HttpResponse::InternalServerError().json(e.to_string()),
+    };
+    if let Err(e) = notifier.send(reset_notification(&user.email, &token)).await {
+        // Not reported to the client, which must not learn whether the account exists
+        error!("Failed to send password reset to {}: {}", user.email, e);
+    }
+    accepted
+}
+
+// Set a new password with a reset token
+pub async fn confirm_password_reset(
+    db_client: web::Data<Client>,
+    policy: web::Data<PasswordPolicy>,
+    data: web::Json<ResetConfirmInput>,
+) -> impl Responder {
+    let reset = match find_reset(&db_client, &data.token).await {
+        Ok(Some(reset)) => reset,
+        Ok(None) => return HttpResponse::BadRequest().body("Invalid or expired reset token"),
+        Err(e) => return HttpResponse::InternalServerError().json(e.to_string()),
+    };
+    let user = match users(&db_client)
+        .find_one(doc! { "_id": reset.user_id }, None)
+        .await
+    {
+        Ok(Some(user)) => user,
+        Ok(None) => return HttpResponse::BadRequest().body("Invalid or expired reset token"),
+        Err(e) => return HttpResponse::InternalServerError().json(e.to_string()),
+    };
+    // A rejected password leaves the token usable for another attempt
+    if let Err(response) = check_new_password(&policy, &user, &data.new_password) {
+        return response;
+    }
+    match consume_reset(&db_client, &reset).await {
+        Ok(true) => {}
+        Ok(false) => return HttpResponse::BadRequest().body("Invalid or expired reset token"),
+        Err(e) => return HttpResponse::InternalServerError().json(e.to_string()),
+    }
+
+    replace_password(&db_client, &policy, &user, &data.new_password).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notify::MemoryNotifier;
+    use actix_web::{test, App};
+    use mongodb::bson::{oid::ObjectId, Document};
+    use serde_json::json;
+    use std::sync::Arc;
+
+    #[actix_web::test]
+    #[ignore = "requires MongoDB (set MONGODB_URI)"]
+    async fn reset_requests_mail_a_working_token() {
+        let uri = std::env::var("MONGODB_URI").expect("MONGODB_URI must be set");
+        let db_client = Client::with_uri_str(&uri).await.unwrap();
+        let email = format!("reset-{}@example.com", ObjectId::new().to_hex());
+        let now = chrono::Utc::now().to_rfc3339();
+        let inserted = users(&db_client)
+            .clone_with_type::<Document>()
+            .insert_one(
+                doc! {
+                    "full_name": "Reset Test",
+                    "email": &email,
+                    "password": "not a hash",
+                    "status": "active",
+                    "created_at": &now,
+                    "updated_at": &now,
+                },
+                None,
+            )
+            .await
+            .unwrap();
+        let user_id = inserted.inserted_id.as_object_id().unwrap();
+
+        let notifier = Arc::new(MemoryNotifier::default());
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(db_client.clone()))
+                .app_data(web::Data::new(BlacklistCache::new()))
+                .app_data(web::Data::from(notifier.clone() as Arc<dyn Notifier>))
+                .route("/password/reset", web::post().to(request_password_reset)),
+        )
+        .await;
+        let request = |email: &str| {
+            test::TestRequest::post()
+                .uri("/password/reset")
+                .set_json(json!({ "email": email }))
+                .to_request()
+        };
+
+        // Unknown accounts get the same answer and no message
+        let res = test::call_service(&app, request("nobody@example.com")).await;
+        assert_eq!(res.status(), 202);
+        assert!(notifier.sent().is_empty());
+
+        let res = test::call_service(&app, request(&email)).await;
+        assert_eq!(res.status(), 202);
+        let sent = notifier.sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].to, email);
+        let token = sent[0]
+            .body
+            .lines()
+            .find_map(|line| line.strip_prefix("Reset token: "))
+            .expect("the message carries the token");
+
+        let reset = find_reset(&db_client, token).await.unwrap().unwrap();
+        assert_eq!(reset.user_id, user_id);
+        assert!(consume_reset(&db_client, &reset).await.unwrap());
+        assert!(find_reset(&db_client, token).await.unwrap().is_none());
+
+        users(&db_client)
+            .delete_one(doc! { "_id": user_id }, None)
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,257 @@
+// src/handlers/rate_limit_policy_handler.rs
+use crate::db::is_duplicate_key;
+use crate::handlers::validation::{validation_error, FieldError};
+use crate::models::RateLimitPolicy;
+use crate::ratelimit::Algorithm;
+use actix_web::{web, HttpResponse, Responder};
+use futures::stream::StreamExt;
+use mongodb::{
+    bson::{self, doc},
+    options::FindOptions,
+    Client, Collection,
+};
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct PolicyInput {
+    pub name: String,
+    pub limit: i32,
+    pub window_seconds: i64,
+    #[serde(default)]
+    pub burst: i32,
+    #[serde(default)]
+    pub algorithm: Algorithm,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdatePolicyInput {
+    pub limit: i32,
+    pub window_seconds: i64,
+    #[serde(default)]
+    pub burst: i32,
+    // Left unchanged when omitted
+    #[serde(default)]
+    pub algorithm: Option<Algorithm>,
+}
+
+fn policy_collection(db_client: &Client) -> Collection<RateLimitPolicy> {
+    db_client
+        .database("rustkeeper")
+        .collection("rate_limit_policies")
+}
+
+// Collect every problem with the numeric policy settings
+fn validate_settings(limit: i32, window_seconds: i64, burst: i32) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+    if limit <= 0 {
+        errors.push(FieldError::new(
+            "limit",
+            &limit.to_string(),
+            "limit must be greater than 0",
+        ));
+    }
+    if window_seconds <= 0 {
+        errors.push(FieldError::new(
+            "window_seconds",
+            &window_seconds.to_string(),
+            "window_seconds must be greater than 0",
+        ));
+    }
+    if burst < 0 {
+        errors.push(FieldError::new(
+            "burst",
+            &burst.to_string(),
+            "burst must not be negative",
+        ));
+    }
+    errors
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+// Create a named rate limit policy
+pub async fn add_rate_limit_policy(
+    db_client: web::Data<Client>,
+    data: web::Json<PolicyInput>,
+) -> impl Responder {
+    let mut errors = validate_settings(data.limit, data.window_seconds, data.burst);
+    if !is_valid_name(&data.name) {
+        errors.push(FieldError::new(
+            "name",
+            &data.name,
+            "name must be 1-64 characters of letters, digits, '-' or '_'",
+        ));
+    }
+    if !errors.is_empty() {
+        return validation_error(errors);
+    }
+
+    let mut policy = RateLimitPolicy::new(
+        data.name.clone(),
+        data.limit,
+        data.window_seconds,
+        data.burst,
+    );
+    policy.algorithm = data.algorithm;
+
+    match policy_collection(&db_client)
+        .insert_one(&policy, None)
+        .await
+    {
+        Ok(result) => {
+            policy._id = result.inserted_id.as_object_id();
+            HttpResponse::Created().json(policy)
+        }
+        Err(e) if is_duplicate_key(&e) => {
+            HttpResponse::Conflict().body("A policy with this name already exists")
+        }
+        Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
+    }
+}
+
+// List all rate limit policies
+pub async fn get_all_rate_limit_policies(db_client: web::Data<Client>) -> impl Responder {
+    let find_options = FindOptions::builder().sort(doc! { "name": 1 }).build();
+    let mut cursor = match policy_collection(&db_client).find(None, find_options).await {
+        Ok(cursor) => cursor,
+        Err(e) => return HttpResponse::InternalServerError().json(e.to_string()),
+    };
+
+    let mut results: Vec<RateLimitPolicy> = Vec::new();
+    while let Some(result) = cursor.next().await {
+        match result {
+            Ok(document) => results.push(document),
+            Err(e) => return HttpResponse::InternalServerError().json(e.to_string()),
+        }
+    }
+
+    HttpResponse::Ok().json(results)
+}
+
+// Get a single policy by name
+pub async fn get_rate_limit_policy(
+    db_client: web::Data<Client>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let name = path.into_inner();
+    match policy_collection(&db_client)
+        .find_one(doc! { "name": &name }, None)
+        .await
+    {
+        Ok(Some(policy)) => HttpResponse::Ok().json(policy),
+        Ok(None) => HttpResponse::NotFound().body("No policy found with the provided name"),
+        Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
+    }
+}
+
+// Update the settings of a policy by name
+pub async fn edit_rate_limit_policy(
+    db_client: web::Data<Client>,
+    path: web::Path<String>,
+    data: web::Json<UpdatePolicyInput>,
+) -> impl Responder {
+    let errors = validate_settings(data.limit, data.window_seconds, data.burst);
+    if !errors.is_empty() {
+        return validation_error(errors);
+    }
+
+    let name = path.into_inner();
+    // Same format as `RateLimitPolicy::new`
+    let updated_at = match bson::to_bson(&chrono::Utc::now()) {
+        Ok(updated_at) => updated_at,
+        Err(e) => return HttpResponse::InternalServerError().json(e.to_string()),
+    };
+    let mut set = doc! {
+        "limit": data.limit,
+        "window_seconds": data.window_seconds,
+        "burst": data.burst,
+        "updated_at": updated_at,
+    };
+    if let Some(algorithm) = &data.algorithm {
+        match bson::to_bson(algorithm) {
+            Ok(algorithm) => set.insert("algorithm", algorithm),
+            Err(e) => return HttpResponse::InternalServerError().json(e.to_string()),
+        };
+    }
+    let update = doc! { "$set": set };
+
+    match policy_collection(&db_client)
+        .update_one(doc! { "name": &name }, update, None)
+        .await
+    {
+        Ok(update_result) => {
+            if update_result.matched_count == 1 {
+                HttpResponse::Ok().json("Rate limit policy successfully updated")
+            } else {
+                HttpResponse::NotFound().body("No policy found with the provided name")
+            }
+        }
+        Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
+    }
+}
+
+// Delete a policy by name
+pub async fn delete_rate_limit_policy(
+    db_client: web::Data<Client>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let name = path.into_inner();
+    match policy_collection(&db_client)
+        .delete_one(doc! { "name": &name }, None)
+        .await
+    {
+        Ok(delete_result) => {
+            if delete_result.deleted_count == 1 {
+                HttpResponse::Ok().json("Rate limit policy successfully deleted")
+            } else {
+                HttpResponse::NotFound().body("No policy found with the provided name")
+            }
+        }
+        Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
+    }
+}
+
+// Resolve the policy a check should use. A missing "default" or "signin"
+// policy falls back to its built-in settings; other names must exist.
+pub async fn find_policy(
+    db_client: &Client,
+    name: &str,
+) -> mongodb::error::Result<Option<RateLimitPolicy>> {
+    let stored = policy_collection(db_client)
+        .find_one(doc! { "name": name }, None)
+        .await?;
+    Ok(match stored {
+        Some(policy) => Some(policy),
+        None if name == crate::models::rate_limit_policy::DEFAULT_POLICY => {
+            Some(RateLimitPolicy::builtin_default())
+        }
+        None if name == crate::models::rate_limit_policy::SIGNIN_POLICY => {
+            Some(RateLimitPolicy::builtin_signin())
+        }
+        None => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn updates_keep_the_algorithm_unless_given() {
+        let input: UpdatePolicyInput =
+            serde_json::from_str(r#"{"limit": 10, "window_seconds": 60}"#).unwrap();
+        assert_eq!(input.algorithm, None);
+
+        let input: UpdatePolicyInput = serde_json::from_str(
+            r#"{"limit": 10, "window_seconds": 60, "algorithm": "token_bucket"}"#,
+        )
+        .unwrap();
+        assert_eq!(input.algorithm, Some(Algorithm::TokenBucket));
+    }
+}
@@ -0,0 +1,267 @@
+// src/handlers/rule_handler.rs
+use crate::auth::Claims;
+use crate::cache::BlacklistCache;
+use crate::handlers::rate_limit_policy_handler::find_policy;
+use crate::handlers::validation::{validation_error, FieldError};
+use crate::models::PolicyRule;
+use crate::net::IpRange;
+use crate::rules::{RuleAction, RuleConditions};
+use actix_web::{web, HttpResponse, Responder};
+use chrono::Utc;
+use futures::stream::TryStreamExt;
+use mongodb::{
+    bson::{self, doc, oid::ObjectId},
+    options::{FindOneAndUpdateOptions, FindOptions, ReturnDocument},
+    Client, Collection,
+};
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct RuleInput {
+    pub name: String,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub priority: i32,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    pub conditions: RuleConditions,
+    pub action: RuleAction,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn rule_collection(db_client: &Client) -> Collection<PolicyRule> {
+    db_client.database("rustkeeper").collection("policy_rules")
+}
+
+// Enabled rules for `/decide`
+pub async fn enabled_rules(
+    db_client: &Client,
+    cache: &BlacklistCache,
+) -> mongodb::error::Result<Vec<PolicyRule>> {
+    // Answer from memory once the cache has loaded; query MongoDB until then
+    if cache.is_ready() {
+        Ok(cache.enabled_rules())
+    } else {
+        find_enabled_rules(db_client).await
+    }
+}
+
+async fn find_enabled_rules(db_client: &Client) -> mongodb::error::Result<Vec<PolicyRule>> {
+    let find_options = FindOptions::builder().sort(doc! { "priority": -1 }).build();
+    rule_collection(db_client)
+        .find(doc! { "enabled": true }, find_options)
+        .await?
+        .try_collect()
+        .await
+}
+
+// Collect every problem with a rule. A rule without conditions would match
+// every request, so at least one is required; "0.0.0.0/0" and "::/0" say so
+// explicitly.
+async fn validate(db_client: &Client, data: &RuleInput) -> Result<Vec<FieldError>, HttpResponse> {
+    let mut errors = Vec::new();
+    if data.name.trim().is_empty() {
+        errors.push(FieldError::new(
+            "name",
+            &data.name,
+            "Name must not be empty",
+        ));
+    }
+
+    let conditions = &data.conditions;
+    if conditions.is_empty() {
+        errors.push(FieldError::new(
+            "conditions",
+            "{}",
+            "At least one condition is required",
+        ));
+    }
+    for (i, ip) in conditions.ips.iter().enumerate() {
+        if let Err(e) = IpRange::parse(ip) {
+            errors.push(FieldError::new(&format!("conditions.ips[{}]", i), ip, e));
+        }
+    }
+    for (i, method) in conditions.methods.iter().enumerate() {
+        if method.is_empty() || !method.chars().all(|c| c.is_ascii_alphabetic()) {
+            errors.push(FieldError::new(
+                &format!("conditions.methods[{}]", i),
+                method,
+                "Method must be letters only, e.g. \"GET\"",
+            ));
+        }
+    }
+    for (i, header) in conditions.headers.iter().enumerate() {
+        if header.name.trim().is_empty() {
+            errors.push(FieldError::new(
+                &format!("conditions.headers[{}].name", i),
+                &header.name,
+                "Header name must not be empty",
+            ));
+        }
+    }
+    for (i, country) in conditions.countries.iter().enumerate() {
+        if country.len() != 2 || !country.chars().all(|c| c.is_ascii_alphabetic()) {
+            errors.push(FieldError::new(
+                &format!("conditions.countries[{}]", i),
+                country,
+                "Country must be a two-letter ISO 3166-1 code",
+            ));
+        }
+    }
+
+    if let RuleAction::RateLimit { policy } = &data.action {
+        match find_policy(db_client, policy).await {
+            Ok(Some(_)) => {}
+            Ok(None) => errors.push(FieldError::new(
+                "action.policy",
+                policy,
+                "No rate limit policy with this name exists",
+            )),
+            Err(e) => return Err(HttpResponse::InternalServerError().json(e.to_string())),
+        }
+    }
+    Ok(errors)
+}
+
+// Create a rule
+pub async fn add_rule(
+    db_client: web::Data<Client>,
+    claims: web::ReqData<Claims>,
+    data: web::Json<RuleInput>,
+) -> impl Responder {
+    match validate(&db_client, &data).await {
+        Ok(errors) if errors.is_empty() => {}
+        Ok(errors) => return validation_error(errors),
+        Err(response) => return response,
+    }
+
+    let data = data.into_inner();
+    let mut rule = PolicyRule::new(
+        data.name.trim().to_string(),
+        data.description.filter(|d| !d.trim().is_empty()),
+        data.priority,
+        data.action,
+        data.conditions,
+        Some(claims.sub.clone()),
+    );
+    rule.enabled = data.enabled;
+
+    match rule_collection(&db_client).insert_one(&rule, None).await {
+        Ok(result) => {
+            rule._id = result.inserted_id.as_object_id();
+            HttpResponse::Created().json(rule)
+        }
+        Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
+    }
+}
+
+// List all rules in evaluation order
+pub async fn get_all_rules(db_client: web::Data<Client>) -> impl Responder {
+    let find_options = FindOptions::builder()
+        .sort(doc! { "priority": -1, "name": 1 })
+        .build();
+    let found: Result<Vec<PolicyRule>, _> = match rule_collection(&db_client)
+        .find(doc! {}, find_options)
+        .await
+    {
+        Ok(cursor) => cursor.try_collect().await,
+        Err(e) => Err(e),
+    };
+    match found {
+        Ok(rules) => HttpResponse::Ok().json(rules),
+        Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
+    }
+}
+
+pub async fn get_rule_by_id(
+    db_client: web::Data<Client>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let oid = match ObjectId::parse_str(path.into_inner()) {
+        Ok(oid) => oid,
+        Err(_) => return HttpResponse::BadRequest().body("Invalid ID format"),
+    };
+
+    match rule_collection(&db_client)
+        .find_one(doc! { "_id": oid }, None)
+        .await
+    {
+        Ok(Some(rule)) => HttpResponse::Ok().json(rule),
+        Ok(None) => HttpResponse::NotFound().body("No rule found with the provided ID"),
+        Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
+    }
+}
+
+// Replace a rule's settings
+pub async fn edit_rule_by_id(
+    db_client: web::Data<Client>,
+    path: web::Path<String>,
+    data: web::Json<RuleInput>,
+) -> impl Responder {
+    let oid = match ObjectId::parse_str(path.into_inner()) {
+        Ok(oid) => oid,
+        Err(_) => return HttpResponse::BadRequest().body("Invalid ID format"),
+    };
+    match validate(&db_client, &data).await {
+        Ok(errors) if errors.is_empty() => {}
+        Ok(errors) => return validation_error(errors),
+        Err(response) => return response,
+    }
+
+    let (conditions, action, updated_at) = match (
+        bson::to_bson(&data.conditions),
+        bson::to_bson(&data.action),
+        bson::to_bson(&Utc::now()),
+    ) {
+        (Ok(conditions), Ok(action), Ok(updated_at)) => (conditions, action, updated_at),
+        (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => {
+            return HttpResponse::InternalServerError().json(e.to_string())
+        }
+    };
+    let update = doc! {
+        "$set": {
+            "name": data.name.trim(),
+            "description": data.description.as_deref().filter(|d| !d.trim().is_empty()),
+            "priority": data.priority,
+            "enabled": data.enabled,
+            "conditions": conditions,
+            "action": action,
+            "updated_at": updated_at,
+        }
+    };
+    let options = FindOneAndUpdateOptions::builder()
+        .return_document(ReturnDocument::After)
+        .build();
+    match rule_collection(&db_client)
+        .find_one_and_update(doc! { "_id": oid }, update, options)
+        .await
+    {
+        Ok(Some(rule)) => HttpResponse::Ok().json(rule),
+        Ok(None) => HttpResponse::NotFound().body("No rule found with the provided ID"),
+        Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
+    }
+}
+
+pub async fn delete_rule_by_id(
+    db_client: web::Data<Client>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let oid = match ObjectId::parse_str(path.into_inner()) {
+        Ok(oid) => oid,
+        Err(_) => return HttpResponse::BadRequest().body("Invalid ID format"),
+    };
+
+    match rule_collection(&db_client)
+        .delete_one(doc! { "_id": oid }, None)
+        .await
+    {
+        Ok(result) if result.deleted_count == 1 => {
+            HttpResponse::Ok().json("Rule successfully deleted")
+        }
+        Ok(_) => HttpResponse::NotFound().body("No rule found with the provided ID"),
+        Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
+    }
+}
@@ -0,0 +1,50 @@
+// src/handlers/token_handler.rs
+use crate::auth::sessions::{refresh_session, revoke_session, revoke_user_sessions, SessionError};
+use crate::auth::{Claims, KeyStore};
+use actix_web::{web, HttpResponse, Responder};
+use mongodb::{bson::oid::ObjectId, Client};
+use serde::Deserialize;
+use serde_json::json;
+
+#[derive(Debug, Deserialize)]
+pub struct RefreshInput {
+    pub refresh_token: String,
+}
+
+// Exchange a refresh token for a new access token and refresh token
+pub async fn refresh_token(
+    db_client: web::Data<Client>,
+    keys: web::Data<KeyStore>,
+    data: web::Json<RefreshInput>,
+) -> impl Responder {
+    match refresh_session(&db_client, &keys, &data.refresh_token).await {
+        Ok(tokens) => HttpResponse::Ok().json(tokens),
+        Err(e @ (SessionError::Invalid | SessionError::Reused)) => HttpResponse::Unauthorized()
+            .json(json!({"error": "Unauthorized", "message": e.to_string()})),
+        Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
+    }
+}
+
+// End the caller's session; its access and refresh tokens stop working
+pub async fn signout(db_client: web::Data<Client>, claims: web::ReqData<Claims>) -> impl Responder {
+    match revoke_session(&db_client, &claims.sid).await {
+        Ok(()) => HttpResponse::Ok().json("Successfully signed out"),
+        Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
+    }
+}
+
+// Revoke every session of a user, e.g. when a device is lost
+pub async fn revoke_all_sessions(
+    db_client: web::Data<Client>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let user_id = match ObjectId::parse_str(path.into_inner()) {
+        Ok(oid) => oid,
+        Err(_) => return HttpResponse::BadRequest().body("Invalid ID format"),
+    };
+
+    match revoke_user_sessions(&db_client, user_id).await {
+        Ok(count) => HttpResponse::Ok().json(json!({ "revoked_sessions": count })),
+        Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
+    }
+}
@@ -0,0 +1,387 @@
+// src/handlers/user_admin_handler.rs
+use crate::auth::password::{create_reset, reset_notification};
+use crate::auth::sessions::revoke_user_sessions;
+use crate::auth::{Claims, Role};
+use crate::handlers::brigatory_users_handler::UserInfo;
+use crate::models::brigatory_users::{MfaSettings, PasswordScheme, UserAction};
+use crate::models::BrigatoryUser;
+use crate::notify::Notifier;
+use actix_web::{web, HttpResponse, Responder};
+use futures::stream::TryStreamExt;
+use mongodb::{
+    bson::{self, doc, oid::ObjectId, Document},
+    options::FindOptions,
+    Client, Collection,
+};
+use serde::{Deserialize, Serialize};
+
+const STATUSES: &[&str] = &["pending", "approved", "rejected", "suspended"];
+
+fn users(db_client: &Client) -> Collection<BrigatoryUser> {
+    db_client
+        .database("rustkeeper")
+        .collection("brigatory_users")
+}
+
+// A user with the admin actions taken on the account
+#[derive(Debug, Serialize)]
+struct UserDetails {
+    #[serde(flatten)]
+    user: UserInfo,
+    // Shows which accounts still have bcrypt or outdated hashes
+    password_scheme: Option<PasswordScheme>,
+    history: Vec<UserAction>,
+}
+
+impl From<BrigatoryUser> for UserDetails {
+    fn from(user: BrigatoryUser) -> Self {
+        UserDetails {
+            user: UserInfo::from(&user),
+            password_scheme: user
+                .password_scheme
+                .or_else(|| PasswordScheme::of_hash(&user.password)),
+            history: user.history,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UserListQuery {
+    pub status: Option<String>,
+}
+
+// List users, optionally only those with a given status, newest first
+pub async fn get_all_users(
+    db_client: web::Data<Client>,
+    query: web::Query<UserListQuery>,
+) -> impl Responder {
+    let mut filter = doc! {};
+    if let Some(status) = &query.status {
+        if !STATUSES.contains(&status.as_str()) {
+            return HttpResponse::BadRequest()
+                .body(format!("status must be one of: {}", STATUSES.join(", ")));
+        }
+        filter.insert("status", status);
+    }
+
+    let find_options = FindOptions::builder()
+        .sort(doc! { "created_at": -1 })
+        .build();
+    let found: Result<Vec<BrigatoryUser>, _> =
+        match users(&db_client).find(filter, find_options).await {
+            Ok(cursor) => cursor.try_collect().await,
+            Err(e) => Err(e),
+        };
+
+    match found {
+        Ok(found) => {
+            HttpResponse::Ok().json(found.into_iter().map(UserDetails::from).collect::<Vec<_>>())
+        }
+        Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
+    }
+}
+
+// Get a single user by ID
+pub async fn get_user_by_id(
+    db_client: web::Data<Client>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let oid = match ObjectId::parse_str(path.into_inner()) {
+        Ok(oid) => oid,
+        Err(_) => return HttpResponse::BadRequest().body("Invalid ID format"),
+    };
+
+    match users(&db_client).find_one(doc! { "_id": oid }, None).await {
+        Ok(Some(user)) => HttpResponse::Ok().json(UserDetails::from(user)),
+        Ok(None) => HttpResponse::NotFound().body("No user found with the provided ID"),
+        Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
+    }
+}
+
+pub async fn approve_user(
+    db_client: web::Data<Client>,
+    claims: web::ReqData<Claims>,
+    path: web::Path<String>,
+) -> impl Responder {
+    change_status(
+        &db_client,
+        &claims,
+        &path,
+        "approve",
+        &["pending", "rejected"],
+        "approved",
+    )
+    .await
+}
+
+pub async fn reject_user(
+    db_client: web::Data<Client>,
+    claims: web::ReqData<Claims>,
+    path: web::Path<String>,
+) -> impl Responder {
+    change_status(
+        &db_client,
+        &claims,
+        &path,
+        "reject",
+        &["pending"],
+        "rejected",
+    )
+    .await
+}
+
+pub async fn suspend_user(
+    db_client: web::Data<Client>,
+    claims: web::ReqData<Claims>,
+    path: web::Path<String>,
+) -> impl Responder {
+    change_status(
+        &db_client,
+        &claims,
+        &path,
+        "suspend",
+        &["approved"],
+        "suspended",
+    )
+    .await
+}
+
+pub async fn reactivate_user(
+    db_client: web::Data<Client>,
+    claims: web::ReqData<Claims>,
+    path: web::Path<String>,
+) -> impl Responder {
+    change_status(
+        &db_client,
+        &claims,
+        &path,
+        "reactivate",
+        &["suspended"],
+        "approved",
+    )
+    .await
+}
+
+// Move a user from one of `from` to `to`, recording the action. Users who can
+// no longer sign in afterwards lose their open sessions.
+async fn change_status(
+    db_client: &Client,
+    claims: &Claims,
+    id: &str,
+    action: &str,
+    from: &[&str],
+    to: &str,
+) -> HttpResponse {
+    let oid = match ObjectId::parse_str(id) {
+        Ok(oid) => oid,
+        Err(_) => return HttpResponse::BadRequest().body("Invalid ID format"),
+    };
+
+    let mut filter = doc! { "_id": oid, "status": { "$in": from } };
+    if to != "approved" {
+        // Admins cannot lock themselves out
+        filter.insert("email", doc! { "$ne": &claims.sub });
+    }
+    let set = doc! { "status": to };
+    let details = format!("status changed to {}", to);
+    let user = match record_action(db_client, filter, set, action, claims, Some(details)).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return explain_no_match(db_client, oid, claims, action).await,
+        Err(e) => return HttpResponse::InternalServerError().json(e.to_string()),
+    };
+
+    if !user.can_sign_in() {
+        if let Err(e) = revoke_user_sessions(db_client, oid).await {
+            return HttpResponse::InternalServerError().json(e.to_string());
+        }
+    }
+    HttpResponse::Ok().json(UserDetails::from(user))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RoleInput {
+    pub role: Role,
+}
+
+// Change a user's role. Open sessions are revoked so the new role applies at once.
+pub async fn change_user_role(
+    db_client: web::Data<Client>,
+    claims: web::ReqData<Claims>,
+    path: web::Path<String>,
+    data: web::Json<RoleInput>,
+) -> impl Responder {
+    let oid = match ObjectId::parse_str(path.into_inner()) {
+        Ok(oid) => oid,
+        Err(_) => return HttpResponse::BadRequest().body("Invalid ID format"),
+    };
+
+    let role = match bson::to_bson(&data.role) {
+        Ok(role) => role,
+        Err(e) => return HttpResponse::InternalServerError().json(e.to_string()),
+    };
+    let filter = doc! { "_id": oid, "email": { "$ne": &claims.sub } };
+    let details = format!("role changed to {}", role.as_str().unwrap_or_default());
+    let user = match record_action(
+        &db_client,
+        filter,
+        doc! { "role": role },
+        "change_role",
+        &claims,
+        Some(details),
+    )
+    .await
+    {
+        Ok(Some(user)) => user,
+        Ok(None) => return explain_no_match(&db_client, oid, &claims, "change_role").await,
+        Err(e) => return HttpResponse::InternalServerError().json(e.to_string()),
+    };
+
+    if let Err(e) = revoke_user_sessions(&db_client, oid).await {
+        return HttpResponse::InternalServerError().json(e.to_string());
+    }
+    HttpResponse::Ok().json(UserDetails::from(user))
+}
+
+// Remove a user's second factor, e.g. after a lost phone. If their role
+// requires one, they are asked to enroll again at the next signin.
+pub async fn reset_user_mfa(
+    db_client: web::Data<Client>,
+    claims: web::ReqData<Claims>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let oid = match ObjectId::parse_str(path.into_inner()) {
+        Ok(oid) => oid,
+        Err(_) => return HttpResponse::BadRequest().body("Invalid ID format"),
+    };
+
+    let mfa = match bson::to_bson(&MfaSettings::default()) {
+        Ok(mfa) => mfa,
+        Err(e) => return HttpResponse::InternalServerError().json(e.to_string()),
+    };
+    let filter = doc! { "_id": oid, "email": { "$ne": &claims.sub } };
+    match record_action(
+        &db_client,
+        filter,
+        doc! { "mfa": mfa },
+        "reset_mfa",
+        &claims,
+        None,
+    )
+    .await
+    {
+        Ok(Some(user)) => HttpResponse::Ok().json(UserDetails::from(user)),
+        Ok(None) => explain_no_match(&db_client, oid, &claims, "reset_mfa").await,
+        Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
+    }
+}
+
+// Send a user a password reset link, e.g. when they cannot sign in anymore
+pub async fn reset_user_password(
+    db_client: web::Data<Client>,
+    notifier: web::Data<dyn Notifier>,
+    claims: web::ReqData<Claims>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let oid = match ObjectId::parse_str(path.into_inner()) {
+        Ok(oid) => oid,
+        Err(_) => return HttpResponse::BadRequest().body("Invalid ID format"),
+    };
+
+    let user = match record_action(
+        &db_client,
+        doc! { "_id": oid },
+        doc! {},
+        "reset_password",
+        &claims,
+        None,
+    )
+    .await
+    {
+        Ok(Some(user)) => user,
+        Ok(None) => return HttpResponse::NotFound().body("No user found with the provided ID"),
+        Err(e) => return HttpResponse::InternalServerError().json(e.to_string()),
+    };
+    let token = match create_reset(&db_client, oid, &claims.sub).await {
+        Ok(token) => token,
+        Err(e) => return HttpResponse::InternalServerError().json(e.to_string()),
+    };
+    if let Err(e) = notifier.send(reset_notification(&user.email, &token)).await {
+        return HttpResponse::BadGateway().body(format!("Failed to send the reset link: {}", e));
+    }
+    HttpResponse::Accepted().json(UserDetails::from(user))
+}
+
+// Delete a user and end their sessions
+pub async fn delete_user_by_id(
+    db_client: web::Data<Client>,
+    claims: web::ReqData<Claims>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let oid = match ObjectId::parse_str(path.into_inner()) {
+        Ok(oid) => oid,
+        Err(_) => return HttpResponse::BadRequest().body("Invalid ID format"),
+    };
+
+    let filter = doc! { "_id": oid, "email": { "$ne": &claims.sub } };
+    match users(&db_client).find_one_and_delete(filter, None).await {
+        Ok(Some(user)) => {
+            // The account document is gone, so the action is logged instead
+            println!("User {} deleted by {}", user.email, claims.sub);
+            if let Err(e) = revoke_user_sessions(&db_client, oid).await {
+                return HttpResponse::InternalServerError().json(e.to_string());
+            }
+            HttpResponse::Ok().json("User successfully deleted")
+        }
+        Ok(None) => explain_no_match(&db_client, oid, &claims, "delete").await,
+        Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
+    }
+}
+
+// Apply `set` to the user matching `filter` and append the action to its
+// history, returning the updated user
+async fn record_action(
+    db_client: &Client,
+    filter: Document,
+    mut set: Document,
+    action: &str,
+    claims: &Claims,
+    details: Option<String>,
+) -> mongodb::error::Result<Option<BrigatoryUser>> {
+    let now = chrono::Utc::now();
+    let entry = UserAction {
+        action: action.to_string(),
+        performed_by: claims.sub.clone(),
+        performed_at: now,
+        details,
+    };
+    set.insert("updated_at", bson::to_bson(&now)?);
+    let update = doc! { "$set": set, "$push": { "history": bson::to_bson(&entry)? } };
+
+    let options = mongodb::options::FindOneAndUpdateOptions::builder()
+        .return_document(mongodb::options::ReturnDocument::After)
+        .build();
+    users(db_client)
+        .find_one_and_update(filter, update, options)
+        .await
+}
+
+// Work out why an admin action matched no user: missing, self, or wrong status
+async fn explain_no_match(
+    db_client: &Client,
+    oid: ObjectId,
+    claims: &Claims,
+    action: &str,
+) -> HttpResponse {
+    let action = action.replace('_', " ");
+    match users(db_client).find_one(doc! { "_id": oid }, None).await {
+        Ok(None) => HttpResponse::NotFound().body("No user found with the provided ID"),
+        Ok(Some(user)) if user.email == claims.sub => {
+            HttpResponse::Conflict().body(format!("Cannot {} your own account", action))
+        }
+        Ok(Some(user)) => HttpResponse::Conflict().body(format!(
+            "Cannot {} a user whose status is '{}'",
+            action, user.status
+        )),
+        Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
+    }
+}
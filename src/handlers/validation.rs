@@ -0,0 +1,76 @@
+// src/handlers/validation.rs
+use actix_web::HttpResponse;
+use serde::Serialize;
+use serde_json::json;
+
+// A single problem with a request field, reported back to the client
+#[derive(Debug, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub value: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &str, value: &str, message: impl Into<String>) -> Self {
+        FieldError {
+            field: field.to_string(),
+            value: value.to_string(),
+            message: message.into(),
+        }
+    }
+}
+
+// 400 response listing every field that failed validation
+pub fn validation_error(errors: Vec<FieldError>) -> HttpResponse {
+    HttpResponse::BadRequest().json(json!({
+        "error": "Validation failed",
+        "details": errors,
+    }))
+}
+
+// Parse a ban duration such as "90s", "15m", "24h", "30d" or a bare number of seconds
+pub fn parse_duration(input: &str) -> Result<chrono::Duration, String> {
+    let input = input.trim();
+    let (number, unit) = match input.find(|c: char| !c.is_ascii_digit()) {
+        Some(idx) => input.split_at(idx),
+        None => (input, "s"),
+    };
+
+    let amount: i64 = number
+        .parse()
+        .map_err(|_| format!("'{}' is not a valid duration", input))?;
+    let seconds = match unit {
+        "s" => Some(amount),
+        "m" => amount.checked_mul(60),
+        "h" => amount.checked_mul(60 * 60),
+        "d" => amount.checked_mul(24 * 60 * 60),
+        _ => None,
+    };
+
+    match seconds
+        .filter(|s| *s > 0)
+        .and_then(chrono::Duration::try_seconds)
+    {
+        Some(duration) => Ok(duration),
+        None => Err(format!(
+            "'{}' is not a valid duration (use e.g. 90s, 15m, 24h or 30d)",
+            input
+        )),
+    }
+}
+
+// Turn an optional duration such as "15m" into an absolute expiry time
+pub fn expiry_from_duration(
+    duration: Option<&str>,
+    errors: &mut Vec<FieldError>,
+) -> Option<chrono::DateTime<chrono::Utc>> {
+    let duration = duration?;
+    match parse_duration(duration) {
+        Ok(duration) => Some(chrono::Utc::now() + duration),
+        Err(e) => {
+            errors.push(FieldError::new("duration", duration, e));
+            None
+        }
+    }
+}
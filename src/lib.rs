@@ -0,0 +1,14 @@
+// Brigatory: IP and URL blacklists, rate limiting, and the accounts that
+// manage them. `main.rs` runs the server; other Actix services can embed
+// `middleware::access_guard::AccessGuard` to enforce the same rules.
+pub mod auth;
+pub mod cache;
+pub mod db;
+pub mod handlers;
+pub mod middleware;
+pub mod models;
+pub mod net;
+pub mod notify;
+pub mod ratelimit;
+pub mod routes;
+pub mod rules;
@@ -1,16 +1,24 @@
-mod auth;
-mod db;
-mod handlers;
-mod middleware;
-mod models;
-mod routes;
-
 use actix_web::{web, App, HttpServer};
-use db::seed::seed_admin;
+use brigatory::auth::api_keys::ApiKeyCache;
+use brigatory::auth::password::PasswordPolicy;
+use brigatory::auth::KeyStore;
+use brigatory::cache::BlacklistCache;
+use brigatory::db::indexes::ensure_indexes;
+use brigatory::db::seed::seed_admin;
+use brigatory::notify::{LogNotifier, Notifier};
+use brigatory::routes;
 use dotenv::dotenv;
 use env_logger::Env;
 use mongodb::{options::ClientOptions, Client};
 use std::env;
+use std::io;
+use std::sync::Arc;
+
+// Report a startup failure and exit with a non-zero status
+fn startup_error(context: &str, e: impl std::fmt::Display) -> io::Error {
+    eprintln!("{}: {}", context, e);
+    io::Error::other(format!("{}: {}", context, e))
+}
 
 async fn connect_to_mongo() -> mongodb::error::Result<Client> {
     let db_uri = env::var("MONGODB_URI").expect("MONGODB_URI must be set in .env");
@@ -27,21 +35,55 @@ async fn main() -> std::io::Result<()> {
     let port = env::var("PORT").unwrap_or_else(|_| "8080".to_string());
     let bind_address = format!("0.0.0.0:{}", port);
 
+    // Token signing and verification keys
+    let keys = match KeyStore::from_env() {
+        Ok(keys) => web::Data::new(keys),
+        Err(e) => return Err(startup_error("Invalid JWT key configuration", e)),
+    };
+
+    // Rules for new passwords, including the breached password list
+    let password_policy = match PasswordPolicy::from_env() {
+        Ok(policy) => web::Data::new(policy),
+        Err(e) => return Err(startup_error("Invalid password policy", e)),
+    };
+
+    // Delivers password reset links; replace to send real email
+    let notifier: web::Data<dyn Notifier> =
+        web::Data::from(Arc::new(LogNotifier) as Arc<dyn Notifier>);
+
     let mongo_client = connect_to_mongo()
         .await
         .expect("Failed to connect to MongoDB");
 
     // Seed the admin user
-    if let Err(e) = seed_admin(web::Data::new(mongo_client.clone())).await {
-        eprintln!("Failed to seed admin user: {}", e);
-        return Ok(()); // Or return an error if seeding failure should stop the server
+    if let Err(e) = seed_admin(web::Data::new(mongo_client.clone()), &password_policy).await {
+        return Err(startup_error("Failed to seed admin user", e));
+    }
+
+    // Create indexes (including the TTL index that purges expired bans)
+    if let Err(e) = ensure_indexes(&mongo_client).await {
+        return Err(startup_error("Failed to create indexes", e));
     }
 
+    // Keep the in-memory blacklist cache in sync in the background
+    let blacklist_cache = web::Data::new(BlacklistCache::new());
+    let sync_cache = blacklist_cache.clone();
+    let sync_client = mongo_client.clone();
+    tokio::spawn(async move { sync_cache.run_sync(sync_client).await });
+
+    // Shared by all workers so that key changes invalidate one cache
+    let api_key_cache = web::Data::new(ApiKeyCache::new());
+
     println!("Brigatory running on http://{}", bind_address);
 
     HttpServer::new(move || {
         App::new()
             .app_data(web::Data::new(mongo_client.clone()))
+            .app_data(blacklist_cache.clone())
+            .app_data(keys.clone())
+            .app_data(api_key_cache.clone())
+            .app_data(password_policy.clone())
+            .app_data(notifier.clone())
             .configure(routes::configure_greet)
             .configure(routes::configure_routes)
     })
@@ -0,0 +1,561 @@
+// src/middleware/access_guard.rs
+// Enforces the blacklist and rate limits in front of any Actix app:
+//
+//     App::new().wrap(AccessGuard::remote("https://ratna.internal", &api_key)?.policy("api"))
+//
+// Requests from blacklisted IPs or to blacklisted paths get a 403, requests
+// over the rate limit a 429, each with a configurable JSON body.
+use crate::cache::BlacklistCache;
+use crate::handlers::check_rate_limit_handler::insert_rate_limit_headers;
+use crate::handlers::decide_handler::{self, evaluate, DecideError, DecideRequest, MatchedRule};
+use crate::models::rate_limit_policy::DEFAULT_POLICY;
+use crate::net::ip::parse_ip;
+use crate::ratelimit::Decision;
+use actix_service::{Service, Transform};
+use actix_web::body::{BoxBody, EitherBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header;
+use actix_web::{web, Error, HttpResponse};
+use async_trait::async_trait;
+use futures_util::future::{ok, LocalBoxFuture, Ready};
+use log::{error, info};
+use mongodb::Client;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+// What the backend decided for one request
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verdict {
+    Allow,
+    // Blacklisted; `reason` names the matching entry, for the log
+    Block { reason: String },
+    // The client should prove it is not a bot before being let through
+    Challenge { reason: String },
+    Throttle(Decision),
+}
+
+impl Verdict {
+    // Reduce a `/decide` answer to what the guard acts on
+    fn from_decision(
+        verdict: decide_handler::Verdict,
+        rules: &[MatchedRule],
+        rate_limit: Option<Decision>,
+    ) -> Self {
+        let reason = || {
+            rules
+                .iter()
+                .filter(|rule| rule.action == verdict)
+                .map(|rule| format!("{} matches {}", rule.source, rule.pattern))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        match (verdict, rate_limit) {
+            (decide_handler::Verdict::Allow, _) => Verdict::Allow,
+            (decide_handler::Verdict::Deny, _) => Verdict::Block { reason: reason() },
+            (decide_handler::Verdict::Challenge, _) => Verdict::Challenge { reason: reason() },
+            (decide_handler::Verdict::Throttle, Some(decision)) => Verdict::Throttle(decision),
+            // A throttle always comes with the rate limit state; refuse without it
+            (decide_handler::Verdict::Throttle, None) => Verdict::Block { reason: reason() },
+        }
+    }
+}
+
+// Where the guard gets its answers
+#[async_trait]
+pub trait GuardBackend: Send + Sync {
+    // Decide on `request`, counting it against its rate limit policy
+    async fn check(&self, request: &DecideRequest) -> Result<Verdict, String>;
+}
+
+// For services sharing Ratna's MongoDB: the in-memory blacklist cache and
+// the MongoDB rate limit counters, without a network hop to Ratna
+pub struct LocalBackend {
+    db_client: Client,
+    cache: web::Data<BlacklistCache>,
+}
+
+impl LocalBackend {
+    // `cache` must be kept in sync, e.g. by spawning `BlacklistCache::run_sync`
+    pub fn new(db_client: Client, cache: web::Data<BlacklistCache>) -> Self {
+        LocalBackend { db_client, cache }
+    }
+}
+
+#[async_trait]
+impl GuardBackend for LocalBackend {
+    async fn check(&self, request: &DecideRequest) -> Result<Verdict, String> {
+        match evaluate(&self.db_client, &self.cache, request).await {
+            Ok(decision) => Ok(Verdict::from_decision(
+                decision.verdict,
+                &decision.matched_rules,
+                decision.binding,
+            )),
+            Err(DecideError::Invalid(errors)) => Err(format!("Invalid request: {:?}", errors)),
+            Err(DecideError::UnknownPolicy) => Err(format!(
+                "No rate limit policy named '{}'",
+                request.policy.as_deref().unwrap_or(DEFAULT_POLICY)
+            )),
+            Err(DecideError::Database(e)) => Err(e.to_string()),
+        }
+    }
+}
+
+// For services elsewhere: asks a Ratna server through `/decide`,
+// authenticating with an API key that has the `check_only` scope
+pub struct RemoteBackend {
+    base_url: String,
+    api_key: String,
+    client: reqwest::Client,
+}
+
+// The fields of a `/decide` response the guard needs
+#[derive(Deserialize)]
+struct RemoteDecision {
+    verdict: decide_handler::Verdict,
+    #[serde(default)]
+    matched_rules: Vec<RemoteRule>,
+    rate_limit: Option<RemoteRateLimit>,
+}
+
+#[derive(Deserialize)]
+struct RemoteRule {
+    source: String,
+    pattern: String,
+    action: decide_handler::Verdict,
+}
+
+#[derive(Deserialize)]
+struct RemoteRateLimit {
+    allowed: bool,
+    limit: i64,
+    remaining: i64,
+    reset: i64,
+    retry_after: Option<i64>,
+}
+
+impl RemoteBackend {
+    // Fails when no HTTP client can be built, e.g. without a TLS backend
+    pub fn new(base_url: &str, api_key: &str) -> Result<Self, reqwest::Error> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(2))
+            .build()?;
+        Ok(RemoteBackend {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key: api_key.to_string(),
+            client,
+        })
+    }
+}
+
+#[async_trait]
+impl GuardBackend for RemoteBackend {
+    async fn check(&self, request: &DecideRequest) -> Result<Verdict, String> {
+        let response = self
+            .client
+            .post(format!("{}/decide", self.base_url))
+            .header("X-API-Key", &self.api_key)
+            .json(request)
+            .send()
+            .await
+            .map_err(|e| format!("/decide failed: {}", e))?;
+        if !response.status().is_success() {
+            return Err(format!("/decide returned {}", response.status()));
+        }
+        let remote: RemoteDecision = response
+            .json()
+            .await
+            .map_err(|e| format!("/decide returned an invalid body: {}", e))?;
+
+        let rules: Vec<MatchedRule> = remote
+            .matched_rules
+            .into_iter()
+            .map(|rule| MatchedRule {
+                source: rule.source,
+                id: None,
+                pattern: rule.pattern,
+                action: rule.action,
+                reason: None,
+            })
+            .collect();
+        let rate_limit = remote.rate_limit.map(|rate| Decision {
+            allowed: rate.allowed,
+            limit: rate.limit,
+            remaining: rate.remaining,
+            reset_after_ms: rate.reset * 1000,
+            retry_after_ms: rate.retry_after.map(|secs| secs * 1000),
+        });
+        Ok(Verdict::from_decision(remote.verdict, &rules, rate_limit))
+    }
+}
+
+#[derive(Clone)]
+struct GuardSettings {
+    backend: Arc<dyn GuardBackend>,
+    policy: String,
+    check_paths: bool,
+    trust_forwarded: bool,
+    fail_open: bool,
+    blocked_body: Value,
+    challenged_body: Value,
+    throttled_body: Value,
+}
+
+// Blocks blacklisted clients and paths and enforces a rate limit per client IP
+pub struct AccessGuard {
+    settings: GuardSettings,
+}
+
+impl AccessGuard {
+    pub fn new(backend: impl GuardBackend + 'static) -> Self {
+        AccessGuard {
+            settings: GuardSettings {
+                backend: Arc::new(backend),
+                policy: DEFAULT_POLICY.to_string(),
+                check_paths: true,
+                trust_forwarded: false,
+                fail_open: false,
+                blocked_body: json!({"error": "Forbidden", "message": "Access denied"}),
+                challenged_body: json!({"error": "Forbidden", "message": "Verification required"}),
+                throttled_body: json!({"error": "Too Many Requests", "message": "Rate limit exceeded"}),
+            },
+        }
+    }
+
+    pub fn local(db_client: Client, cache: web::Data<BlacklistCache>) -> Self {
+        Self::new(LocalBackend::new(db_client, cache))
+    }
+
+    pub fn remote(base_url: &str, api_key: &str) -> Result<Self, reqwest::Error> {
+        Ok(Self::new(RemoteBackend::new(base_url, api_key)?))
+    }
+
+    // Rate limit policy requests are counted against ("default" unless set)
+    pub fn policy(mut self, name: &str) -> Self {
+        self.settings.policy = name.to_string();
+        self
+    }
+
+    // Whether the host and path are checked against the URL blacklist (on by default)
+    pub fn check_paths(mut self, check_paths: bool) -> Self {
+        self.settings.check_paths = check_paths;
+        self
+    }
+
+    // Take the client IP from `Forwarded` / `X-Forwarded-For`. Only safe
+    // behind a proxy that overwrites these headers, since clients can set them.
+    pub fn trust_forwarded_headers(mut self) -> Self {
+        self.settings.trust_forwarded = true;
+        self
+    }
+
+    // Let requests through unchecked when the backend cannot be reached,
+    // instead of refusing them with 503. An outage then disables the
+    // blacklist and rate limits, so only opt in where availability matters more.
+    pub fn fail_open(mut self) -> Self {
+        self.settings.fail_open = true;
+        self
+    }
+
+    pub fn blocked_body(mut self, body: Value) -> Self {
+        self.settings.blocked_body = body;
+        self
+    }
+
+    // Sent with a 403 when Ratna asks for a challenge, so a front end can show one
+    pub fn challenged_body(mut self, body: Value) -> Self {
+        self.settings.challenged_body = body;
+        self
+    }
+
+    pub fn throttled_body(mut self, body: Value) -> Self {
+        self.settings.throttled_body = body;
+        self
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for AccessGuard
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B, BoxBody>>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = AccessGuardMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(AccessGuardMiddleware {
+            service: Rc::new(service),
+            settings: Rc::new(self.settings.clone()),
+        })
+    }
+}
+
+pub struct AccessGuardMiddleware<S> {
+    service: Rc<S>,
+    settings: Rc<GuardSettings>,
+}
+
+impl<S, B> Service<ServiceRequest> for AccessGuardMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B, BoxBody>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let settings = self.settings.clone();
+        Box::pin(async move {
+            let ip = match client_ip(&req, settings.trust_forwarded) {
+                Some(ip) => ip,
+                None => {
+                    // Nothing to check, e.g. a Unix socket connection
+                    let res = service.call(req).await?.map_into_left_body();
+                    return Ok(res);
+                }
+            };
+            let request = decide_request(&req, ip, &settings);
+
+            match settings.backend.check(&request).await {
+                Ok(Verdict::Allow) => {}
+                Ok(Verdict::Block { reason }) => {
+                    info!("Blocked {} {}: {}", ip, req.path(), reason);
+                    let response = HttpResponse::Forbidden().json(&settings.blocked_body);
+                    return Ok(req.into_response(response.map_into_right_body()));
+                }
+                Ok(Verdict::Challenge { reason }) => {
+                    info!("Challenged {} {}: {}", ip, req.path(), reason);
+                    let response = HttpResponse::Forbidden().json(&settings.challenged_body);
+                    return Ok(req.into_response(response.map_into_right_body()));
+                }
+                Ok(Verdict::Throttle(decision)) => {
+                    let mut response = HttpResponse::TooManyRequests();
+                    insert_rate_limit_headers(&mut response, &decision);
+                    let response = response.json(&settings.throttled_body);
+                    return Ok(req.into_response(response.map_into_right_body()));
+                }
+                Err(e) if settings.fail_open => {
+                    error!("Access check failed, letting {} through: {}", ip, e);
+                }
+                Err(e) => {
+                    error!("Access check failed, refusing {}: {}", ip, e);
+                    let response = HttpResponse::ServiceUnavailable().finish();
+                    return Ok(req.into_response(response.map_into_right_body()));
+                }
+            }
+
+            let res = service.call(req).await?.map_into_left_body();
+            Ok(res)
+        })
+    }
+}
+
+// Describe `req` for the backend. Only the user agent is passed on from the
+// headers; credentials and cookies never leave the service.
+fn decide_request(req: &ServiceRequest, ip: IpAddr, settings: &GuardSettings) -> DecideRequest {
+    let mut headers = HashMap::new();
+    if let Some(agent) = req
+        .headers()
+        .get(header::USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+    {
+        headers.insert("user-agent".to_string(), agent.to_string());
+    }
+    DecideRequest {
+        ip_address: ip.to_string(),
+        method: Some(req.method().to_string()),
+        path: settings.check_paths.then(|| req.path().to_string()),
+        host: settings
+            .check_paths
+            .then(|| req.connection_info().host().to_string()),
+        scheme: settings
+            .check_paths
+            .then(|| req.connection_info().scheme().to_string()),
+        headers,
+        country: None,
+        user: None,
+        policy: Some(settings.policy.clone()),
+    }
+}
+
+// The peer address, or with `trust_forwarded` the address the proxy reported
+fn client_ip(req: &ServiceRequest, trust_forwarded: bool) -> Option<IpAddr> {
+    if trust_forwarded {
+        let forwarded = req
+            .connection_info()
+            .realip_remote_addr()
+            .map(str::to_string);
+        if let Some(addr) = forwarded {
+            if let Ok(socket) = addr.parse::<SocketAddr>() {
+                return Some(socket.ip());
+            }
+            if let Ok(ip) = parse_ip(&addr) {
+                return Some(ip);
+            }
+        }
+    }
+    req.peer_addr().map(|addr| addr.ip())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, App};
+
+    // Blocks one IP and throttles another; errors for everything else
+    struct StubBackend;
+
+    #[async_trait]
+    impl GuardBackend for StubBackend {
+        async fn check(&self, request: &DecideRequest) -> Result<Verdict, String> {
+            let path = request.path.as_deref();
+            match request.ip_address.as_str() {
+                "203.0.113.1" => Ok(Verdict::Block {
+                    reason: "stub".to_string(),
+                }),
+                "203.0.113.2" => Ok(Verdict::Throttle(Decision {
+                    allowed: false,
+                    limit: 10,
+                    remaining: 0,
+                    reset_after_ms: 30_000,
+                    retry_after_ms: Some(1_500),
+                })),
+                "203.0.113.3" => Ok(Verdict::Challenge {
+                    reason: "stub".to_string(),
+                }),
+                _ if path == Some("/admin") => Ok(Verdict::Block {
+                    reason: "stub".to_string(),
+                }),
+                "198.51.100.1" => Ok(Verdict::Allow),
+                _ => Err("backend unavailable".to_string()),
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn decide_answers_map_to_guard_verdicts() {
+        let rule = MatchedRule {
+            source: "ip_blacklist".to_string(),
+            id: None,
+            pattern: "203.0.113.0/24".to_string(),
+            action: decide_handler::Verdict::Deny,
+            reason: None,
+        };
+        assert_eq!(
+            Verdict::from_decision(decide_handler::Verdict::Deny, &[rule], None),
+            Verdict::Block {
+                reason: "ip_blacklist matches 203.0.113.0/24".to_string()
+            }
+        );
+        assert_eq!(
+            Verdict::from_decision(decide_handler::Verdict::Allow, &[], None),
+            Verdict::Allow
+        );
+    }
+
+    fn request(ip: &str, path: &str) -> test::TestRequest {
+        test::TestRequest::get()
+            .uri(path)
+            .peer_addr(format!("{}:4000", ip).parse().unwrap())
+    }
+
+    #[actix_web::test]
+    async fn blocks_throttles_and_passes_requests() {
+        let app = test::init_service(
+            App::new()
+                .wrap(AccessGuard::new(StubBackend).blocked_body(json!({"error": "go away"})))
+                .route("/", web::get().to(HttpResponse::Ok))
+                .route("/admin", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let res = test::call_service(&app, request("198.51.100.1", "/").to_request()).await;
+        assert_eq!(res.status(), 200);
+
+        let res = test::call_service(&app, request("203.0.113.1", "/").to_request()).await;
+        assert_eq!(res.status(), 403);
+        let body: Value = test::read_body_json(res).await;
+        assert_eq!(body, json!({"error": "go away"}));
+
+        let res = test::call_service(&app, request("198.51.100.1", "/admin").to_request()).await;
+        assert_eq!(res.status(), 403);
+
+        let res = test::call_service(&app, request("203.0.113.3", "/").to_request()).await;
+        assert_eq!(res.status(), 403);
+        let body: Value = test::read_body_json(res).await;
+        assert_eq!(body["message"], "Verification required");
+
+        let res = test::call_service(&app, request("203.0.113.2", "/").to_request()).await;
+        assert_eq!(res.status(), 429);
+        assert_eq!(res.headers().get("Retry-After").unwrap(), "2");
+        assert_eq!(res.headers().get("RateLimit-Limit").unwrap(), "10");
+    }
+
+    #[actix_web::test]
+    async fn backend_errors_fail_closed_unless_configured_otherwise() {
+        let closed = test::init_service(
+            App::new()
+                .wrap(AccessGuard::new(StubBackend))
+                .route("/", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        let res = test::call_service(&closed, request("192.0.2.9", "/").to_request()).await;
+        assert_eq!(res.status(), 503);
+
+        let open = test::init_service(
+            App::new()
+                .wrap(AccessGuard::new(StubBackend).fail_open())
+                .route("/", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        let res = test::call_service(&open, request("192.0.2.9", "/").to_request()).await;
+        assert_eq!(res.status(), 200);
+    }
+
+    #[actix_web::test]
+    async fn remote_backends_trim_the_base_url() {
+        let backend = RemoteBackend::new("https://ratna.internal/", "key").unwrap();
+        assert_eq!(backend.base_url, "https://ratna.internal");
+    }
+
+    #[actix_web::test]
+    async fn forwarded_headers_are_only_used_when_trusted() {
+        let app = test::init_service(
+            App::new()
+                .wrap(AccessGuard::new(StubBackend).trust_forwarded_headers())
+                .route("/", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        let req = request("198.51.100.1", "/")
+            .insert_header(("X-Forwarded-For", "203.0.113.1"))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 403);
+
+        let untrusted = test::init_service(
+            App::new()
+                .wrap(AccessGuard::new(StubBackend))
+                .route("/", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        let req = request("198.51.100.1", "/")
+            .insert_header(("X-Forwarded-For", "203.0.113.1"))
+            .to_request();
+        assert_eq!(test::call_service(&untrusted, req).await.status(), 200);
+    }
+}
@@ -1,20 +1,43 @@
-use crate::auth::Claims;
+use crate::auth::api_keys::{authenticate, key_claims, ApiKeyCache};
+use crate::auth::roles::ApiKeyScope;
+use crate::auth::sessions::is_revoked;
+use crate::auth::{KeyStore, Role};
 use actix_service::{Service, Transform};
 use actix_web::body::{BoxBody, EitherBody};
 use actix_web::dev::{ServiceRequest, ServiceResponse};
-use actix_web::{Error, HttpResponse};
+use actix_web::{web, Error, HttpMessage, HttpResponse};
 use futures_util::future::{ok, LocalBoxFuture, Ready};
-use jsonwebtoken::{decode, DecodingKey, Validation};
 use log::error;
+use mongodb::Client;
 use serde_json::json;
 use std::rc::Rc;
 use std::task::{Context, Poll}; // Import the Claims struct from auth module
 
-pub struct JwtAuth;
+// Requires a valid bearer token whose role is one of `roles`, or an API key
+// with one of the scopes given to `with_api_keys`
+pub struct JwtAuth {
+    roles: Rc<Vec<Role>>,
+    scopes: Rc<Vec<ApiKeyScope>>,
+}
+
+impl JwtAuth {
+    pub fn allow(roles: &[Role]) -> Self {
+        JwtAuth {
+            roles: Rc::new(roles.to_vec()),
+            scopes: Rc::new(Vec::new()),
+        }
+    }
+
+    // Also accept API keys, sent as `X-API-Key: <key>` or `Authorization: ApiKey <key>`
+    pub fn with_api_keys(mut self, scopes: &[ApiKeyScope]) -> Self {
+        self.scopes = Rc::new(scopes.to_vec());
+        self
+    }
+}
 
 impl<S, B> Transform<S, ServiceRequest> for JwtAuth
 where
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
     B: 'static,
 {
@@ -27,17 +50,97 @@ where
     fn new_transform(&self, service: S) -> Self::Future {
         ok(JwtAuthMiddleware {
             service: Rc::new(service),
+            roles: self.roles.clone(),
+            scopes: self.scopes.clone(),
         })
     }
 }
 
 pub struct JwtAuthMiddleware<S> {
     service: Rc<S>,
+    roles: Rc<Vec<Role>>,
+    scopes: Rc<Vec<ApiKeyScope>>,
+}
+
+impl<S, B> JwtAuthMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    fn call_with_api_key(
+        &self,
+        req: ServiceRequest,
+        presented: String,
+    ) -> LocalBoxFuture<'static, Result<ServiceResponse<EitherBody<B, BoxBody>>, Error>> {
+        let service = self.service.clone();
+        let scopes = self.scopes.clone();
+        Box::pin(async move {
+            let db_client = match req.app_data::<web::Data<Client>>() {
+                Some(db_client) => db_client.clone(),
+                None => {
+                    error!("API key used without a MongoDB client in app data");
+                    return reject(req, HttpResponse::InternalServerError().finish());
+                }
+            };
+            let cache = req.app_data::<web::Data<ApiKeyCache>>().cloned();
+
+            let key = match authenticate(
+                &db_client,
+                cache.as_ref().map(|cache| cache.get_ref()),
+                &presented,
+            )
+            .await
+            {
+                Ok(Some(key)) => key,
+                Ok(None) => {
+                    return reject(
+                        req,
+                        HttpResponse::Unauthorized()
+                            .json(json!({"error": "Unauthorized", "message": "Invalid API key"})),
+                    )
+                }
+                Err(e) => {
+                    error!("API key lookup failed: {}", e);
+                    return reject(req, HttpResponse::InternalServerError().finish());
+                }
+            };
+            if !key.scopes.iter().any(|scope| scopes.contains(scope)) {
+                return reject(
+                    req,
+                    HttpResponse::Forbidden()
+                        .json(json!({"error": "Forbidden", "message": "Insufficient scope"})),
+                );
+            }
+
+            req.extensions_mut().insert(key_claims(&key));
+            let res = service.call(req).await?.map_into_left_body();
+            Ok(res)
+        })
+    }
+}
+
+// The API key sent with a request, if any
+fn api_key_from(req: &ServiceRequest) -> Option<String> {
+    if let Some(key) = req.headers().get("X-API-Key") {
+        return key.to_str().ok().map(|key| key.trim().to_string());
+    }
+    let auth_str = req.headers().get("Authorization")?.to_str().ok()?;
+    auth_str
+        .strip_prefix("ApiKey ")
+        .map(|key| key.trim().to_string())
+}
+
+fn reject<B>(
+    req: ServiceRequest,
+    response: HttpResponse,
+) -> Result<ServiceResponse<EitherBody<B, BoxBody>>, Error> {
+    Ok(req.into_response(response.map_into_right_body()))
 }
 
 impl<S, B> Service<ServiceRequest> for JwtAuthMiddleware<S>
 where
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
     B: 'static,
 {
@@ -50,21 +153,72 @@ where
     }
 
     fn call(&self, req: ServiceRequest) -> Self::Future {
+        if let Some(presented) = api_key_from(&req) {
+            return self.call_with_api_key(req, presented);
+        }
+
         if let Some(auth_header) = req.headers().get("Authorization") {
             if let Ok(auth_str) = auth_header.to_str() {
                 if auth_str.starts_with("Bearer ") {
                     let token = auth_str.trim_start_matches("Bearer ").trim();
 
-                    let validation = Validation::default();
-                    match decode::<Claims>(
-                        token,
-                        &DecodingKey::from_secret("your_secret_key".as_ref()),
-                        &validation,
-                    ) {
-                        Ok(_token_data) => {
-                            let fut = self.service.call(req);
+                    let keys = match req.app_data::<web::Data<KeyStore>>() {
+                        Some(keys) => keys,
+                        None => {
+                            error!("JwtAuth used without a KeyStore in app data");
+                            return Box::pin(async move {
+                                let response = HttpResponse::InternalServerError()
+                                    .finish()
+                                    .map_into_right_body();
+                                Ok(req.into_response(response))
+                            });
+                        }
+                    };
+                    match keys.verify(token) {
+                        Ok(claims) if !self.roles.contains(&claims.role) => {
+                            return Box::pin(async move {
+                                let response = HttpResponse::Forbidden()
+                                    .json(json!({"error": "Forbidden", "message": "Insufficient role"}))
+                                    .map_into_right_body();
+                                Ok(req.into_response(response))
+                            });
+                        }
+                        Ok(claims) => {
+                            let service = self.service.clone();
                             return Box::pin(async move {
-                                let res = fut.await?.map_into_left_body();
+                                // Signed out or revoked sessions must stop working
+                                // immediately, so a token is refused when the
+                                // revocation list cannot be checked
+                                let db_client = match req.app_data::<web::Data<Client>>() {
+                                    Some(db_client) => db_client.clone(),
+                                    None => {
+                                        error!("JwtAuth used without a MongoDB client in app data");
+                                        return reject(
+                                            req,
+                                            HttpResponse::InternalServerError().finish(),
+                                        );
+                                    }
+                                };
+                                match is_revoked(&db_client, &claims.jti).await {
+                                    Ok(false) => {}
+                                    Ok(true) => {
+                                        let response = HttpResponse::Unauthorized()
+                                            .json(json!({"error": "Unauthorized", "message": "Token revoked"}))
+                                            .map_into_right_body();
+                                        return Ok(req.into_response(response));
+                                    }
+                                    Err(e) => {
+                                        error!("Token revocation check failed: {}", e);
+                                        let response = HttpResponse::InternalServerError()
+                                            .finish()
+                                            .map_into_right_body();
+                                        return Ok(req.into_response(response));
+                                    }
+                                }
+
+                                // Make the claims available to handlers via `web::ReqData<Claims>`
+                                req.extensions_mut().insert(claims);
+                                let res = service.call(req).await?.map_into_left_body();
                                 Ok(res)
                             });
                         }
@@ -90,3 +244,37 @@ where
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Claims;
+    use actix_web::{test, App};
+
+    #[actix_web::test]
+    async fn tokens_are_refused_when_revocation_cannot_be_checked() {
+        let keys = KeyStore::with_secret("test", b"secret");
+        let token = keys
+            .sign(&Claims {
+                sub: "ops@example.com".to_string(),
+                exp: (chrono::Utc::now() + chrono::Duration::minutes(5)).timestamp() as usize,
+                role: Role::Admin,
+                jti: "jti".to_string(),
+                sid: "sid".to_string(),
+            })
+            .unwrap();
+        // No MongoDB client registered
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(keys))
+                .wrap(JwtAuth::allow(&[Role::Admin]))
+                .route("/", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        let req = test::TestRequest::get()
+            .uri("/")
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 500);
+    }
+}
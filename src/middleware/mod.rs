@@ -1,2 +1,3 @@
 // src/middleware/mod.rs
+pub mod access_guard;
 pub mod jwt_auth;
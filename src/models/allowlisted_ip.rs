@@ -0,0 +1,49 @@
+// src/models/allowlisted_ip.rs
+use crate::models::blacklisted_ip::serialize_expiry;
+use crate::models::object_id::serialize_objectid_as_string;
+use crate::models::EntryMetadata;
+use crate::net::IpRange;
+use bson::oid::ObjectId;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+// An address or range that is never blacklisted or rate limited, such as
+// monitoring probes, office ranges or partner IPs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AllowlistedIp {
+    #[serde(
+        rename = "_id",
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_objectid_as_string"
+    )]
+    pub _id: Option<ObjectId>,
+    pub ip_address: String, // Canonical address or CIDR, e.g. "198.51.100.0/24"
+    pub prefix_len: u8,
+    // Range bounds encoded with `net::cidr::ip_key`, used for containment lookups
+    pub range_start: String,
+    pub range_end: String,
+    // Temporary entries expire at this time; `None` means the entry is permanent
+    #[serde(default, serialize_with = "serialize_expiry")]
+    pub expires_at: Option<bson::DateTime>,
+    #[serde(flatten)]
+    pub metadata: EntryMetadata,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl AllowlistedIp {
+    pub fn new(range: IpRange, expires_at: Option<DateTime<Utc>>, metadata: EntryMetadata) -> Self {
+        let now = Utc::now();
+        AllowlistedIp {
+            _id: None,
+            ip_address: range.to_string(),
+            prefix_len: range.prefix_len(),
+            range_start: range.start_key(),
+            range_end: range.end_key(),
+            expires_at: expires_at.map(|dt| bson::DateTime::from_millis(dt.timestamp_millis())),
+            metadata,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+}
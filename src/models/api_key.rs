@@ -0,0 +1,33 @@
+use crate::auth::roles::ApiKeyScope;
+use crate::models::blacklisted_ip::serialize_expiry;
+use bson::oid::ObjectId;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+// A service credential. The key is shown once when it is created; only its
+// prefix, which identifies it in lists and logs, and its hash are stored.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKey {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub _id: Option<ObjectId>,
+    pub name: String,
+    pub prefix: String,
+    pub key_hash: String, // SHA-256 of the full key
+    pub scopes: Vec<ApiKeyScope>,
+    // `None` means the key does not expire
+    #[serde(default, serialize_with = "serialize_expiry")]
+    pub expires_at: Option<bson::DateTime>,
+    // Updated at most once a minute per server, see `auth::api_keys`
+    #[serde(default, serialize_with = "serialize_expiry")]
+    pub last_used_at: Option<bson::DateTime>,
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl ApiKey {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at <= bson::DateTime::now())
+    }
+}
@@ -1,44 +1,92 @@
+use crate::models::object_id::serialize_objectid_as_string;
+use crate::models::EntryMetadata;
+use crate::net::IpRange;
 use bson::oid::ObjectId;
 use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize, Serializer}; // Import Serializer
+use serde::{Deserialize, Serialize, Serializer};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BlacklistedIp {
     #[serde(
         rename = "_id",
         skip_serializing_if = "Option::is_none",
         serialize_with = "serialize_objectid_as_string"
     )]
-    pub _id: Option<ObjectId>, // Use custom serialization
-    pub ip_address: String,
+    pub _id: Option<ObjectId>,
+    pub ip_address: String, // Canonical address or CIDR, e.g. "203.0.113.0/24"
+    #[serde(default)]
+    pub prefix_len: u8,
+    // Range bounds encoded with `net::cidr::ip_key`, used for containment lookups
+    #[serde(default)]
+    pub range_start: String,
+    #[serde(default)]
+    pub range_end: String,
     pub status: String,
+    // Temporary bans expire at this time; `None` means the ban is permanent
+    #[serde(default, serialize_with = "serialize_expiry")]
+    pub expires_at: Option<bson::DateTime>,
+    // Seconds left on a temporary ban, filled in for responses only
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub remaining_seconds: Option<i64>,
+    #[serde(flatten)]
+    pub metadata: EntryMetadata,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
 impl BlacklistedIp {
-    pub fn new(ip_address: String) -> Self {
+    pub fn new(range: IpRange, expires_at: Option<DateTime<Utc>>, metadata: EntryMetadata) -> Self {
         let now = Utc::now();
         BlacklistedIp {
             _id: None,
-            ip_address,
+            ip_address: range.to_string(),
+            prefix_len: range.prefix_len(),
+            range_start: range.start_key(),
+            range_end: range.end_key(),
             status: "blocked".to_string(),
+            expires_at: expires_at.map(|dt| bson::DateTime::from_millis(dt.timestamp_millis())),
+            remaining_seconds: None,
+            metadata,
             created_at: now,
             updated_at: now,
         }
     }
+
+    // Fill in `remaining_seconds` before the entry is returned to a client
+    pub fn with_remaining(mut self) -> Self {
+        self.remaining_seconds = self.expires_at.map(|expires_at| {
+            let remaining =
+                expires_at.timestamp_millis() - bson::DateTime::now().timestamp_millis();
+            (remaining / 1000).max(0)
+        });
+        self
+    }
+}
+
+// Filter matching entries that have no expiry or have not expired yet
+pub fn not_expired_filter() -> bson::Document {
+    bson::doc! {
+        "$or": [
+            { "expires_at": null },
+            { "expires_at": { "$gt": bson::DateTime::now() } },
+        ]
+    }
 }
 
-// Custom serialization function for ObjectId
-fn serialize_objectid_as_string<S>(
-    value: &Option<ObjectId>,
+// Store expiry as a BSON date in MongoDB but render it as RFC 3339 in JSON responses
+pub(crate) fn serialize_expiry<S>(
+    value: &Option<bson::DateTime>,
     serializer: S,
 ) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
 {
     match value {
-        Some(oid) => serializer.serialize_str(&oid.to_hex()),
+        Some(dt) if serializer.is_human_readable() => match dt.try_to_rfc3339_string() {
+            Ok(formatted) => serializer.serialize_str(&formatted),
+            Err(e) => Err(serde::ser::Error::custom(e)),
+        },
+        Some(dt) => dt.serialize(serializer),
         None => serializer.serialize_none(),
     }
 }
@@ -1,6 +1,8 @@
+use crate::auth::Role;
+use crate::models::object_id::serialize_objectid_as_string;
 use bson::oid::ObjectId;
 use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize, Serializer}; // Import Serializer
+use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct BrigatoryUser {
@@ -9,15 +11,91 @@ pub struct BrigatoryUser {
         skip_serializing_if = "Option::is_none",
         serialize_with = "serialize_objectid_as_string"
     )]
-    pub _id: Option<ObjectId>, // Use custom serialization
+    pub _id: Option<ObjectId>,
     pub full_name: String,
     pub email: String,
     pub password: String,
+    // Algorithm and parameters of `password`; missing for hashes created
+    // before this was recorded, which were all bcrypt
+    #[serde(default)]
+    pub password_scheme: Option<PasswordScheme>,
+    // Hashes of earlier passwords, most recent last, so they cannot be reused
+    #[serde(default)]
+    pub password_history: Vec<String>,
+    #[serde(default)]
+    pub password_changed_at: Option<DateTime<Utc>>,
     pub status: String,
+    #[serde(default)]
+    pub role: Role,
+    // Admin actions taken on this account, oldest first
+    #[serde(default)]
+    pub history: Vec<UserAction>,
+    #[serde(default)]
+    pub mfa: MfaSettings,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
+// Second factor settings of a user
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MfaSettings {
+    #[serde(default)]
+    pub enabled: bool,
+    pub totp_secret: Option<String>, // Base32 secret of the active authenticator
+    // Secret handed out by enrollment, until a code generated from it is confirmed
+    pub pending_secret: Option<String>,
+    // SHA-256 hashes of the recovery codes that have not been used yet
+    #[serde(default)]
+    pub recovery_codes: Vec<String>,
+    // Last TOTP time step accepted; older codes are rejected as replays
+    #[serde(default)]
+    pub last_used_step: i64,
+    pub enabled_at: Option<DateTime<Utc>>,
+}
+
+// How a password hash was computed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "algorithm", rename_all = "snake_case")]
+pub enum PasswordScheme {
+    Argon2id {
+        memory_kib: u32,
+        iterations: u32,
+        parallelism: u32,
+    },
+    Bcrypt {
+        cost: u32,
+    },
+}
+
+impl PasswordScheme {
+    // Read the scheme from a PHC (`$argon2id$...`) or bcrypt (`$2b$...`) hash
+    pub fn of_hash(hash: &str) -> Option<Self> {
+        if hash.starts_with("$argon2id$") {
+            let parsed = argon2::PasswordHash::new(hash).ok()?;
+            let params = argon2::Params::try_from(&parsed).ok()?;
+            Some(PasswordScheme::Argon2id {
+                memory_kib: params.m_cost(),
+                iterations: params.t_cost(),
+                parallelism: params.p_cost(),
+            })
+        } else if hash.starts_with("$2") {
+            let cost = hash.split('$').nth(2)?.parse().ok()?;
+            Some(PasswordScheme::Bcrypt { cost })
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserAction {
+    pub action: String,       // e.g. "approve", "suspend", "change_role"
+    pub performed_by: String, // Email of the admin who took the action
+    pub performed_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub details: Option<String>,
+}
+
 impl BrigatoryUser {
     pub fn new(full_name: String, email: String, password: String) -> Self {
         let now = Utc::now();
@@ -25,24 +103,21 @@ impl BrigatoryUser {
             _id: None,
             full_name,
             email,
+            password_scheme: PasswordScheme::of_hash(&password),
             password,
+            password_history: Vec::new(),
+            password_changed_at: Some(now),
             status: "pending".to_string(),
+            role: Role::default(),
+            history: Vec::new(),
+            mfa: MfaSettings::default(),
             created_at: now,
             updated_at: now,
         }
     }
-}
 
-// Custom serialization function for ObjectId
-fn serialize_objectid_as_string<S>(
-    value: &Option<ObjectId>,
-    serializer: S,
-) -> Result<S::Ok, S::Error>
-where
-    S: Serializer,
-{
-    match value {
-        Some(oid) => serializer.serialize_str(&oid.to_hex()),
-        None => serializer.serialize_none(),
+    // Only approved accounts may sign in or refresh their tokens
+    pub fn can_sign_in(&self) -> bool {
+        self.status == "approved"
     }
 }
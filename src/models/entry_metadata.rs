@@ -0,0 +1,63 @@
+// src/models/entry_metadata.rs
+use serde::{Deserialize, Serialize};
+
+// Provenance shared by blacklist and malicious URL entries, so an entry can
+// still be explained long after it was created.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EntryMetadata {
+    #[serde(default)]
+    pub reason: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    // "manual", the name of a feed, or "auto-escalation"
+    #[serde(default = "default_source")]
+    pub source: String,
+    // Subject (`Claims.sub`) of the user who created the entry
+    #[serde(default)]
+    pub created_by: Option<String>,
+}
+
+impl EntryMetadata {
+    pub fn new(
+        reason: Option<String>,
+        tags: Vec<String>,
+        source: Option<String>,
+        created_by: Option<String>,
+    ) -> Self {
+        EntryMetadata {
+            reason: reason.filter(|r| !r.trim().is_empty()),
+            tags: normalize_tags(tags),
+            source: source
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .unwrap_or_else(default_source),
+            created_by,
+        }
+    }
+}
+
+// Trim, lowercase and de-duplicate tags so filtering is predictable
+pub fn normalize_tags(tags: Vec<String>) -> Vec<String> {
+    let mut normalized: Vec<String> = Vec::new();
+    for tag in tags {
+        let tag = tag.trim().to_lowercase();
+        if !tag.is_empty() && !normalized.contains(&tag) {
+            normalized.push(tag);
+        }
+    }
+    normalized
+}
+
+fn default_source() -> String {
+    "manual".to_string()
+}
+
+// Narrow a list query to entries carrying `tag` and/or created by `source`
+pub fn apply_metadata_filter(filter: &mut bson::Document, tag: Option<&str>, source: Option<&str>) {
+    if let Some(tag) = tag {
+        filter.insert("tags", tag.trim().to_lowercase());
+    }
+    if let Some(source) = source {
+        filter.insert("source", source.trim());
+    }
+}
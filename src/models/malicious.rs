@@ -1,44 +1,58 @@
+use crate::models::object_id::serialize_objectid_as_string;
+use crate::models::EntryMetadata;
+use crate::net::{UrlMatchType, UrlPattern};
 use bson::oid::ObjectId;
 use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize, Serializer}; // Import Serializer
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MaliciousUrl {
     #[serde(
         rename = "_id",
         skip_serializing_if = "Option::is_none",
         serialize_with = "serialize_objectid_as_string"
     )]
-    pub _id: Option<ObjectId>, // Use custom serialization
-    pub url: String,
+    pub _id: Option<ObjectId>,
+    pub url: String, // The pattern, validated and normalized for `match_type`
+    #[serde(default)]
+    pub match_type: UrlMatchType,
+    // The URL as it was submitted, for display
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub original_url: Option<String>,
+    // Match regardless of the checked URL's query string
+    #[serde(default)]
+    pub ignore_query: bool,
     pub status: String,
+    #[serde(flatten)]
+    pub metadata: EntryMetadata,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
 impl MaliciousUrl {
-    pub fn new(url: String) -> Self {
+    pub fn new(
+        pattern: &UrlPattern,
+        original_url: String,
+        match_type: UrlMatchType,
+        ignore_query: bool,
+        metadata: EntryMetadata,
+    ) -> Self {
         let now = Utc::now();
         MaliciousUrl {
             _id: None,
-            url,
+            url: pattern.pattern().to_string(),
+            match_type,
+            original_url: Some(original_url),
+            ignore_query,
             status: "blocked".to_string(),
+            metadata,
             created_at: now,
             updated_at: now,
         }
     }
-}
 
-// Custom serialization function for ObjectId
-fn serialize_objectid_as_string<S>(
-    value: &Option<ObjectId>,
-    serializer: S,
-) -> Result<S::Ok, S::Error>
-where
-    S: Serializer,
-{
-    match value {
-        Some(oid) => serializer.serialize_str(&oid.to_hex()),
-        None => serializer.serialize_none(),
+    // The entry's pattern, compiled for matching
+    pub fn pattern(&self) -> Result<UrlPattern, String> {
+        UrlPattern::new(self.match_type, &self.url, self.ignore_query)
     }
 }
@@ -0,0 +1,29 @@
+use crate::auth::Role;
+use bson::oid::ObjectId;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+// Which roles must use a second factor; stored as the "mfa_policy" settings document
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MfaPolicy {
+    #[serde(default)]
+    pub required_roles: Vec<Role>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_by: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+// A signin that passed the password check and now needs the second factor,
+// or needs to enroll one first
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MfaChallenge {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub _id: Option<ObjectId>,
+    pub token_hash: String, // SHA-256 of the `mfa_token` handed to the client
+    pub user_id: ObjectId,
+    pub enrollment: bool, // The user has no second factor yet and must set one up
+    #[serde(default)]
+    pub attempts: i32,
+    pub expires_at: bson::DateTime, // TTL index removes the document after this
+}
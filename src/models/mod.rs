@@ -1,11 +1,40 @@
 pub mod blacklisted_ip;
 pub use blacklisted_ip::BlacklistedIp;
 
+pub mod allowlisted_ip;
+pub use allowlisted_ip::AllowlistedIp;
+
 pub mod malicious;
 pub use malicious::MaliciousUrl;
 
 pub mod brigatory_users;
 pub use brigatory_users::BrigatoryUser;
 
+pub mod entry_metadata;
+pub use entry_metadata::EntryMetadata;
+
 pub mod rate_limit;
 pub use rate_limit::RateLimitEntry; // Add this line to include the rate limit model
+
+pub mod signin_lockout;
+pub use signin_lockout::SigninLockout;
+
+pub mod mfa;
+pub use mfa::{MfaChallenge, MfaPolicy};
+
+pub mod api_key;
+pub use api_key::ApiKey;
+
+pub mod password_reset;
+pub use password_reset::PasswordReset;
+
+pub mod session_token;
+pub use session_token::{RefreshToken, RevokedToken};
+
+pub mod policy_rule;
+pub use policy_rule::PolicyRule;
+
+pub mod rate_limit_policy;
+pub use rate_limit_policy::RateLimitPolicy;
+
+pub mod object_id;
@@ -0,0 +1,20 @@
+// src/models/object_id.rs
+use bson::oid::ObjectId;
+use serde::Serializer;
+
+// Render an `_id` as its hex string in JSON responses:
+//
+//     #[serde(rename = "_id", skip_serializing_if = "Option::is_none",
+//             serialize_with = "serialize_objectid_as_string")]
+pub(crate) fn serialize_objectid_as_string<S>(
+    value: &Option<ObjectId>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match value {
+        Some(oid) => serializer.serialize_str(&oid.to_hex()),
+        None => serializer.serialize_none(),
+    }
+}
@@ -0,0 +1,16 @@
+use bson::oid::ObjectId;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+// An outstanding password reset. The token is sent to the user and works
+// once; a newer reset for the same user replaces it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PasswordReset {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub _id: Option<ObjectId>,
+    pub token_hash: String, // SHA-256 of the token; the token itself is never stored
+    pub user_id: ObjectId,
+    pub requested_by: String, // The user's own email, or the admin who started the reset
+    pub expires_at: bson::DateTime, // TTL index removes the document after this
+    pub created_at: DateTime<Utc>,
+}
@@ -0,0 +1,59 @@
+// src/models/policy_rule.rs
+use crate::models::object_id::serialize_objectid_as_string;
+use crate::rules::{RuleAction, RuleConditions};
+use bson::oid::ObjectId;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+// A stored rule, evaluated by `rules::evaluate` on every `/decide` call
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PolicyRule {
+    #[serde(
+        rename = "_id",
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_objectid_as_string"
+    )]
+    pub _id: Option<ObjectId>,
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    pub priority: i32, // Higher priorities are evaluated first
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    pub conditions: RuleConditions,
+    pub action: RuleAction,
+    // Subject (`Claims.sub`) of the user who created the rule
+    #[serde(default)]
+    pub created_by: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl PolicyRule {
+    pub fn new(
+        name: String,
+        description: Option<String>,
+        priority: i32,
+        action: RuleAction,
+        conditions: RuleConditions,
+        created_by: Option<String>,
+    ) -> Self {
+        let now = Utc::now();
+        PolicyRule {
+            _id: None,
+            name,
+            description,
+            priority,
+            enabled: true,
+            conditions,
+            action,
+            created_by,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+}
+
+fn default_enabled() -> bool {
+    true
+}
@@ -1,4 +1,5 @@
 // src/models/rate_limit.rs
+use crate::ratelimit::LimiterState;
 use mongodb::bson::DateTime;
 use serde::{Deserialize, Serialize};
 
@@ -6,7 +7,19 @@ use serde::{Deserialize, Serialize};
 pub struct RateLimitEntry {
     #[serde(rename = "_id")]
     pub id: Option<bson::oid::ObjectId>,
-    pub ip: String,
+    pub key: String, // Canonical counter key, e.g. "ip=203.0.113.7&route=/login"
+    #[serde(default)]
+    pub dimension: String, // What the key was built from, e.g. "ip+route"
+    #[serde(default)]
+    pub policy: String, // Name of the policy this counter belongs to
+    // Fixed window counter, updated atomically in MongoDB
+    #[serde(default)]
     pub request_count: i32,
-    pub last_request_time: DateTime, // Use BSON DateTime type here
+    #[serde(default)]
+    pub last_request_time: Option<DateTime>, // Use BSON DateTime type here
+    // State of the other algorithms, replaced with a compare-and-swap on `version`
+    #[serde(default)]
+    pub state: Option<LimiterState>,
+    #[serde(default)]
+    pub version: i64,
 }
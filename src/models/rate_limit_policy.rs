@@ -0,0 +1,59 @@
+// src/models/rate_limit_policy.rs
+use crate::models::object_id::serialize_objectid_as_string;
+use crate::ratelimit::Algorithm;
+use bson::oid::ObjectId;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+// Name of the policy applied when a check does not ask for one
+pub const DEFAULT_POLICY: &str = "default";
+// Name of the policy that throttles signin attempts per email and per IP
+pub const SIGNIN_POLICY: &str = "signin";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RateLimitPolicy {
+    #[serde(
+        rename = "_id",
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_objectid_as_string"
+    )]
+    pub _id: Option<ObjectId>,
+    pub name: String,
+    pub limit: i32,          // Requests allowed per window
+    pub window_seconds: i64, // Length of the window
+    pub burst: i32,          // Extra requests tolerated on top of `limit`
+    #[serde(default)]
+    pub algorithm: Algorithm,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl RateLimitPolicy {
+    pub fn new(name: String, limit: i32, window_seconds: i64, burst: i32) -> Self {
+        let now = Utc::now();
+        RateLimitPolicy {
+            _id: None,
+            name,
+            limit,
+            window_seconds,
+            burst,
+            algorithm: Algorithm::default(),
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    // Used when no "default" policy has been stored: 10 requests per second
+    pub fn builtin_default() -> Self {
+        RateLimitPolicy::new(DEFAULT_POLICY.to_string(), 10, 1, 0)
+    }
+
+    // Used when no "signin" policy has been stored: 10 attempts per minute
+    pub fn builtin_signin() -> Self {
+        RateLimitPolicy::new(SIGNIN_POLICY.to_string(), 10, 60, 0)
+    }
+
+    pub fn window_millis(&self) -> i64 {
+        self.window_seconds.saturating_mul(1000)
+    }
+}
@@ -0,0 +1,32 @@
+use bson::oid::ObjectId;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+// One refresh token of a signin session. Refreshing marks the token used and
+// issues the next one in the same session, so each token works exactly once.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RefreshToken {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub _id: Option<ObjectId>,
+    pub token_hash: String, // SHA-256 of the token; the token itself is never stored
+    pub session_id: String,
+    pub user_id: ObjectId,
+    // The access token issued alongside, so revoking the session can revoke it too
+    pub access_jti: String,
+    pub access_expires_at: bson::DateTime,
+    pub expires_at: bson::DateTime, // TTL index removes the document after this
+    #[serde(default)]
+    pub used_at: Option<bson::DateTime>,
+    #[serde(default)]
+    pub revoked_at: Option<bson::DateTime>,
+    pub created_at: DateTime<Utc>,
+}
+
+// An access token that must no longer be accepted. Kept until the token would
+// have expired anyway.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RevokedToken {
+    pub jti: String,
+    pub expires_at: bson::DateTime, // TTL index removes the document after this
+    pub revoked_at: DateTime<Utc>,
+}
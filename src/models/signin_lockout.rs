@@ -0,0 +1,33 @@
+use crate::models::blacklisted_ip::serialize_expiry;
+use crate::models::object_id::serialize_objectid_as_string;
+use bson::oid::ObjectId;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize, Serializer};
+
+// Failed signin attempts for one email address or one source IP
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SigninLockout {
+    #[serde(
+        rename = "_id",
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_objectid_as_string"
+    )]
+    pub _id: Option<ObjectId>,
+    pub key: String, // Counter key, e.g. "email=admin@example.com" or "ip=203.0.113.7"
+    pub kind: String, // "email" or "ip"
+    pub failures: i32, // Consecutive failures since the last lockout or success
+    pub lockouts: i32, // Lockouts so far; each one doubles the next
+    #[serde(default, serialize_with = "serialize_expiry")]
+    pub locked_until: Option<bson::DateTime>,
+    // TTL index forgets the record a day after the last failure or lockout
+    #[serde(serialize_with = "serialize_date")]
+    pub expires_at: bson::DateTime,
+    pub last_failure_at: DateTime<Utc>,
+}
+
+fn serialize_date<S>(value: &bson::DateTime, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serialize_expiry(&Some(*value), serializer)
+}
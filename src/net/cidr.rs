@@ -0,0 +1,251 @@
+// src/net/cidr.rs
+use super::ip::parse_ip;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+// A single address or a CIDR prefix such as `203.0.113.0/24` or `2001:db8::/32`.
+// The network address is always stored with its host bits cleared.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct IpRange {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpRange {
+    // Parse an IP address or CIDR string. Host bits set below the prefix are
+    // cleared, so `10.1.2.3/8` canonicalizes to `10.0.0.0/8`.
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let input = input.trim();
+        let (addr_part, prefix_part) = match input.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (input, None),
+        };
+
+        let addr = parse_ip(addr_part)?;
+        // `parse_ip` folds `::ffff:a.b.c.d` to IPv4, but the prefix length was
+        // written against the 128-bit mapped form and has to be shifted down.
+        let mapped = addr.is_ipv4() && addr_part.contains(':');
+        let written_max = if mapped { 128 } else { max_prefix_len(&addr) };
+
+        let prefix_len = match prefix_part {
+            Some(prefix) => match prefix.parse::<u8>() {
+                Ok(len) if len <= written_max && (!mapped || len >= 96) => {
+                    if mapped {
+                        len - 96
+                    } else {
+                        len
+                    }
+                }
+                _ => {
+                    return Err(format!(
+                        "'{}' is not a valid prefix length (expected {}-{})",
+                        prefix,
+                        if mapped { 96 } else { 0 },
+                        written_max
+                    ))
+                }
+            },
+            None => max_prefix_len(&addr),
+        };
+
+        Ok(IpRange {
+            network: mask(&addr, prefix_len),
+            prefix_len,
+        })
+    }
+
+    // The range of length `prefix_len` that contains `ip`
+    pub fn covering(ip: IpAddr, prefix_len: u8) -> Self {
+        let prefix_len = prefix_len.min(max_prefix_len(&ip));
+        IpRange {
+            network: mask(&ip, prefix_len),
+            prefix_len,
+        }
+    }
+
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    // True when the range covers exactly one address.
+    pub fn is_single(&self) -> bool {
+        self.prefix_len == max_prefix_len(&self.network)
+    }
+
+    // True when `ip` lies within the range. Addresses of the other family never do.
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        ip.is_ipv4() == self.network.is_ipv4() && IpRange::covering(*ip, self.prefix_len) == *self
+    }
+
+    // Lowest address in the range, encoded with `ip_key`.
+    pub fn start_key(&self) -> String {
+        ip_key(&self.network)
+    }
+
+    // Highest address in the range, encoded with `ip_key`.
+    pub fn end_key(&self) -> String {
+        let last = match self.network {
+            IpAddr::V4(v4) => {
+                let host_mask = u32::MAX.checked_shr(self.prefix_len as u32).unwrap_or(0);
+                IpAddr::V4(Ipv4Addr::from(u32::from(v4) | host_mask))
+            }
+            IpAddr::V6(v6) => {
+                let host_mask = u128::MAX.checked_shr(self.prefix_len as u32).unwrap_or(0);
+                IpAddr::V6(Ipv6Addr::from(u128::from(v6) | host_mask))
+            }
+        };
+        ip_key(&last)
+    }
+}
+
+impl fmt::Display for IpRange {
+    // Single addresses are printed without a prefix length.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_single() {
+            write!(f, "{}", self.network)
+        } else {
+            write!(f, "{}/{}", self.network, self.prefix_len)
+        }
+    }
+}
+
+// Fixed-width, lexicographically ordered key for an address, so that range
+// containment can be answered by MongoDB with `$lte`/`$gte` on strings.
+// IPv4 and IPv6 use separate prefixes and never compare as overlapping.
+pub fn ip_key(ip: &IpAddr) -> String {
+    match ip {
+        IpAddr::V4(v4) => format!("4:{:08x}", u32::from(*v4)),
+        IpAddr::V6(v6) => format!("6:{:032x}", u128::from(*v6)),
+    }
+}
+
+fn max_prefix_len(ip: &IpAddr) -> u8 {
+    match ip {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+fn mask(ip: &IpAddr, prefix_len: u8) -> IpAddr {
+    match ip {
+        IpAddr::V4(v4) => {
+            let bits = u32::MAX.checked_shl(32 - prefix_len as u32).unwrap_or(0);
+            IpAddr::V4(Ipv4Addr::from(u32::from(*v4) & bits))
+        }
+        IpAddr::V6(v6) => {
+            let bits = u128::MAX.checked_shl(128 - prefix_len as u32).unwrap_or(0);
+            IpAddr::V6(Ipv6Addr::from(u128::from(*v6) & bits))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(input: &str) -> IpAddr {
+        input.parse().unwrap()
+    }
+
+    #[test]
+    fn addresses_and_prefixes_parse() {
+        let single = IpRange::parse(" 203.0.113.7 ").unwrap();
+        assert!(single.is_single());
+        assert_eq!(single.to_string(), "203.0.113.7");
+
+        let v6 = IpRange::parse("2001:DB8::/32").unwrap();
+        assert_eq!(v6.prefix_len(), 32);
+        assert_eq!(v6.to_string(), "2001:db8::/32");
+
+        assert!(IpRange::parse("203.0.113.0/33").is_err());
+        assert!(IpRange::parse("2001:db8::/129").is_err());
+        assert!(IpRange::parse("203.0.113.0/").is_err());
+        assert!(IpRange::parse("203.0.113.0/-1").is_err());
+        assert!(IpRange::parse("not-an-ip/8").is_err());
+    }
+
+    #[test]
+    fn host_bits_are_cleared() {
+        assert_eq!(
+            IpRange::parse("10.1.2.3/8").unwrap().to_string(),
+            "10.0.0.0/8"
+        );
+        assert_eq!(
+            IpRange::parse("2001:db8:ffff::1/32").unwrap(),
+            IpRange::parse("2001:db8::/32").unwrap()
+        );
+        assert_eq!(
+            IpRange::parse("192.0.2.1/0").unwrap().to_string(),
+            "0.0.0.0/0"
+        );
+    }
+
+    #[test]
+    fn keys_span_the_range() {
+        let range = IpRange::parse("203.0.113.0/24").unwrap();
+        assert_eq!(range.start_key(), "4:cb007100");
+        assert_eq!(range.end_key(), "4:cb0071ff");
+
+        let single = IpRange::parse("203.0.113.7").unwrap();
+        assert_eq!(single.start_key(), single.end_key());
+
+        let everything = IpRange::parse("0.0.0.0/0").unwrap();
+        assert_eq!(everything.start_key(), "4:00000000");
+        assert_eq!(everything.end_key(), "4:ffffffff");
+
+        let v6 = IpRange::parse("2001:db8::/32").unwrap();
+        assert_eq!(v6.start_key(), format!("6:20010db8{}", "0".repeat(24)));
+        assert_eq!(v6.end_key(), format!("6:20010db8{}", "f".repeat(24)));
+        // IPv4 keys sort before every IPv6 key
+        assert!(everything.end_key() < IpRange::parse("::/0").unwrap().start_key());
+    }
+
+    #[test]
+    fn containment_respects_prefix_and_family() {
+        let range = IpRange::parse("203.0.113.0/24").unwrap();
+        assert!(range.contains(&ip("203.0.113.0")));
+        assert!(range.contains(&ip("203.0.113.255")));
+        assert!(!range.contains(&ip("203.0.114.0")));
+        // The mapped form of a contained address is folded before lookups,
+        // but the raw IPv6 value is another family
+        assert!(!range.contains(&ip("::ffff:203.0.113.7")));
+
+        let everything = IpRange::parse("::/0").unwrap();
+        assert!(everything.contains(&ip("2001:db8::1")));
+        assert!(!everything.contains(&ip("203.0.113.7")));
+    }
+
+    #[test]
+    fn covering_ranges_are_clamped_to_the_family() {
+        assert_eq!(
+            IpRange::covering(ip("203.0.113.7"), 24),
+            IpRange::parse("203.0.113.0/24").unwrap()
+        );
+        assert_eq!(
+            IpRange::covering(ip("203.0.113.7"), 64),
+            IpRange::parse("203.0.113.7").unwrap()
+        );
+        assert_eq!(
+            IpRange::covering(ip("2001:db8::1"), 0),
+            IpRange::parse("::/0").unwrap()
+        );
+    }
+
+    #[test]
+    fn ipv4_mapped_prefixes_are_shifted_to_ipv4() {
+        assert_eq!(
+            IpRange::parse("::ffff:203.0.113.7/120").unwrap(),
+            IpRange::parse("203.0.113.0/24").unwrap()
+        );
+        assert_eq!(
+            IpRange::parse("::ffff:203.0.113.7").unwrap(),
+            IpRange::parse("203.0.113.7").unwrap()
+        );
+        assert_eq!(
+            IpRange::parse("::ffff:0.0.0.0/96").unwrap(),
+            IpRange::parse("0.0.0.0/0").unwrap()
+        );
+        assert!(IpRange::parse("::ffff:203.0.113.7/95").is_err());
+        assert!(IpRange::parse("::ffff:203.0.113.7/129").is_err());
+    }
+}
@@ -0,0 +1,105 @@
+// src/net/ip.rs
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+// Parse an IP address into its canonical form.
+//
+// - IPv4 octets may carry leading zeros (`001.002.003.004` -> `1.2.3.4`)
+//   and are always read as decimal, never octal.
+// - IPv6 is case-insensitive and may be wrapped in brackets.
+// - IPv4-mapped IPv6 (`::ffff:1.2.3.4`) is folded to plain IPv4.
+// - Zone ids (`fe80::1%eth0`) are rejected.
+pub fn parse_ip(input: &str) -> Result<IpAddr, String> {
+    let trimmed = input.trim();
+    if trimmed.is_empty() {
+        return Err("IP address must not be empty".to_string());
+    }
+
+    if trimmed.contains(':') {
+        let unbracketed = trimmed
+            .strip_prefix('[')
+            .and_then(|s| s.strip_suffix(']'))
+            .unwrap_or(trimmed);
+        let v6: Ipv6Addr = unbracketed
+            .parse()
+            .map_err(|_| format!("'{}' is not a valid IPv6 address", input))?;
+        return Ok(match v6.to_ipv4_mapped() {
+            Some(v4) => IpAddr::V4(v4),
+            None => IpAddr::V6(v6),
+        });
+    }
+
+    parse_ipv4(trimmed)
+        .map(IpAddr::V4)
+        .ok_or_else(|| format!("'{}' is not a valid IPv4 address", input))
+}
+
+fn parse_ipv4(input: &str) -> Option<Ipv4Addr> {
+    let mut octets = [0u8; 4];
+    let mut parts = input.split('.');
+    for octet in octets.iter_mut() {
+        let part = parts.next()?;
+        if part.is_empty() || part.len() > 3 || !part.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        *octet = part.parse().ok()?;
+    }
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(Ipv4Addr::from(octets))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ipv4_leading_zeros_are_decimal() {
+        assert_eq!(parse_ip("001.002.003.004").unwrap().to_string(), "1.2.3.4");
+        assert_eq!(parse_ip("010.0.0.010").unwrap().to_string(), "10.0.0.10");
+        assert!(parse_ip("0001.2.3.4").is_err());
+        assert!(parse_ip("256.0.0.1").is_err());
+        assert!(parse_ip("1.2.3").is_err());
+        assert!(parse_ip("1.2.3.4.5").is_err());
+        assert!(parse_ip("1.2.3.+4").is_err());
+    }
+
+    #[test]
+    fn ipv6_may_be_bracketed_and_is_case_insensitive() {
+        let expected: IpAddr = "2001:db8::1".parse().unwrap();
+        assert_eq!(parse_ip("2001:DB8::1").unwrap(), expected);
+        assert_eq!(parse_ip("[2001:db8::1]").unwrap(), expected);
+        assert!(parse_ip("[2001:db8::1").is_err());
+        assert!(parse_ip("[203.0.113.7]").is_err());
+    }
+
+    #[test]
+    fn zone_ids_are_rejected() {
+        assert!(parse_ip("fe80::1%eth0").is_err());
+        assert!(parse_ip("[fe80::1%25eth0]").is_err());
+    }
+
+    #[test]
+    fn ipv4_mapped_ipv6_is_folded_to_ipv4() {
+        let expected: IpAddr = "203.0.113.7".parse().unwrap();
+        assert_eq!(parse_ip("::ffff:203.0.113.7").unwrap(), expected);
+        assert_eq!(parse_ip("[::FFFF:cb00:7107]").unwrap(), expected);
+        // IPv4-compatible and NAT64 addresses stay IPv6
+        assert!(parse_ip("::203.0.113.7").unwrap().is_ipv6());
+        assert!(parse_ip("64:ff9b::203.0.113.7").unwrap().is_ipv6());
+    }
+
+    #[test]
+    fn surrounding_whitespace_is_ignored() {
+        assert_eq!(
+            parse_ip(" \t203.0.113.7\n").unwrap().to_string(),
+            "203.0.113.7"
+        );
+        assert_eq!(
+            parse_ip("  [2001:db8::1] ").unwrap().to_string(),
+            "2001:db8::1"
+        );
+        assert!(parse_ip("   ").is_err());
+        assert!(parse_ip("203.0.113. 7").is_err());
+    }
+}
@@ -0,0 +1,12 @@
+// src/net/mod.rs
+pub mod cidr;
+pub use cidr::IpRange;
+
+pub mod ip;
+pub use ip::parse_ip;
+
+pub mod url_pattern;
+pub use url_pattern::{UrlMatchType, UrlPattern};
+
+pub mod url_normalize;
+pub use url_normalize::{normalize_path, normalize_url};
@@ -0,0 +1,273 @@
+// src/net/url_normalize.rs
+
+// Bring a URL to the normal form of RFC 3986 section 6, so that spellings of
+// the same resource compare equal:
+//
+// - scheme and host are lowercased, a trailing dot on the host is dropped
+// - default ports (":80" for http, ":443" for https, ...) are removed
+// - percent-encoding uses uppercase hex, unreserved characters are decoded,
+//   and spaces, control and non-ASCII characters are encoded
+// - "." and ".." path segments are resolved
+// - an empty path becomes "/", and other paths lose their trailing slash
+// - the fragment and an empty query ("?") are dropped
+//
+// Input without a scheme is read as "host/path", or as a bare path when it
+// starts with "/".
+pub fn normalize_url(input: &str) -> String {
+    let input = input.trim();
+    let input = input.split('#').next().unwrap_or("");
+    let (before_query, query) = match input.split_once('?') {
+        Some((before, query)) => (before, Some(query)),
+        None => (input, None),
+    };
+
+    let (scheme, rest) = match before_query.split_once("://") {
+        Some((scheme, rest)) if is_scheme(scheme) => (Some(scheme.to_ascii_lowercase()), rest),
+        _ => (None, before_query),
+    };
+    let (authority, path) = if scheme.is_none() && rest.starts_with('/') {
+        match rest.strip_prefix("//") {
+            Some(rest) => split_authority(rest),
+            None => (None, rest),
+        }
+    } else {
+        split_authority(rest)
+    };
+
+    let mut normalized = String::with_capacity(input.len());
+    if let Some(scheme) = &scheme {
+        normalized.push_str(scheme);
+        normalized.push_str("://");
+    } else if authority.is_some() && before_query.starts_with("//") {
+        normalized.push_str("//");
+    }
+    if let Some(authority) = authority {
+        normalized.push_str(&normalize_authority(authority, scheme.as_deref()));
+    }
+
+    let path = remove_dot_segments(&normalize_percent_encoding(path));
+    let path = match path.trim_end_matches('/') {
+        "" if authority.is_some() || path.starts_with('/') => "/",
+        trimmed => trimmed,
+    };
+    normalized.push_str(path);
+
+    if let Some(query) = query.filter(|q| !q.is_empty()) {
+        normalized.push('?');
+        normalized.push_str(&normalize_percent_encoding(query));
+    }
+    normalized
+}
+
+// Normalize the path of a request the way `normalize_url` normalizes the
+// path of a URL, so rules see one spelling of it. Repeated slashes are also
+// collapsed, as most servers treat them as one; the query and fragment are
+// dropped and a trailing slash is kept.
+pub fn normalize_path(path: &str) -> String {
+    let path = path.trim();
+    let path = path.split(['?', '#']).next().unwrap_or("");
+    let mut collapsed = String::with_capacity(path.len() + 1);
+    if !path.starts_with('/') {
+        collapsed.push('/');
+    }
+    for c in path.chars() {
+        if !(c == '/' && collapsed.ends_with('/')) {
+            collapsed.push(c);
+        }
+    }
+    remove_dot_segments(&normalize_percent_encoding(&collapsed))
+}
+
+// `url` without its query string
+pub fn strip_query(url: &str) -> &str {
+    url.split('?').next().unwrap_or(url)
+}
+
+// scheme = ALPHA *( ALPHA / DIGIT / "+" / "-" / "." )
+fn is_scheme(scheme: &str) -> bool {
+    let mut chars = scheme.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+}
+
+fn split_authority(rest: &str) -> (Option<&str>, &str) {
+    match rest.find('/') {
+        Some(i) => (Some(&rest[..i]), &rest[i..]),
+        None => (Some(rest), ""),
+    }
+}
+
+fn default_port(scheme: &str) -> Option<u16> {
+    match scheme {
+        "http" | "ws" => Some(80),
+        "https" | "wss" => Some(443),
+        "ftp" => Some(21),
+        _ => None,
+    }
+}
+
+fn normalize_authority(authority: &str, scheme: Option<&str>) -> String {
+    let (userinfo, host_port) = match authority.rsplit_once('@') {
+        Some((userinfo, host_port)) => (Some(userinfo), host_port),
+        None => (None, authority),
+    };
+    // The port follows the last ':' unless that colon is inside an IPv6 literal
+    let (host, port) = match host_port.rsplit_once(':') {
+        Some((host, port)) if !port.contains(']') => (host, Some(port)),
+        _ => (host_port, None),
+    };
+
+    let mut normalized = String::new();
+    if let Some(userinfo) = userinfo {
+        normalized.push_str(&normalize_percent_encoding(userinfo));
+        normalized.push('@');
+    }
+    normalized.push_str(&host.trim_end_matches('.').to_lowercase());
+    match port.map(|p| (p, p.parse::<u16>())) {
+        // An empty port means the default one
+        Some(("", _)) | None => {}
+        Some((_, Ok(port))) if Some(port) == scheme.and_then(default_port) => {}
+        Some((_, Ok(port))) => normalized.push_str(&format!(":{}", port)),
+        Some((port, Err(_))) => {
+            normalized.push(':');
+            normalized.push_str(port);
+        }
+    }
+    normalized
+}
+
+fn is_unreserved(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~')
+}
+
+// Decode percent-encoded unreserved characters, uppercase the hex digits of
+// the remaining escapes and encode bytes that may not appear in a URI
+fn normalize_percent_encoding(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let hex = |b: u8| (b as char).to_digit(16);
+    let mut out = String::with_capacity(input.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let byte = bytes[i];
+        if byte == b'%' && i + 2 < bytes.len() {
+            if let (Some(hi), Some(lo)) = (hex(bytes[i + 1]), hex(bytes[i + 2])) {
+                let decoded = (hi * 16 + lo) as u8;
+                if is_unreserved(decoded) {
+                    out.push(decoded as char);
+                } else {
+                    out.push_str(&format!("%{:02X}", decoded));
+                }
+                i += 3;
+                continue;
+            }
+        }
+        if byte <= b' ' || byte >= 0x7f || byte == b'%' {
+            out.push_str(&format!("%{:02X}", byte));
+        } else {
+            out.push(byte as char);
+        }
+        i += 1;
+    }
+    out
+}
+
+// RFC 3986 section 5.2.4
+fn remove_dot_segments(path: &str) -> String {
+    let mut input = path.to_string();
+    let mut output = String::with_capacity(path.len());
+    let pop_segment = |output: &mut String| match output.rfind('/') {
+        Some(i) => output.truncate(i),
+        None => output.clear(),
+    };
+    while !input.is_empty() {
+        if input.starts_with("../") {
+            input.drain(..3);
+        } else if input.starts_with("./") || input.starts_with("/./") {
+            input.drain(..2);
+        } else if input == "/." {
+            input.truncate(1);
+        } else if input.starts_with("/../") {
+            input.drain(..3);
+            pop_segment(&mut output);
+        } else if input == "/.." {
+            input.truncate(1);
+            pop_segment(&mut output);
+        } else if input == "." || input == ".." {
+            input.clear();
+        } else {
+            let start = usize::from(input.starts_with('/'));
+            let end = input[start..].find('/').map_or(input.len(), |i| i + start);
+            output.extend(input.drain(..end));
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn equivalent_spellings_normalize_alike() {
+        let expected = "http://evil.com/b";
+        assert_eq!(normalize_url("HTTP://Evil.com:80/a/../b?"), expected);
+        assert_eq!(normalize_url("http://evil.com/b"), expected);
+        assert_eq!(normalize_url("http://evil.com./%62/"), expected);
+        assert_eq!(normalize_url("http://evil.com/./b#fragment"), expected);
+    }
+
+    #[test]
+    fn only_default_ports_are_removed() {
+        assert_eq!(
+            normalize_url("https://x.example:443/"),
+            "https://x.example/"
+        );
+        assert_eq!(
+            normalize_url("https://x.example:80/"),
+            "https://x.example:80/"
+        );
+        assert_eq!(normalize_url("http://x.example:/"), "http://x.example/");
+        assert_eq!(
+            normalize_url("http://[2001:DB8::1]:8080"),
+            "http://[2001:db8::1]:8080/"
+        );
+    }
+
+    #[test]
+    fn percent_encoding_is_made_canonical() {
+        assert_eq!(normalize_url("/a%2fb/%7euser"), "/a%2Fb/~user");
+        assert_eq!(normalize_url("/caf\u{e9} menu"), "/caf%C3%A9%20menu");
+        assert_eq!(normalize_url("/100%"), "/100%25");
+        // Encoded dots are decoded first, then resolved
+        assert_eq!(normalize_url("/a/%2E%2E/b"), "/b");
+    }
+
+    #[test]
+    fn dot_segments_cannot_climb_above_the_root() {
+        assert_eq!(normalize_url("/../../etc/passwd"), "/etc/passwd");
+        assert_eq!(normalize_url("/a/b/.."), "/a");
+        assert_eq!(normalize_url("/a/.."), "/");
+    }
+
+    #[test]
+    fn queries_are_kept_unless_empty() {
+        assert_eq!(normalize_url("/search?q=%7ex"), "/search?q=~x");
+        assert_eq!(strip_query("/search?q=x"), "/search");
+    }
+
+    #[test]
+    fn request_paths_have_one_spelling() {
+        assert_eq!(normalize_path("/%61dmin/x"), "/admin/x");
+        assert_eq!(normalize_path("//admin//x"), "/admin/x");
+        assert_eq!(normalize_path("/x/../admin/"), "/admin/");
+        assert_eq!(normalize_path("admin?debug=1"), "/admin");
+        assert_eq!(normalize_path("/.."), "/");
+    }
+
+    #[test]
+    fn urls_without_a_scheme_keep_their_shape() {
+        assert_eq!(normalize_url("Evil.com/A/"), "evil.com/A");
+        assert_eq!(normalize_url("//CDN.example"), "//cdn.example/");
+        assert_eq!(normalize_url("/"), "/");
+    }
+}
@@ -0,0 +1,358 @@
+// src/net/url_pattern.rs
+use crate::net::url_normalize::{normalize_url, strip_query};
+use crate::rules::glob::glob_match;
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use std::net::Ipv6Addr;
+
+// Longest pattern accepted, in bytes
+const MAX_PATTERN_LEN: usize = 2048;
+// Compiled size limit for regex patterns. The regex crate never backtracks,
+// so matching is linear in the URL; this only bounds memory.
+const MAX_REGEX_SIZE: usize = 1 << 20;
+
+// How a malicious URL entry is compared with a checked URL
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UrlMatchType {
+    // The whole URL
+    Exact,
+    // The start of the URL; entries stored before match types existed use this
+    #[default]
+    Prefix,
+    // The end of the URL, e.g. ".exe"
+    Suffix,
+    // `*` and `?` wildcards over the whole URL
+    Glob,
+    // A regular expression searched for anywhere in the URL
+    Regex,
+    // The URL's host, e.g. "evil.example"
+    Host,
+    // The host or any subdomain of it
+    HostAndSubdomains,
+}
+
+// A validated entry pattern, ready to be matched
+#[derive(Debug, Clone)]
+pub struct UrlPattern {
+    match_type: UrlMatchType,
+    pattern: String,
+    folded: String, // `pattern` lowercased, for suffix entries
+    regex: Option<Regex>,
+    ignore_query: bool,
+}
+
+impl UrlPattern {
+    // Validate `pattern` for `match_type`. Exact and prefix patterns are
+    // normalized like checked URLs (see `normalize_url`), which folds only
+    // the scheme and host, so their paths stay case-sensitive. Host patterns
+    // are lowercased and may be IPv6 literals, with or without brackets;
+    // suffix and glob patterns compare case-insensitively.
+    // With `ignore_query` the query string of checked URLs is disregarded.
+    pub fn new(
+        match_type: UrlMatchType,
+        pattern: &str,
+        ignore_query: bool,
+    ) -> Result<Self, String> {
+        let pattern = pattern.trim();
+        if pattern.is_empty() {
+            return Err("URL pattern must not be empty".to_string());
+        }
+        if pattern.len() > MAX_PATTERN_LEN {
+            return Err(format!(
+                "URL pattern must be at most {} characters",
+                MAX_PATTERN_LEN
+            ));
+        }
+
+        let mut regex = None;
+        let pattern = match match_type {
+            UrlMatchType::Host | UrlMatchType::HostAndSubdomains => {
+                let host = pattern.trim_end_matches('.').to_lowercase();
+                let unbracketed = host
+                    .strip_prefix('[')
+                    .and_then(|h| h.strip_suffix(']'))
+                    .unwrap_or(&host);
+                if let Ok(v6) = unbracketed.parse::<Ipv6Addr>() {
+                    v6.to_string()
+                } else if host.is_empty()
+                    || host.starts_with('.')
+                    || !host
+                        .chars()
+                        .all(|c| c.is_alphanumeric() || c == '-' || c == '.')
+                {
+                    return Err(format!(
+                        "'{}' is not a host name (give only the host, e.g. \"example.com\")",
+                        pattern
+                    ));
+                } else {
+                    host
+                }
+            }
+            UrlMatchType::Regex => {
+                let compiled = RegexBuilder::new(pattern)
+                    .size_limit(MAX_REGEX_SIZE)
+                    .build()
+                    .map_err(|e| format!("Invalid regular expression: {}", e))?;
+                regex = Some(compiled);
+                pattern.to_string()
+            }
+            UrlMatchType::Exact | UrlMatchType::Prefix => {
+                let normalized = normalize_url(pattern);
+                if ignore_query {
+                    strip_query(&normalized).to_string()
+                } else {
+                    normalized
+                }
+            }
+            _ => pattern.to_string(),
+        };
+
+        Ok(UrlPattern {
+            match_type,
+            folded: pattern.to_lowercase(),
+            pattern,
+            regex,
+            ignore_query,
+        })
+    }
+
+    // The pattern as it should be stored
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    // How narrowly the pattern matches, for choosing between several matching
+    // entries: exact URLs first, then prefixes, hosts, suffixes, globs and
+    // regular expressions, and longer patterns before shorter ones
+    pub fn specificity(&self) -> (u8, usize) {
+        let rank = match self.match_type {
+            UrlMatchType::Exact => 6,
+            UrlMatchType::Prefix => 5,
+            UrlMatchType::Host => 4,
+            UrlMatchType::HostAndSubdomains => 3,
+            UrlMatchType::Suffix => 2,
+            UrlMatchType::Glob => 1,
+            UrlMatchType::Regex => 0,
+        };
+        (rank, self.pattern.len())
+    }
+
+    // `url` should already be normalized with `normalize_url`, so that glob
+    // and regex patterns see the same form as the stored ones
+    pub fn matches(&self, url: &str) -> bool {
+        let url = url.trim();
+        let url = if self.ignore_query {
+            strip_query(url)
+        } else {
+            url
+        };
+        match self.match_type {
+            UrlMatchType::Exact => url == self.pattern,
+            UrlMatchType::Prefix => url.starts_with(&self.pattern),
+            UrlMatchType::Suffix => url.to_lowercase().ends_with(&self.folded),
+            UrlMatchType::Glob => glob_match(&self.pattern, url, true),
+            UrlMatchType::Regex => self.regex.as_ref().is_some_and(|re| re.is_match(url)),
+            UrlMatchType::Host => url_host(url).is_some_and(|host| host == self.pattern),
+            UrlMatchType::HostAndSubdomains => url_host(url).is_some_and(|host| {
+                host == self.pattern
+                    || host
+                        .strip_suffix(&self.pattern)
+                        .is_some_and(|rest| rest.ends_with('.'))
+            }),
+        }
+    }
+}
+
+// The lowercased host of `url`, with or without a scheme. IPv6 literals
+// lose their brackets and are written in their canonical form. Paths such
+// as "/login" have no host.
+pub fn url_host(url: &str) -> Option<String> {
+    let url = url.trim();
+    let rest = match url.split_once("://") {
+        Some((_, rest)) => rest,
+        None if url.starts_with("//") => &url[2..],
+        None if url.starts_with('/') => return None,
+        None => url,
+    };
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or("");
+    let host_port = authority.rsplit_once('@').map_or(authority, |(_, h)| h);
+    let host = if let Some(bracketed) = host_port.strip_prefix('[') {
+        let literal = bracketed.split(']').next().unwrap_or("");
+        if let Ok(v6) = literal.parse::<Ipv6Addr>() {
+            return Some(v6.to_string());
+        }
+        literal
+    } else {
+        host_port.split(':').next().unwrap_or("")
+    };
+    let host = host.trim_end_matches('.').to_lowercase();
+    (!host.is_empty()).then_some(host)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(match_type: UrlMatchType, pattern: &str, url: &str) -> bool {
+        UrlPattern::new(match_type, pattern, false)
+            .unwrap()
+            .matches(url)
+    }
+
+    #[test]
+    fn stored_pattern_is_compared_against_the_checked_url() {
+        // The checked URL must start with the entry, not the other way round
+        assert!(matches(
+            UrlMatchType::Prefix,
+            "http://evil.example/download",
+            "http://evil.example/download/payload.exe"
+        ));
+        assert!(!matches(
+            UrlMatchType::Prefix,
+            "http://evil.example/download",
+            "http://evil.example"
+        ));
+        // Only the scheme and host are case-insensitive
+        assert!(matches(
+            UrlMatchType::Exact,
+            "http://Evil.example/a",
+            &normalize_url("HTTP://EVIL.example/a")
+        ));
+        assert!(!matches(
+            UrlMatchType::Exact,
+            "http://Evil.example/a",
+            &normalize_url("http://evil.example/A")
+        ));
+        assert!(!matches(
+            UrlMatchType::Prefix,
+            "/Admin",
+            &normalize_url("/admin/users")
+        ));
+        assert!(matches(
+            UrlMatchType::Suffix,
+            ".exe",
+            "http://x.example/a.EXE"
+        ));
+    }
+
+    #[test]
+    fn regex_metacharacters_are_literal_outside_regex_entries() {
+        assert!(!matches(UrlMatchType::Prefix, ".", "http://example.com"));
+        assert!(!matches(UrlMatchType::Exact, "a.c", "abc"));
+        assert!(matches(
+            UrlMatchType::Glob,
+            "http://*.example/*.php",
+            "http://a.example/x.php"
+        ));
+        assert!(!matches(
+            UrlMatchType::Glob,
+            "http://*.example/*.php",
+            "http://a.example/x.phpx"
+        ));
+    }
+
+    #[test]
+    fn regex_entries_are_validated_and_search_the_url() {
+        assert!(matches(
+            UrlMatchType::Regex,
+            r"/wp-admin/.*\.php$",
+            "http://a.example/wp-admin/x.php"
+        ));
+        assert!(UrlPattern::new(UrlMatchType::Regex, "(unclosed", false).is_err());
+        // Nested quantifiers are harmless: matching does not backtrack
+        let pattern = UrlPattern::new(UrlMatchType::Regex, "^(a+)+$", false).unwrap();
+        assert!(!pattern.matches(&format!("{}b", "a".repeat(10_000))));
+    }
+
+    #[test]
+    fn host_entries_match_the_host_only() {
+        assert!(matches(
+            UrlMatchType::Host,
+            "Evil.example",
+            "https://user@EVIL.example:8443/x"
+        ));
+        assert!(!matches(
+            UrlMatchType::Host,
+            "evil.example",
+            "https://a.evil.example/"
+        ));
+        assert!(!matches(
+            UrlMatchType::Host,
+            "evil.example",
+            "https://good.example/evil.example"
+        ));
+        assert!(matches(
+            UrlMatchType::HostAndSubdomains,
+            "evil.example",
+            "https://a.b.evil.example/"
+        ));
+        assert!(matches(
+            UrlMatchType::HostAndSubdomains,
+            "evil.example",
+            "evil.example/path"
+        ));
+        assert!(!matches(
+            UrlMatchType::HostAndSubdomains,
+            "evil.example",
+            "https://notevil.example/"
+        ));
+        assert!(UrlPattern::new(UrlMatchType::Host, "http://evil.example/", false).is_err());
+    }
+
+    #[test]
+    fn host_entries_may_be_ipv6_literals() {
+        let pattern = UrlPattern::new(UrlMatchType::Host, "[2001:DB8:0::1]", false).unwrap();
+        assert_eq!(pattern.pattern(), "2001:db8::1");
+        assert!(pattern.matches("http://[2001:db8::1]:8080/x"));
+        assert!(pattern.matches("[2001:0db8::0001]/x"));
+        assert!(!pattern.matches("http://[2001:db8::2]/x"));
+        assert!(UrlPattern::new(UrlMatchType::HostAndSubdomains, "2001:db8::1", false).is_ok());
+        assert!(UrlPattern::new(UrlMatchType::Host, "[2001:db8::1", false).is_err());
+    }
+
+    #[test]
+    fn exact_and_prefix_patterns_are_normalized() {
+        let pattern =
+            UrlPattern::new(UrlMatchType::Exact, "HTTP://Evil.com:80/a/../b?", false).unwrap();
+        assert_eq!(pattern.pattern(), "http://evil.com/b");
+        assert!(pattern.matches(&normalize_url("http://evil.com/%62/")));
+        assert!(!pattern.matches(&normalize_url("http://evil.com/b?x=1")));
+
+        let pattern = UrlPattern::new(UrlMatchType::Exact, "http://evil.com/b?x=1", true).unwrap();
+        assert_eq!(pattern.pattern(), "http://evil.com/b");
+        assert!(pattern.matches(&normalize_url("http://evil.com/b?y=2")));
+    }
+
+    #[test]
+    fn narrower_patterns_are_more_specific() {
+        let specificity = |match_type, pattern| {
+            UrlPattern::new(match_type, pattern, false)
+                .unwrap()
+                .specificity()
+        };
+        assert!(
+            specificity(UrlMatchType::Exact, "http://evil.com/a")
+                > specificity(UrlMatchType::Prefix, "http://evil.com/a/b")
+        );
+        assert!(
+            specificity(UrlMatchType::Prefix, "http://evil.com/a/b")
+                > specificity(UrlMatchType::Prefix, "http://evil.com/a")
+        );
+        assert!(
+            specificity(UrlMatchType::Host, "evil.com")
+                > specificity(UrlMatchType::Regex, "evil\\.com/.*")
+        );
+    }
+
+    #[test]
+    fn hosts_are_extracted_from_urls() {
+        assert_eq!(
+            url_host("http://[2001:db8::1]:80/x").as_deref(),
+            Some("2001:db8::1")
+        );
+        assert_eq!(url_host("//cdn.example/a").as_deref(), Some("cdn.example"));
+        assert_eq!(url_host("example.com.").as_deref(), Some("example.com"));
+        assert_eq!(url_host("/login"), None);
+    }
+}
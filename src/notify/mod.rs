@@ -0,0 +1,56 @@
+// src/notify/mod.rs
+// Messages sent to users outside the API, such as password reset links.
+// Deployments plug in their own delivery (SMTP, a chat webhook, ...) by
+// implementing `Notifier` and registering it in `main`.
+use async_trait::async_trait;
+use log::info;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notification {
+    pub to: String, // Email address of the user
+    pub subject: String,
+    pub body: String,
+}
+
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    async fn send(&self, notification: Notification) -> Result<(), String>;
+}
+
+// Writes messages to the server log. Meant for development: reset links
+// end up in the log, so production deployments should plug in a real channel.
+pub struct LogNotifier;
+
+#[async_trait]
+impl Notifier for LogNotifier {
+    async fn send(&self, notification: Notification) -> Result<(), String> {
+        info!(
+            "Notification to {}: {}\n{}",
+            notification.to, notification.subject, notification.body
+        );
+        Ok(())
+    }
+}
+
+// Keeps messages in memory, standing in for a mail server in tests
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemoryNotifier {
+    sent: std::sync::Mutex<Vec<Notification>>,
+}
+
+#[cfg(test)]
+impl MemoryNotifier {
+    pub fn sent(&self) -> Vec<Notification> {
+        self.sent.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+#[async_trait]
+impl Notifier for MemoryNotifier {
+    async fn send(&self, notification: Notification) -> Result<(), String> {
+        self.sent.lock().unwrap().push(notification);
+        Ok(())
+    }
+}
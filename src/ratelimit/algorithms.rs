@@ -0,0 +1,437 @@
+// src/ratelimit/algorithms.rs
+use super::{Algorithm, Decision, Limits};
+use serde::{Deserialize, Serialize};
+
+// Per-key limiter state as stored alongside the counter document. A state
+// written by a different algorithm (after a policy change) is discarded.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "algorithm", rename_all = "snake_case")]
+pub enum LimiterState {
+    FixedWindow {
+        window_start: i64,
+        count: i64,
+    },
+    SlidingWindowLog {
+        log: Vec<i64>,
+    },
+    SlidingWindowCounter {
+        window_start: i64,
+        count: i64,
+        previous_count: i64,
+    },
+    TokenBucket {
+        tokens: f64,
+        last_refill: i64,
+    },
+    Gcra {
+        tat: f64,
+    },
+}
+
+// Counts one request at `now_ms` against the previous state, returning the
+// decision and the state to store.
+pub trait RateLimiter: Send + Sync {
+    fn check(&self, state: Option<LimiterState>, now_ms: i64) -> (Decision, LimiterState);
+}
+
+pub fn limiter_for(algorithm: Algorithm, limits: Limits) -> Box<dyn RateLimiter> {
+    match algorithm {
+        Algorithm::FixedWindow => Box::new(FixedWindow(limits)),
+        Algorithm::SlidingWindowLog => Box::new(SlidingWindowLog(limits)),
+        Algorithm::SlidingWindowCounter => Box::new(SlidingWindowCounter(limits)),
+        Algorithm::TokenBucket => Box::new(TokenBucket(limits)),
+        Algorithm::Gcra => Box::new(Gcra(limits)),
+    }
+}
+
+pub struct FixedWindow(pub Limits);
+
+impl FixedWindow {
+    // Decision for a window that started at `window_start` and has now seen
+    // `count` requests, including the current one. Shared with the atomic
+    // MongoDB path, which keeps the counter in the database.
+    pub fn decide(&self, window_start: i64, count: i64, now_ms: i64) -> Decision {
+        let limits = self.0;
+        let allowed = count <= limits.capacity;
+        let reset_after_ms = (window_start + limits.window_ms - now_ms).max(0);
+        Decision {
+            allowed,
+            limit: limits.capacity,
+            remaining: (limits.capacity - count).max(0),
+            reset_after_ms,
+            retry_after_ms: if allowed { None } else { Some(reset_after_ms) },
+        }
+    }
+}
+
+impl RateLimiter for FixedWindow {
+    fn check(&self, state: Option<LimiterState>, now_ms: i64) -> (Decision, LimiterState) {
+        let (window_start, count) = match state {
+            Some(LimiterState::FixedWindow {
+                window_start,
+                count,
+            }) if now_ms - window_start < self.0.window_ms => (window_start, count + 1),
+            _ => (now_ms, 1),
+        };
+        (
+            self.decide(window_start, count, now_ms),
+            LimiterState::FixedWindow {
+                window_start,
+                count,
+            },
+        )
+    }
+}
+
+pub struct SlidingWindowLog(pub Limits);
+
+impl RateLimiter for SlidingWindowLog {
+    fn check(&self, state: Option<LimiterState>, now_ms: i64) -> (Decision, LimiterState) {
+        let limits = self.0;
+        let mut log = match state {
+            Some(LimiterState::SlidingWindowLog { log }) => log,
+            _ => Vec::new(),
+        };
+        log.retain(|t| now_ms - *t < limits.window_ms);
+
+        let allowed = (log.len() as i64) < limits.capacity;
+        if allowed {
+            log.push(now_ms);
+        }
+
+        // The oldest request is the next one to leave the window
+        let oldest_expiry = log
+            .first()
+            .map(|t| t + limits.window_ms - now_ms)
+            .unwrap_or(0);
+        let newest_expiry = log
+            .last()
+            .map(|t| t + limits.window_ms - now_ms)
+            .unwrap_or(0);
+        let decision = Decision {
+            allowed,
+            limit: limits.capacity,
+            remaining: (limits.capacity - log.len() as i64).max(0),
+            reset_after_ms: newest_expiry,
+            retry_after_ms: if allowed { None } else { Some(oldest_expiry) },
+        };
+        (decision, LimiterState::SlidingWindowLog { log })
+    }
+}
+
+pub struct SlidingWindowCounter(pub Limits);
+
+impl RateLimiter for SlidingWindowCounter {
+    fn check(&self, state: Option<LimiterState>, now_ms: i64) -> (Decision, LimiterState) {
+        let limits = self.0;
+        let window = limits.window_ms;
+        let (mut window_start, mut count, mut previous_count) = match state {
+            Some(LimiterState::SlidingWindowCounter {
+                window_start,
+                count,
+                previous_count,
+            }) => (window_start, count, previous_count),
+            _ => (now_ms, 0, 0),
+        };
+
+        // Roll forward to the window containing `now_ms`
+        let elapsed_windows = (now_ms - window_start).max(0) / window;
+        if elapsed_windows > 0 {
+            previous_count = if elapsed_windows == 1 { count } else { 0 };
+            count = 0;
+            window_start += elapsed_windows * window;
+        }
+
+        // Share of the previous window that still overlaps the sliding window
+        let weight = (window - (now_ms - window_start)) as f64 / window as f64;
+        let estimated = previous_count as f64 * weight + count as f64;
+        let allowed = estimated + 1.0 <= limits.capacity as f64;
+        if allowed {
+            count += 1;
+        }
+        let estimated = previous_count as f64 * weight + count as f64;
+
+        let until_window_end = window_start + window - now_ms;
+        let retry_after_ms = if allowed {
+            None
+        } else if previous_count > 0 && count < limits.capacity {
+            // Wait until enough of the previous window has slid out
+            let needed_weight = (limits.capacity - count - 1) as f64 / previous_count as f64;
+            let wait = window_start + window - (needed_weight * window as f64).floor() as i64;
+            Some((wait - now_ms).max(1))
+        } else {
+            Some(until_window_end.max(1))
+        };
+
+        let decision = Decision {
+            allowed,
+            limit: limits.capacity,
+            remaining: (limits.capacity as f64 - estimated).floor().max(0.0) as i64,
+            reset_after_ms: if count > 0 {
+                until_window_end + window
+            } else {
+                until_window_end
+            },
+            retry_after_ms,
+        };
+        (
+            decision,
+            LimiterState::SlidingWindowCounter {
+                window_start,
+                count,
+                previous_count,
+            },
+        )
+    }
+}
+
+pub struct TokenBucket(pub Limits);
+
+impl RateLimiter for TokenBucket {
+    fn check(&self, state: Option<LimiterState>, now_ms: i64) -> (Decision, LimiterState) {
+        let limits = self.0;
+        let capacity = limits.capacity as f64;
+        let refill_per_ms = limits.limit as f64 / limits.window_ms as f64;
+
+        let mut tokens = match state {
+            Some(LimiterState::TokenBucket {
+                tokens,
+                last_refill,
+            }) => (tokens + (now_ms - last_refill).max(0) as f64 * refill_per_ms).min(capacity),
+            _ => capacity,
+        };
+
+        let allowed = tokens >= 1.0;
+        if allowed {
+            tokens -= 1.0;
+        }
+
+        let decision = Decision {
+            allowed,
+            limit: limits.capacity,
+            remaining: tokens.floor() as i64,
+            reset_after_ms: ((capacity - tokens) / refill_per_ms).ceil() as i64,
+            retry_after_ms: if allowed {
+                None
+            } else {
+                Some(((1.0 - tokens) / refill_per_ms).ceil() as i64)
+            },
+        };
+        (
+            decision,
+            LimiterState::TokenBucket {
+                tokens,
+                last_refill: now_ms,
+            },
+        )
+    }
+}
+
+pub struct Gcra(pub Limits);
+
+impl RateLimiter for Gcra {
+    fn check(&self, state: Option<LimiterState>, now_ms: i64) -> (Decision, LimiterState) {
+        let limits = self.0;
+        let now = now_ms as f64;
+        // One request is "emitted" every `interval`; up to `capacity` may arrive at once
+        let interval = limits.window_ms as f64 / limits.limit as f64;
+        let tolerance = interval * (limits.capacity - 1) as f64;
+
+        let tat = match state {
+            Some(LimiterState::Gcra { tat }) => tat.max(now),
+            _ => now,
+        };
+
+        let allowed = tat - now <= tolerance;
+        let tat = if allowed { tat + interval } else { tat };
+
+        let decision = Decision {
+            allowed,
+            limit: limits.capacity,
+            remaining: ((tolerance + interval - (tat - now)) / interval)
+                .floor()
+                .max(0.0) as i64,
+            reset_after_ms: (tat - now).ceil() as i64,
+            retry_after_ms: if allowed {
+                None
+            } else {
+                Some((tat - now - tolerance).ceil() as i64)
+            },
+        };
+        (decision, LimiterState::Gcra { tat })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ratelimit::Clock;
+    use std::sync::atomic::{AtomicI64, Ordering};
+
+    struct ManualClock(AtomicI64);
+
+    impl ManualClock {
+        fn new(start: i64) -> Self {
+            ManualClock(AtomicI64::new(start))
+        }
+
+        fn advance(&self, ms: i64) {
+            self.0.fetch_add(ms, Ordering::SeqCst);
+        }
+    }
+
+    impl Clock for ManualClock {
+        fn now_millis(&self) -> i64 {
+            self.0.load(Ordering::SeqCst)
+        }
+    }
+
+    // Runs requests through a limiter, threading the state like the store does
+    struct Harness {
+        limiter: Box<dyn RateLimiter>,
+        state: Option<LimiterState>,
+        clock: ManualClock,
+    }
+
+    impl Harness {
+        fn new(algorithm: Algorithm, limits: Limits) -> Self {
+            Harness {
+                limiter: limiter_for(algorithm, limits),
+                state: None,
+                clock: ManualClock::new(1_000_000),
+            }
+        }
+
+        fn hit(&mut self) -> Decision {
+            let (decision, state) = self
+                .limiter
+                .check(self.state.take(), self.clock.now_millis());
+            self.state = Some(state);
+            decision
+        }
+
+        fn allowed_of(&mut self, attempts: usize) -> usize {
+            (0..attempts).filter(|_| self.hit().allowed).count()
+        }
+    }
+
+    #[test]
+    fn fixed_window_resets_at_window_boundary() {
+        let mut h = Harness::new(Algorithm::FixedWindow, Limits::new(3, 0, 1000));
+        assert_eq!(h.allowed_of(5), 3);
+
+        let rejected = h.hit();
+        assert_eq!(rejected.retry_after_ms, Some(1000));
+
+        h.clock.advance(999);
+        assert!(!h.hit().allowed);
+        h.clock.advance(1);
+        assert!(h.hit().allowed);
+    }
+
+    #[test]
+    fn fixed_window_allows_double_limit_across_edge() {
+        // The weakness the other algorithms address
+        let mut h = Harness::new(Algorithm::FixedWindow, Limits::new(3, 0, 1000));
+        h.hit(); // Opens a window
+        h.clock.advance(999);
+        assert_eq!(h.allowed_of(2), 2);
+        h.clock.advance(1);
+        assert_eq!(h.allowed_of(3), 3); // 5 requests within 2ms
+    }
+
+    #[test]
+    fn sliding_window_log_counts_exact_last_window() {
+        let mut h = Harness::new(Algorithm::SlidingWindowLog, Limits::new(3, 0, 1000));
+        h.hit(); // t=0
+        h.clock.advance(500);
+        assert_eq!(h.allowed_of(3), 2); // t=500
+
+        let rejected = h.hit();
+        assert!(!rejected.allowed);
+        assert_eq!(rejected.retry_after_ms, Some(500));
+
+        h.clock.advance(500); // first request leaves the window
+        assert_eq!(h.allowed_of(2), 1);
+    }
+
+    #[test]
+    fn sliding_window_counter_weights_previous_window() {
+        let mut h = Harness::new(Algorithm::SlidingWindowCounter, Limits::new(4, 0, 1000));
+        assert_eq!(h.allowed_of(4), 4);
+
+        // Halfway through the next window half of the previous count still applies
+        h.clock.advance(1500);
+        assert_eq!(h.allowed_of(4), 2);
+
+        // Two full windows later nothing carries over
+        h.clock.advance(2000);
+        assert_eq!(h.allowed_of(5), 4);
+    }
+
+    #[test]
+    fn sliding_window_counter_has_no_edge_burst() {
+        let mut h = Harness::new(Algorithm::SlidingWindowCounter, Limits::new(3, 0, 1000));
+        assert_eq!(h.allowed_of(3), 3);
+        h.clock.advance(1000); // New window, previous window still fully weighted
+        assert_eq!(h.allowed_of(3), 0);
+    }
+
+    #[test]
+    fn token_bucket_refills_at_limit_per_window() {
+        let mut h = Harness::new(Algorithm::TokenBucket, Limits::new(10, 0, 1000));
+        assert_eq!(h.allowed_of(12), 10);
+
+        let rejected = h.hit();
+        assert_eq!(rejected.retry_after_ms, Some(100));
+
+        h.clock.advance(100); // One token
+        assert_eq!(h.allowed_of(2), 1);
+        h.clock.advance(1000); // Back to full, never above capacity
+        assert_eq!(h.allowed_of(12), 10);
+    }
+
+    #[test]
+    fn token_bucket_burst_raises_capacity_not_rate() {
+        let mut h = Harness::new(Algorithm::TokenBucket, Limits::new(2, 3, 1000));
+        assert_eq!(h.allowed_of(10), 5);
+        h.clock.advance(500);
+        assert_eq!(h.allowed_of(10), 1);
+    }
+
+    #[test]
+    fn gcra_spaces_requests_after_burst() {
+        let mut h = Harness::new(Algorithm::Gcra, Limits::new(4, 0, 1000));
+        assert_eq!(h.allowed_of(6), 4);
+
+        let rejected = h.hit();
+        assert_eq!(rejected.retry_after_ms, Some(250));
+
+        h.clock.advance(250);
+        assert_eq!(h.allowed_of(2), 1);
+        h.clock.advance(249);
+        assert!(!h.hit().allowed);
+        h.clock.advance(1);
+        assert!(h.hit().allowed);
+    }
+
+    #[test]
+    fn gcra_reports_remaining() {
+        let mut h = Harness::new(Algorithm::Gcra, Limits::new(3, 0, 900));
+        assert_eq!(h.hit().remaining, 2);
+        assert_eq!(h.hit().remaining, 1);
+        assert_eq!(h.hit().remaining, 0);
+        h.clock.advance(300);
+        assert_eq!(h.hit().remaining, 0);
+    }
+
+    #[test]
+    fn state_from_another_algorithm_is_discarded() {
+        let mut h = Harness::new(Algorithm::TokenBucket, Limits::new(2, 0, 1000));
+        h.state = Some(LimiterState::FixedWindow {
+            window_start: h.clock.now_millis(),
+            count: 100,
+        });
+        assert!(h.hit().allowed);
+    }
+}
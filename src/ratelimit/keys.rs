@@ -0,0 +1,124 @@
+// src/ratelimit/keys.rs
+use crate::net::parse_ip;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+
+// One thing to rate limit on, such as `{"ip": "203.0.113.7"}` or a
+// combination like `{"ip": "203.0.113.7", "route": "/login"}`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Dimension {
+    // Label reported when this dimension trips; defaults to the part names joined with '+'
+    pub name: Option<String>,
+    pub parts: BTreeMap<String, String>,
+}
+
+// A dimension reduced to the counter key it is stored under
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CounterKey {
+    pub dimension: String,
+    pub key: String,
+    pub ip: Option<IpAddr>, // The "ip" part, if the dimension has one
+}
+
+impl Dimension {
+    pub fn single(part: &str, value: &str) -> Self {
+        Dimension {
+            name: None,
+            parts: BTreeMap::from([(part.to_string(), value.to_string())]),
+        }
+    }
+
+    // Build the canonical key: parts sorted by name, IP parts normalized, so
+    // that the same combination always maps to the same counter.
+    pub fn to_counter_key(&self) -> Result<CounterKey, String> {
+        if self.parts.is_empty() {
+            return Err("a dimension needs at least one part".to_string());
+        }
+
+        let mut segments = Vec::with_capacity(self.parts.len());
+        let mut ip = None;
+        for (part, value) in &self.parts {
+            if part.is_empty()
+                || !part
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+            {
+                return Err(format!(
+                    "'{}' is not a valid part name (use lowercase letters, digits and '_')",
+                    part
+                ));
+            }
+            let value = value.trim();
+            if value.is_empty() {
+                return Err(format!("part '{}' must not be empty", part));
+            }
+            let value = if part == "ip" {
+                let parsed = parse_ip(value)?;
+                ip = Some(parsed);
+                parsed.to_string()
+            } else {
+                value.to_string()
+            };
+            // '&' and '=' separate segments, so escape them in values
+            let value = value
+                .replace('%', "%25")
+                .replace('&', "%26")
+                .replace('=', "%3D");
+            segments.push(format!("{}={}", part, value));
+        }
+
+        let dimension = match &self.name {
+            Some(name) if !name.trim().is_empty() => name.trim().to_string(),
+            _ => self
+                .parts
+                .keys()
+                .map(String::as_str)
+                .collect::<Vec<_>>()
+                .join("+"),
+        };
+
+        Ok(CounterKey {
+            dimension,
+            key: segments.join("&"),
+            ip,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn combined_parts_are_sorted_and_named() {
+        let dimension = Dimension {
+            name: None,
+            parts: BTreeMap::from([
+                ("route".to_string(), "/login".to_string()),
+                ("ip".to_string(), "::ffff:203.0.113.7".to_string()),
+            ]),
+        };
+        let key = dimension.to_counter_key().unwrap();
+        assert_eq!(key.dimension, "ip+route");
+        assert_eq!(key.key, "ip=203.0.113.7&route=/login");
+        assert_eq!(key.ip, Some("203.0.113.7".parse().unwrap()));
+    }
+
+    #[test]
+    fn separators_in_values_cannot_collide() {
+        let sneaky = Dimension::single("user", "a&route=/x")
+            .to_counter_key()
+            .unwrap();
+        assert_eq!(sneaky.key, "user=a%26route%3D/x");
+    }
+
+    #[test]
+    fn invalid_parts_are_rejected() {
+        assert!(Dimension::single("ip", "not-an-ip")
+            .to_counter_key()
+            .is_err());
+        assert!(Dimension::single("Route", "/x").to_counter_key().is_err());
+        assert!(Dimension::single("user", " ").to_counter_key().is_err());
+    }
+}
@@ -0,0 +1,81 @@
+// src/ratelimit/mod.rs
+pub mod algorithms;
+pub mod keys;
+pub mod store;
+
+use serde::{Deserialize, Serialize};
+
+pub use algorithms::{limiter_for, LimiterState};
+
+// Rate limiting algorithm a policy is enforced with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Algorithm {
+    // Counter reset at the start of every window (the original behaviour)
+    #[default]
+    FixedWindow,
+    // Timestamp of every allowed request within the last window
+    SlidingWindowLog,
+    // Current window count plus a weighted share of the previous window
+    SlidingWindowCounter,
+    // Bucket of `limit + burst` tokens refilled at `limit` per window
+    TokenBucket,
+    // Generic cell rate algorithm: one theoretical arrival time per key
+    Gcra,
+}
+
+// Source of the current time, injectable so algorithms can be tested deterministically
+pub trait Clock: Send + Sync {
+    fn now_millis(&self) -> i64;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_millis(&self) -> i64 {
+        chrono::Utc::now().timestamp_millis()
+    }
+}
+
+// Settings an algorithm needs, taken from a `RateLimitPolicy`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    pub limit: i64,     // Requests per window
+    pub capacity: i64,  // Requests allowed at once: `limit + burst`
+    pub window_ms: i64, // Window length in milliseconds
+}
+
+impl Limits {
+    pub fn new(limit: i64, burst: i64, window_ms: i64) -> Self {
+        let limit = limit.max(1);
+        Limits {
+            limit,
+            capacity: limit.saturating_add(burst.max(0)),
+            window_ms: window_ms.max(1),
+        }
+    }
+}
+
+// Outcome of counting one request
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Decision {
+    pub allowed: bool,
+    pub limit: i64,
+    pub remaining: i64,
+    // Milliseconds until the limiter is back to full capacity
+    pub reset_after_ms: i64,
+    // Milliseconds until a rejected request could succeed
+    pub retry_after_ms: Option<i64>,
+}
+
+impl Decision {
+    // Whole seconds until the limiter resets, rounded up as the headers require
+    pub fn reset_after_secs(&self) -> i64 {
+        (self.reset_after_ms.max(0) + 999) / 1000
+    }
+
+    pub fn retry_after_secs(&self) -> Option<i64> {
+        self.retry_after_ms
+            .map(|ms| ((ms.max(0) + 999) / 1000).max(1))
+    }
+}
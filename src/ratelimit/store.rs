@@ -0,0 +1,407 @@
+// src/ratelimit/store.rs
+use super::{algorithms::FixedWindow, limiter_for, Algorithm, Clock, Decision, Limits};
+use crate::db::is_duplicate_key;
+use crate::models::{RateLimitEntry, RateLimitPolicy};
+use bson::{doc, DateTime};
+use mongodb::{
+    options::{FindOneAndUpdateOptions, ReturnDocument, UpdateModifications},
+    Collection,
+};
+
+// Compare-and-swap attempts before giving up on a heavily contended counter
+const MAX_CAS_ATTEMPTS: usize = 16;
+
+// Count one request for the counter `key` under `policy` in the
+// `rate_limits` collection. `dimension` names what the key was built from
+// (e.g. "ip" or "ip+route") and is stored for reporting only.
+//
+// Fixed windows are a single atomic findAndModify. The other algorithms keep
+// richer state, so they read it, evaluate in Rust and write it back with a
+// compare-and-swap on `version`, retrying when another caller got there first.
+// A counter last written by another algorithm is taken over, and its state
+// discarded, when the policy's algorithm changes.
+pub async fn hit(
+    collection: &Collection<RateLimitEntry>,
+    key: &str,
+    dimension: &str,
+    policy: &RateLimitPolicy,
+    clock: &dyn Clock,
+) -> mongodb::error::Result<Decision> {
+    let limits = Limits::new(
+        policy.limit as i64,
+        policy.burst as i64,
+        policy.window_millis(),
+    );
+    match policy.algorithm {
+        Algorithm::FixedWindow => {
+            let now = DateTime::from_millis(clock.now_millis());
+            let entry = record_request(collection, key, dimension, policy, now).await?;
+            let window_start = entry
+                .last_request_time
+                .map(|t| t.timestamp_millis())
+                .unwrap_or(now.timestamp_millis());
+            Ok(FixedWindow(limits).decide(
+                window_start,
+                entry.request_count as i64,
+                now.timestamp_millis(),
+            ))
+        }
+        algorithm => {
+            compare_and_swap(collection, key, dimension, policy, algorithm, limits, clock).await
+        }
+    }
+}
+
+async fn compare_and_swap(
+    collection: &Collection<RateLimitEntry>,
+    key: &str,
+    dimension: &str,
+    policy: &RateLimitPolicy,
+    algorithm: Algorithm,
+    limits: Limits,
+    clock: &dyn Clock,
+) -> mongodb::error::Result<Decision> {
+    let limiter = limiter_for(algorithm, limits);
+    let filter = doc! { "key": key, "policy": &policy.name };
+
+    for _ in 0..MAX_CAS_ATTEMPTS {
+        let current = collection.find_one(filter.clone(), None).await?;
+        let (state, version) = match &current {
+            Some(entry) => (entry.state.clone(), Some(entry.version)),
+            None => (None, None),
+        };
+
+        let (decision, state) = limiter.check(state, clock.now_millis());
+        let state = bson::to_bson(&state)?;
+
+        let written = match version {
+            // First request for this counter; the unique index stops a second insert
+            None => {
+                let insert = collection
+                    .clone_with_type::<bson::Document>()
+                    .insert_one(
+                        doc! {
+                            "key": key,
+                            "dimension": dimension,
+                            "policy": &policy.name,
+                            "state": state,
+                            "version": 1_i64,
+                        },
+                        None,
+                    )
+                    .await;
+                match insert {
+                    Ok(_) => true,
+                    Err(e) if is_duplicate_key(&e) => false,
+                    Err(e) => return Err(e),
+                }
+            }
+            Some(version) => {
+                let mut filter = filter.clone();
+                if version == 0 {
+                    // Fixed-window counters are written without a version
+                    filter.insert(
+                        "$or",
+                        vec![
+                            doc! { "version": 0_i64 },
+                            doc! { "version": { "$exists": false } },
+                        ],
+                    );
+                } else {
+                    filter.insert("version", version);
+                }
+                let update = doc! {
+                    "$set": { "state": state, "version": version + 1 },
+                    "$unset": { "request_count": "", "last_request_time": "" },
+                };
+                collection
+                    .update_one(filter, update, None)
+                    .await?
+                    .matched_count
+                    == 1
+            }
+        };
+
+        if written {
+            return Ok(decision);
+        }
+    }
+    Err(mongodb::error::Error::custom(format!(
+        "rate limit counter '{}' still contended after {} attempts",
+        key, MAX_CAS_ATTEMPTS
+    )))
+}
+
+// Count one request for `key` under a fixed-window `policy` and return the
+// updated counter.
+//
+// The window check, reset and increment run inside MongoDB as a single
+// findAndModify with an update pipeline, so concurrent callers can never read
+// the same count. The unique index on `(key, policy)` makes racing upserts for
+// a new counter fail with a duplicate key error; the loser retries as an update.
+pub async fn record_request(
+    collection: &Collection<RateLimitEntry>,
+    key: &str,
+    dimension: &str,
+    policy: &RateLimitPolicy,
+    now: DateTime,
+) -> mongodb::error::Result<RateLimitEntry> {
+    // The current window is still open if it started less than one window ago.
+    // A missing `last_request_time` (new document) counts as a closed window;
+    // its type is tested first since no sentinel date is safe to subtract.
+    let window_open = doc! {
+        "$and": [
+            { "$eq": [{ "$type": "$last_request_time" }, "date"] },
+            { "$lt": [{ "$subtract": [now, "$last_request_time"] }, policy.window_millis()] },
+        ]
+    };
+    let pipeline = vec![doc! {
+        "$set": {
+            "request_count": {
+                "$cond": [
+                    window_open.clone(),
+                    // Counters written by another algorithm have no count
+                    { "$add": [{ "$ifNull": ["$request_count", 0] }, 1] },
+                    1
+                ]
+            },
+            "last_request_time": {
+                "$cond": [window_open, "$last_request_time", now]
+            },
+            "dimension": { "$literal": dimension },
+            // Drop the state of an algorithm the policy used before
+            "state": "$$REMOVE",
+            "version": "$$REMOVE",
+        }
+    }];
+    let options = FindOneAndUpdateOptions::builder()
+        .upsert(true)
+        .return_document(ReturnDocument::After)
+        .build();
+
+    loop {
+        let result = collection
+            .find_one_and_update(
+                doc! { "key": key, "policy": &policy.name },
+                UpdateModifications::Pipeline(pipeline.clone()),
+                options.clone(),
+            )
+            .await;
+
+        match result {
+            Ok(Some(entry)) => return Ok(entry),
+            // With `upsert` and `ReturnDocument::After` a document is always returned
+            Ok(None) => {
+                return Err(mongodb::error::Error::custom(
+                    "rate limit upsert returned no document",
+                ))
+            }
+            Err(e) if is_duplicate_key(&e) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ratelimit::SystemClock;
+    use futures::future::join_all;
+    use mongodb::{options::ClientOptions, Client};
+
+    async fn test_collection() -> Collection<RateLimitEntry> {
+        let uri = std::env::var("MONGODB_URI").expect("MONGODB_URI must be set");
+        let client = Client::with_options(ClientOptions::parse(&uri).await.unwrap()).unwrap();
+        let db = client.database("rustkeeper_test");
+        crate::db::indexes::ensure_rate_limit_indexes(&db)
+            .await
+            .unwrap();
+        db.collection("rate_limits")
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    #[ignore = "requires MongoDB (set MONGODB_URI)"]
+    async fn limit_holds_under_parallel_callers() {
+        let collection = test_collection().await;
+        let key = format!("ip=198.51.100.{}", std::process::id() % 250);
+        collection
+            .delete_many(doc! { "key": &key }, None)
+            .await
+            .unwrap();
+
+        // Every caller lands in the same window
+        let now = DateTime::now();
+        let calls = (0..100).map(|_| {
+            let collection = collection.clone();
+            let key = key.clone();
+            tokio::spawn(async move {
+                let policy = RateLimitPolicy::builtin_default();
+                record_request(&collection, &key, "ip", &policy, now)
+                    .await
+                    .unwrap()
+            })
+        });
+        let counts: Vec<i32> = join_all(calls)
+            .await
+            .into_iter()
+            .map(|entry| entry.unwrap().request_count)
+            .collect();
+
+        let default = RateLimitPolicy::builtin_default();
+        let max_requests = default.limit + default.burst;
+        let allowed = counts.iter().filter(|c| **c <= max_requests).count();
+        assert_eq!(allowed, max_requests as usize);
+
+        // Each caller saw a distinct count, and only one document was created
+        let mut sorted = counts.clone();
+        sorted.sort_unstable();
+        assert_eq!(sorted, (1..=100).collect::<Vec<i32>>());
+        assert_eq!(
+            collection
+                .count_documents(doc! { "key": &key }, None)
+                .await
+                .unwrap(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "requires MongoDB (set MONGODB_URI)"]
+    async fn window_resets_after_it_closes() {
+        let collection = test_collection().await;
+        let key = format!("ip=198.51.100.{}", (std::process::id() + 1) % 250);
+        collection
+            .delete_many(doc! { "key": &key }, None)
+            .await
+            .unwrap();
+
+        let policy = RateLimitPolicy::new("test-window".to_string(), 5, 2, 0);
+        let start = DateTime::now();
+        // A new counter starts a window rather than reading a missing start time
+        let first = record_request(&collection, &key, "ip", &policy, start)
+            .await
+            .unwrap();
+        assert_eq!(first.request_count, 1);
+        assert_eq!(first.last_request_time, Some(start));
+        for _ in 0..policy.limit + 4 {
+            record_request(&collection, &key, "ip", &policy, start)
+                .await
+                .unwrap();
+        }
+        let later = DateTime::from_millis(start.timestamp_millis() + policy.window_millis());
+        let entry = record_request(&collection, &key, "ip", &policy, later)
+            .await
+            .unwrap();
+        assert_eq!(entry.request_count, 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    #[ignore = "requires MongoDB (set MONGODB_URI)"]
+    async fn compare_and_swap_holds_under_parallel_callers() {
+        let collection = test_collection().await;
+        let key = format!("ip=198.51.100.{}", (std::process::id() + 2) % 250);
+        let mut policy = RateLimitPolicy::new("test-bucket".to_string(), 10, 60, 0);
+        policy.algorithm = Algorithm::TokenBucket;
+        collection
+            .delete_many(doc! { "key": &key, "policy": &policy.name }, None)
+            .await
+            .unwrap();
+
+        let calls = (0..50).map(|_| {
+            let collection = collection.clone();
+            let key = key.clone();
+            let policy = policy.clone();
+            tokio::spawn(async move {
+                hit(&collection, &key, "ip", &policy, &SystemClock)
+                    .await
+                    .unwrap()
+                    .allowed
+            })
+        });
+        let allowed = join_all(calls)
+            .await
+            .into_iter()
+            .filter(|allowed| *allowed.as_ref().unwrap())
+            .count();
+        assert_eq!(allowed, 10);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires MongoDB (set MONGODB_URI)"]
+    async fn counter_survives_an_algorithm_switch() {
+        let collection = test_collection().await;
+        let key = format!("ip=198.51.100.{}", (std::process::id() + 3) % 250);
+        let mut policy = RateLimitPolicy::new("test-switch".to_string(), 3, 60, 0);
+        collection
+            .delete_many(doc! { "key": &key, "policy": &policy.name }, None)
+            .await
+            .unwrap();
+
+        // A fixed-window counter has no `version`; a token bucket takes it over
+        hit(&collection, &key, "ip", &policy, &SystemClock)
+            .await
+            .unwrap();
+        policy.algorithm = Algorithm::TokenBucket;
+        for _ in 0..3 {
+            assert!(
+                hit(&collection, &key, "ip", &policy, &SystemClock)
+                    .await
+                    .unwrap()
+                    .allowed
+            );
+        }
+        assert!(
+            !hit(&collection, &key, "ip", &policy, &SystemClock)
+                .await
+                .unwrap()
+                .allowed
+        );
+
+        // And back: the token bucket document has no `request_count`
+        policy.algorithm = Algorithm::FixedWindow;
+        let decision = hit(&collection, &key, "ip", &policy, &SystemClock)
+            .await
+            .unwrap();
+        assert!(decision.allowed);
+        let entry = collection
+            .find_one(doc! { "key": &key, "policy": &policy.name }, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(entry.request_count, 1);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires MongoDB (set MONGODB_URI)"]
+    async fn legacy_counters_do_not_block_the_unique_index() {
+        // A database of its own, so the other tests keep their index
+        let uri = std::env::var("MONGODB_URI").expect("MONGODB_URI must be set");
+        let client = Client::with_options(ClientOptions::parse(&uri).await.unwrap()).unwrap();
+        let db = client.database("rustkeeper_test_legacy");
+        db.drop(None).await.unwrap();
+        let legacy = db.collection::<bson::Document>("rate_limits");
+
+        // Counters from before the (key, policy) index have neither field
+        let now = DateTime::now();
+        for _ in 0..2 {
+            legacy
+                .insert_one(
+                    doc! { "ip": "198.51.100.1", "request_count": 1, "last_request_time": now },
+                    None,
+                )
+                .await
+                .unwrap();
+        }
+
+        crate::db::indexes::ensure_rate_limit_indexes(&db)
+            .await
+            .unwrap();
+        assert_eq!(
+            legacy
+                .count_documents(doc! { "key": { "$exists": false } }, None)
+                .await
+                .unwrap(),
+            0
+        );
+    }
+}
@@ -1,56 +1,472 @@
 use actix_web::{web, HttpResponse};
 
+use crate::auth::roles::{
+    ADMIN_ROLES, ADMIN_SCOPES, BLACKLIST_SCOPES, CHECK_ROLES, CHECK_SCOPES, READ_ROLES, WRITE_ROLES,
+};
+use crate::middleware::jwt_auth::JwtAuth;
+
 use crate::handlers::{
+    add_allowlist_ip,
     add_blacklist_ip,
     add_blacklist_url,
+    add_rate_limit_policy,
+    add_rule,
+    approve_user,
+    cache_status,
+    change_password,
+    change_user_role,
     check_rate_limit, // Import the check_rate_limit handler
+    confirm_password_reset,
+    confirm_totp,
+    create_api_key,
+    decide,
+    delete_allowlist_ip_by_id,
+    delete_api_key_by_id,
     delete_blacklist_ip_by_id,
     delete_blacklist_url_by_id,
+    delete_rate_limit_policy,
+    delete_rule_by_id,
+    delete_signin_lockout,
+    delete_user_by_id,
+    disable_totp,
+    edit_allowlist_ip_by_id,
+    edit_api_key_by_id,
     edit_blacklist_ip_by_id,
     edit_blacklist_url_by_id,
+    edit_rate_limit_policy,
+    edit_rule_by_id,
+    enroll_totp,
+    get_all_allowlist_ip,
+    get_all_api_keys,
     get_all_blacklist_ip,
     get_all_blacklist_url,
+    get_all_rate_limit_policies,
+    get_all_rules,
+    get_all_signin_lockouts,
+    get_all_users,
+    get_allowlist_ip_by_id,
+    get_api_key_by_id,
     get_blacklist_ip_by_id,
     get_blacklist_url_by_id,
+    get_mfa_policy,
+    get_rate_limit_policy,
+    get_rule_by_id,
+    get_user_by_id,
     is_blacklist_ip,
     is_blacklist_url,
+    jwks,
+    new_recovery_codes,
+    reactivate_user,
+    refresh_token,
+    reject_user,
+    request_password_reset,
+    reset_user_mfa,
+    reset_user_password,
+    revoke_all_sessions,
     signin,
+    signin_mfa,
+    signin_mfa_enroll,
+    signout,
     signup,
+    suspend_user,
+    update_mfa_policy,
 };
 
+// Every endpoint except signup, signin (including its MFA step), token refresh,
+// password reset and the JWKS requires a bearer token. Writes need an admin or operator, reads also allow read-only
+// users, and the check endpoints additionally accept checker credentials.
+// API keys work on the check, allowlist, blacklist, rule, rate limit policy, cache and lockout
+// endpoints according to their scopes; account and key management need a user.
 pub fn configure_routes(cfg: &mut web::ServiceConfig) {
     cfg
         // Rate limiting endpoint
-        .service(web::resource("/check-rate-limit").route(web::post().to(check_rate_limit)))
+        .service(
+            web::resource("/check-rate-limit").route(
+                web::post()
+                    .to(check_rate_limit)
+                    .wrap(JwtAuth::allow(CHECK_ROLES).with_api_keys(CHECK_SCOPES)),
+            ),
+        )
+        // Blacklist and rate limit checks in one call
+        .service(
+            web::resource("/decide").route(
+                web::post()
+                    .to(decide)
+                    .wrap(JwtAuth::allow(CHECK_ROLES).with_api_keys(CHECK_SCOPES)),
+            ),
+        )
+        // Policy rule endpoints
+        .service(
+            web::resource("/rules")
+                .route(
+                    web::post()
+                        .to(add_rule)
+                        .wrap(JwtAuth::allow(WRITE_ROLES).with_api_keys(BLACKLIST_SCOPES)),
+                )
+                .route(
+                    web::get()
+                        .to(get_all_rules)
+                        .wrap(JwtAuth::allow(READ_ROLES).with_api_keys(BLACKLIST_SCOPES)),
+                ),
+        )
+        .service(
+            web::resource("/rules/{id}")
+                .route(
+                    web::get()
+                        .to(get_rule_by_id)
+                        .wrap(JwtAuth::allow(READ_ROLES).with_api_keys(BLACKLIST_SCOPES)),
+                )
+                .route(
+                    web::put()
+                        .to(edit_rule_by_id)
+                        .wrap(JwtAuth::allow(WRITE_ROLES).with_api_keys(BLACKLIST_SCOPES)),
+                )
+                .route(
+                    web::delete()
+                        .to(delete_rule_by_id)
+                        .wrap(JwtAuth::allow(WRITE_ROLES).with_api_keys(BLACKLIST_SCOPES)),
+                ),
+        )
+        // Rate limit policy endpoints
+        .service(
+            web::resource("/rate-limit-policies")
+                .route(
+                    web::post()
+                        .to(add_rate_limit_policy)
+                        .wrap(JwtAuth::allow(WRITE_ROLES).with_api_keys(ADMIN_SCOPES)),
+                )
+                .route(
+                    web::get()
+                        .to(get_all_rate_limit_policies)
+                        .wrap(JwtAuth::allow(READ_ROLES).with_api_keys(ADMIN_SCOPES)),
+                ),
+        )
+        .service(
+            web::resource("/rate-limit-policies/{name}")
+                .route(
+                    web::get()
+                        .to(get_rate_limit_policy)
+                        .wrap(JwtAuth::allow(READ_ROLES).with_api_keys(ADMIN_SCOPES)),
+                )
+                .route(
+                    web::delete()
+                        .to(delete_rate_limit_policy)
+                        .wrap(JwtAuth::allow(WRITE_ROLES).with_api_keys(ADMIN_SCOPES)),
+                )
+                .route(
+                    web::put()
+                        .to(edit_rate_limit_policy)
+                        .wrap(JwtAuth::allow(WRITE_ROLES).with_api_keys(ADMIN_SCOPES)),
+                ),
+        )
+        // Allowlist IP endpoints. Entries bypass every protection, so only
+        // admins may change them.
+        .service(
+            web::resource("/allowlist-ip")
+                .route(
+                    web::post()
+                        .to(add_allowlist_ip)
+                        .wrap(JwtAuth::allow(ADMIN_ROLES).with_api_keys(ADMIN_SCOPES)),
+                )
+                .route(
+                    web::get()
+                        .to(get_all_allowlist_ip)
+                        .wrap(JwtAuth::allow(READ_ROLES).with_api_keys(BLACKLIST_SCOPES)),
+                ),
+        )
+        .service(
+            web::resource("/allowlist-ip/{id}")
+                .route(
+                    web::get()
+                        .to(get_allowlist_ip_by_id)
+                        .wrap(JwtAuth::allow(READ_ROLES).with_api_keys(BLACKLIST_SCOPES)),
+                )
+                .route(
+                    web::put()
+                        .to(edit_allowlist_ip_by_id)
+                        .wrap(JwtAuth::allow(ADMIN_ROLES).with_api_keys(ADMIN_SCOPES)),
+                )
+                .route(
+                    web::delete()
+                        .to(delete_allowlist_ip_by_id)
+                        .wrap(JwtAuth::allow(ADMIN_ROLES).with_api_keys(ADMIN_SCOPES)),
+                ),
+        )
         // Blacklist IP endpoints
         .service(
             web::resource("/blacklist-ip")
-                .route(web::post().to(add_blacklist_ip))
-                .route(web::get().to(get_all_blacklist_ip)),
+                .route(
+                    web::post()
+                        .to(add_blacklist_ip)
+                        .wrap(JwtAuth::allow(WRITE_ROLES).with_api_keys(BLACKLIST_SCOPES)),
+                )
+                .route(
+                    web::get()
+                        .to(get_all_blacklist_ip)
+                        .wrap(JwtAuth::allow(READ_ROLES).with_api_keys(BLACKLIST_SCOPES)),
+                ),
         )
         .service(
             web::resource("/blacklist-ip/{id}")
-                .route(web::get().to(get_blacklist_ip_by_id))
-                .route(web::delete().to(delete_blacklist_ip_by_id))
-                .route(web::put().to(edit_blacklist_ip_by_id)),
+                .route(
+                    web::get()
+                        .to(get_blacklist_ip_by_id)
+                        .wrap(JwtAuth::allow(READ_ROLES).with_api_keys(BLACKLIST_SCOPES)),
+                )
+                .route(
+                    web::delete()
+                        .to(delete_blacklist_ip_by_id)
+                        .wrap(JwtAuth::allow(WRITE_ROLES).with_api_keys(BLACKLIST_SCOPES)),
+                )
+                .route(
+                    web::put()
+                        .to(edit_blacklist_ip_by_id)
+                        .wrap(JwtAuth::allow(WRITE_ROLES).with_api_keys(BLACKLIST_SCOPES)),
+                ),
+        )
+        .service(
+            web::resource("/check-blacklist-ip").route(
+                web::post()
+                    .to(is_blacklist_ip)
+                    .wrap(JwtAuth::allow(CHECK_ROLES).with_api_keys(CHECK_SCOPES)),
+            ),
         )
-        .service(web::resource("/check-blacklist-ip").route(web::post().to(is_blacklist_ip)))
         // Blacklist URL endpoints
         .service(
             web::resource("/blacklist-url")
-                .route(web::post().to(add_blacklist_url))
-                .route(web::get().to(get_all_blacklist_url)),
+                .route(
+                    web::post()
+                        .to(add_blacklist_url)
+                        .wrap(JwtAuth::allow(WRITE_ROLES).with_api_keys(BLACKLIST_SCOPES)),
+                )
+                .route(
+                    web::get()
+                        .to(get_all_blacklist_url)
+                        .wrap(JwtAuth::allow(READ_ROLES).with_api_keys(BLACKLIST_SCOPES)),
+                ),
         )
         .service(
             web::resource("/blacklist-url/{id}")
-                .route(web::get().to(get_blacklist_url_by_id))
-                .route(web::delete().to(delete_blacklist_url_by_id))
-                .route(web::put().to(edit_blacklist_url_by_id)),
+                .route(
+                    web::get()
+                        .to(get_blacklist_url_by_id)
+                        .wrap(JwtAuth::allow(READ_ROLES).with_api_keys(BLACKLIST_SCOPES)),
+                )
+                .route(
+                    web::delete()
+                        .to(delete_blacklist_url_by_id)
+                        .wrap(JwtAuth::allow(WRITE_ROLES).with_api_keys(BLACKLIST_SCOPES)),
+                )
+                .route(
+                    web::put()
+                        .to(edit_blacklist_url_by_id)
+                        .wrap(JwtAuth::allow(WRITE_ROLES).with_api_keys(BLACKLIST_SCOPES)),
+                ),
+        )
+        .service(
+            web::resource("/check-blacklist-url").route(
+                web::post()
+                    .to(is_blacklist_url)
+                    .wrap(JwtAuth::allow(CHECK_ROLES).with_api_keys(CHECK_SCOPES)),
+            ),
         )
-        .service(web::resource("/check-blacklist-url").route(web::post().to(is_blacklist_url)))
+        // Blacklist cache status
+        .service(
+            web::resource("/cache/status").route(
+                web::get()
+                    .to(cache_status)
+                    .wrap(JwtAuth::allow(READ_ROLES).with_api_keys(ADMIN_SCOPES)),
+            ),
+        )
+        // Public keys for verifying issued tokens
+        .service(web::resource("/.well-known/jwks.json").route(web::get().to(jwks)))
         // User endpoints
         .service(web::resource("/signup").route(web::post().to(signup)))
-        .service(web::resource("/signin").route(web::post().to(signin)));
+        .service(web::resource("/signin").route(web::post().to(signin)))
+        .service(web::resource("/signin/mfa").route(web::post().to(signin_mfa)))
+        .service(web::resource("/signin/mfa/enroll").route(web::post().to(signin_mfa_enroll)))
+        .service(web::resource("/token/refresh").route(web::post().to(refresh_token)))
+        .service(web::resource("/password/reset").route(web::post().to(request_password_reset)))
+        .service(
+            web::resource("/password/reset/confirm").route(web::post().to(confirm_password_reset)),
+        )
+        .service(
+            web::resource("/signout")
+                .route(web::post().to(signout).wrap(JwtAuth::allow(CHECK_ROLES))),
+        )
+        // User administration
+        .service(
+            web::resource("/users").route(
+                web::get()
+                    .to(get_all_users)
+                    .wrap(JwtAuth::allow(ADMIN_ROLES)),
+            ),
+        )
+        .service(
+            web::resource("/users/{id}")
+                .route(
+                    web::get()
+                        .to(get_user_by_id)
+                        .wrap(JwtAuth::allow(ADMIN_ROLES)),
+                )
+                .route(
+                    web::delete()
+                        .to(delete_user_by_id)
+                        .wrap(JwtAuth::allow(ADMIN_ROLES)),
+                ),
+        )
+        .service(
+            web::resource("/users/{id}/approve").route(
+                web::post()
+                    .to(approve_user)
+                    .wrap(JwtAuth::allow(ADMIN_ROLES)),
+            ),
+        )
+        .service(
+            web::resource("/users/{id}/reject").route(
+                web::post()
+                    .to(reject_user)
+                    .wrap(JwtAuth::allow(ADMIN_ROLES)),
+            ),
+        )
+        .service(
+            web::resource("/users/{id}/suspend").route(
+                web::post()
+                    .to(suspend_user)
+                    .wrap(JwtAuth::allow(ADMIN_ROLES)),
+            ),
+        )
+        .service(
+            web::resource("/users/{id}/reactivate").route(
+                web::post()
+                    .to(reactivate_user)
+                    .wrap(JwtAuth::allow(ADMIN_ROLES)),
+            ),
+        )
+        .service(
+            web::resource("/users/{id}/role").route(
+                web::put()
+                    .to(change_user_role)
+                    .wrap(JwtAuth::allow(ADMIN_ROLES)),
+            ),
+        )
+        .service(
+            web::resource("/users/{id}/password-reset").route(
+                web::post()
+                    .to(reset_user_password)
+                    .wrap(JwtAuth::allow(ADMIN_ROLES)),
+            ),
+        )
+        .service(
+            web::resource("/users/{id}/mfa").route(
+                web::delete()
+                    .to(reset_user_mfa)
+                    .wrap(JwtAuth::allow(ADMIN_ROLES)),
+            ),
+        )
+        .service(
+            web::resource("/password/change").route(
+                web::post()
+                    .to(change_password)
+                    .wrap(JwtAuth::allow(CHECK_ROLES)),
+            ),
+        )
+        // Two-factor authentication
+        .service(
+            web::resource("/mfa/totp/enroll").route(
+                web::post()
+                    .to(enroll_totp)
+                    .wrap(JwtAuth::allow(CHECK_ROLES)),
+            ),
+        )
+        .service(
+            web::resource("/mfa/totp/confirm").route(
+                web::post()
+                    .to(confirm_totp)
+                    .wrap(JwtAuth::allow(CHECK_ROLES)),
+            ),
+        )
+        .service(
+            web::resource("/mfa/totp/disable").route(
+                web::post()
+                    .to(disable_totp)
+                    .wrap(JwtAuth::allow(CHECK_ROLES)),
+            ),
+        )
+        .service(
+            web::resource("/mfa/recovery-codes").route(
+                web::post()
+                    .to(new_recovery_codes)
+                    .wrap(JwtAuth::allow(CHECK_ROLES)),
+            ),
+        )
+        .service(
+            web::resource("/mfa/policy")
+                .route(
+                    web::get()
+                        .to(get_mfa_policy)
+                        .wrap(JwtAuth::allow(ADMIN_ROLES)),
+                )
+                .route(
+                    web::put()
+                        .to(update_mfa_policy)
+                        .wrap(JwtAuth::allow(ADMIN_ROLES)),
+                ),
+        )
+        // Signin lockouts
+        .service(
+            web::resource("/signin-lockouts").route(
+                web::get()
+                    .to(get_all_signin_lockouts)
+                    .wrap(JwtAuth::allow(ADMIN_ROLES).with_api_keys(ADMIN_SCOPES)),
+            ),
+        )
+        .service(
+            web::resource("/signin-lockouts/{id}").route(
+                web::delete()
+                    .to(delete_signin_lockout)
+                    .wrap(JwtAuth::allow(ADMIN_ROLES).with_api_keys(ADMIN_SCOPES)),
+            ),
+        )
+        .service(
+            web::resource("/users/{id}/sessions").route(
+                web::delete()
+                    .to(revoke_all_sessions)
+                    .wrap(JwtAuth::allow(ADMIN_ROLES)),
+            ),
+        )
+        // API keys for services
+        .service(
+            web::resource("/api-keys")
+                .route(
+                    web::post()
+                        .to(create_api_key)
+                        .wrap(JwtAuth::allow(ADMIN_ROLES)),
+                )
+                .route(
+                    web::get()
+                        .to(get_all_api_keys)
+                        .wrap(JwtAuth::allow(ADMIN_ROLES)),
+                ),
+        )
+        .service(
+            web::resource("/api-keys/{id}")
+                .route(
+                    web::get()
+                        .to(get_api_key_by_id)
+                        .wrap(JwtAuth::allow(ADMIN_ROLES)),
+                )
+                .route(
+                    web::put()
+                        .to(edit_api_key_by_id)
+                        .wrap(JwtAuth::allow(ADMIN_ROLES)),
+                )
+                .route(
+                    web::delete()
+                        .to(delete_api_key_by_id)
+                        .wrap(JwtAuth::allow(ADMIN_ROLES)),
+                ),
+        );
 }
 
 pub fn configure_greet(cfg: &mut web::ServiceConfig) {
@@ -0,0 +1,70 @@
+// src/rules/glob.rs
+
+// Match `text` against a pattern where `*` stands for any run of characters
+// and `?` for exactly one. Runs in O(pattern * text) at worst, whatever the
+// pattern, so user-supplied patterns cannot cause runaway backtracking.
+pub fn glob_match(pattern: &str, text: &str, case_insensitive: bool) -> bool {
+    let fold = |s: &str| -> Vec<char> {
+        if case_insensitive {
+            s.to_lowercase().chars().collect()
+        } else {
+            s.chars().collect()
+        }
+    };
+    let pattern = fold(pattern);
+    let text = fold(text);
+
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` seen and the text position it was tried at
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                // Let the last `*` swallow one more character and retry
+                Some((star_p, star_t)) => {
+                    p = star_p + 1;
+                    t = star_t + 1;
+                    star = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcards_match_runs_and_single_characters() {
+        assert!(glob_match("/admin/*", "/admin/users/1", false));
+        assert!(glob_match("/admin/*", "/admin/", false));
+        assert!(!glob_match("/admin/*", "/admin", false));
+        assert!(glob_match("/v?/items", "/v2/items", false));
+        assert!(glob_match("*.example.com", "api.example.com", false));
+        assert!(!glob_match("*.example.com", "example.com", false));
+        assert!(glob_match("*bot*", "Googlebot/2.1", false));
+    }
+
+    #[test]
+    fn case_folding_is_optional() {
+        assert!(glob_match("*.Example.COM", "api.example.com", true));
+        assert!(!glob_match("/Admin", "/admin", false));
+    }
+
+    #[test]
+    fn pathological_patterns_finish_quickly() {
+        let text = "a".repeat(10_000);
+        assert!(!glob_match("*a*a*a*a*a*a*a*b", &text, false));
+    }
+}
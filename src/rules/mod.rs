@@ -0,0 +1,342 @@
+// src/rules/mod.rs
+// Stored policy rules: conditions on a request plus the action to take when
+// they all hold. Evaluation is pure so it can be tested without MongoDB.
+pub mod glob;
+
+use crate::models::PolicyRule;
+use crate::net::{normalize_path, IpRange};
+use glob::glob_match;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RuleAction {
+    // Let the request through without checking the blacklists or rate limits
+    Allow,
+    Deny,
+    // Ask the client to prove it is not a bot, e.g. with a CAPTCHA
+    Challenge,
+    // Count the request against the named rate limit policy
+    RateLimit { policy: String },
+    // Report the match without affecting the verdict
+    LogOnly,
+}
+
+impl RuleAction {
+    // Whether a matching rule ends evaluation; log-only rules never do
+    pub fn is_final(&self) -> bool {
+        !matches!(self, RuleAction::LogOnly)
+    }
+
+    // Among rules of equal priority the more restrictive action goes first
+    fn severity(&self) -> u8 {
+        match self {
+            RuleAction::Deny => 4,
+            RuleAction::Challenge => 3,
+            RuleAction::RateLimit { .. } => 2,
+            RuleAction::Allow => 1,
+            RuleAction::LogOnly => 0,
+        }
+    }
+}
+
+// Every condition that is set must hold; within one condition, any of the
+// listed values may match. Conditions on facts the request lacks never hold.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RuleConditions {
+    // Addresses or CIDRs, e.g. "203.0.113.0/24"
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ips: Vec<String>,
+    // Globs, e.g. "/admin/*", matched against the normalized path so that
+    // "/%61dmin/x", "//admin/x" and "/x/../admin/x" all match "/admin/*"
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub paths: Vec<String>,
+    // Case-insensitive globs, e.g. "*.example.com"
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hosts: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub methods: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub headers: Vec<HeaderCondition>,
+    // Case-insensitive globs, e.g. "*curl*"
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub user_agents: Vec<String>,
+    // ISO 3166-1 alpha-2 codes, e.g. "NL"
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub countries: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HeaderCondition {
+    pub name: String, // Case-insensitive
+    // Glob the value must match; the header only has to be present when omitted
+    pub value: Option<String>,
+}
+
+// What is known about the request being evaluated
+#[derive(Debug, Clone)]
+pub struct RequestFacts {
+    pub ip: IpAddr,
+    pub path: Option<String>,
+    pub host: Option<String>,
+    pub method: Option<String>,
+    pub headers: HashMap<String, String>, // Names lowercased
+    pub country: Option<String>,
+}
+
+impl RequestFacts {
+    pub fn new(ip: IpAddr) -> Self {
+        RequestFacts {
+            ip,
+            path: None,
+            host: None,
+            method: None,
+            headers: HashMap::new(),
+            country: None,
+        }
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(&name.to_lowercase()).map(String::as_str)
+    }
+}
+
+// True when `fact` is known and matches at least one of `patterns`
+fn any_glob(patterns: &[String], fact: Option<&str>, case_insensitive: bool) -> bool {
+    fact.is_some_and(|fact| {
+        patterns
+            .iter()
+            .any(|pattern| glob_match(pattern, fact, case_insensitive))
+    })
+}
+
+impl RuleConditions {
+    pub fn is_empty(&self) -> bool {
+        self == &RuleConditions::default()
+    }
+
+    pub fn matches(&self, facts: &RequestFacts) -> bool {
+        (self.ips.is_empty()
+            || self
+                .ips
+                .iter()
+                .any(|ip| IpRange::parse(ip).is_ok_and(|range| range.contains(&facts.ip))))
+            && (self.paths.is_empty()
+                || any_glob(
+                    &self.paths,
+                    facts.path.as_deref().map(normalize_path).as_deref(),
+                    false,
+                ))
+            && (self.hosts.is_empty() || any_glob(&self.hosts, facts.host.as_deref(), true))
+            && (self.methods.is_empty()
+                || facts.method.as_deref().is_some_and(|method| {
+                    self.methods.iter().any(|m| m.eq_ignore_ascii_case(method))
+                }))
+            && self.headers.iter().all(|condition| {
+                match (facts.header(&condition.name), &condition.value) {
+                    (Some(value), Some(pattern)) => glob_match(pattern, value, false),
+                    (Some(_), None) => true,
+                    (None, _) => false,
+                }
+            })
+            && (self.user_agents.is_empty()
+                || any_glob(&self.user_agents, facts.header("user-agent"), true))
+            && (self.countries.is_empty()
+                || facts.country.as_deref().is_some_and(|country| {
+                    self.countries
+                        .iter()
+                        .any(|c| c.eq_ignore_ascii_case(country.trim()))
+                }))
+    }
+}
+
+pub struct Evaluation<'r> {
+    // Every rule that matched, in evaluation order, log-only rules included
+    pub matched: Vec<&'r PolicyRule>,
+    // The rule whose action applies, if any rule but a log-only one matched
+    pub decision: Option<&'r PolicyRule>,
+}
+
+// Evaluate enabled rules from the highest priority down; the first matching
+// rule that is not log-only decides. This lets a narrow allow rule with a
+// higher priority override a broader deny.
+pub fn evaluate<'r>(rules: &'r [PolicyRule], facts: &RequestFacts) -> Evaluation<'r> {
+    let mut ordered: Vec<&PolicyRule> = rules.iter().filter(|rule| rule.enabled).collect();
+    ordered.sort_by(|a, b| {
+        b.priority
+            .cmp(&a.priority)
+            .then(b.action.severity().cmp(&a.action.severity()))
+            .then(a.name.cmp(&b.name))
+    });
+
+    let mut matched = Vec::new();
+    for rule in ordered {
+        if rule.conditions.matches(facts) {
+            matched.push(rule);
+            if rule.action.is_final() {
+                return Evaluation {
+                    matched,
+                    decision: Some(rule),
+                };
+            }
+        }
+    }
+    Evaluation {
+        matched,
+        decision: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(
+        name: &str,
+        priority: i32,
+        action: RuleAction,
+        conditions: RuleConditions,
+    ) -> PolicyRule {
+        PolicyRule::new(name.to_string(), None, priority, action, conditions, None)
+    }
+
+    fn ips(ips: &[&str]) -> RuleConditions {
+        RuleConditions {
+            ips: ips.iter().map(|ip| ip.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn facts(ip: &str) -> RequestFacts {
+        RequestFacts::new(ip.parse().unwrap())
+    }
+
+    fn decided<'r>(rules: &'r [PolicyRule], facts: &RequestFacts) -> Option<&'r str> {
+        evaluate(rules, facts)
+            .decision
+            .map(|rule| rule.name.as_str())
+    }
+
+    #[test]
+    fn higher_priority_allow_overrides_broader_deny() {
+        let rules = vec![
+            rule(
+                "block-range",
+                10,
+                RuleAction::Deny,
+                ips(&["203.0.113.0/24"]),
+            ),
+            rule("office", 100, RuleAction::Allow, ips(&["203.0.113.7"])),
+        ];
+        assert_eq!(decided(&rules, &facts("203.0.113.7")), Some("office"));
+        assert_eq!(decided(&rules, &facts("203.0.113.8")), Some("block-range"));
+        assert_eq!(decided(&rules, &facts("198.51.100.1")), None);
+    }
+
+    #[test]
+    fn equal_priority_prefers_the_restrictive_action() {
+        let rules = vec![
+            rule("allow", 5, RuleAction::Allow, ips(&["203.0.113.7"])),
+            rule("deny", 5, RuleAction::Deny, ips(&["203.0.113.7"])),
+        ];
+        assert_eq!(decided(&rules, &facts("203.0.113.7")), Some("deny"));
+    }
+
+    #[test]
+    fn log_only_rules_are_reported_but_do_not_decide() {
+        let rules = vec![
+            rule("watch", 50, RuleAction::LogOnly, ips(&["203.0.113.0/24"])),
+            rule(
+                "limit",
+                1,
+                RuleAction::RateLimit {
+                    policy: "strict".to_string(),
+                },
+                ips(&["0.0.0.0/0"]),
+            ),
+        ];
+        let result = evaluate(&rules, &facts("203.0.113.7"));
+        let matched: Vec<&str> = result.matched.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(matched, vec!["watch", "limit"]);
+        assert_eq!(result.decision.map(|r| r.name.as_str()), Some("limit"));
+    }
+
+    #[test]
+    fn disabled_rules_are_skipped() {
+        let mut deny = rule("deny", 1, RuleAction::Deny, ips(&["203.0.113.7"]));
+        deny.enabled = false;
+        assert_eq!(decided(&[deny], &facts("203.0.113.7")), None);
+    }
+
+    #[test]
+    fn all_conditions_must_hold() {
+        let conditions = RuleConditions {
+            paths: vec!["/admin/*".to_string()],
+            hosts: vec!["*.example.com".to_string()],
+            methods: vec!["post".to_string()],
+            headers: vec![HeaderCondition {
+                name: "X-Debug".to_string(),
+                value: None,
+            }],
+            user_agents: vec!["*curl*".to_string()],
+            countries: vec!["nl".to_string()],
+            ..Default::default()
+        };
+        let rules = vec![rule("admin-posts", 1, RuleAction::Deny, conditions)];
+
+        let mut request = facts("198.51.100.1");
+        request.path = Some("/admin/users".to_string());
+        request.host = Some("API.example.com".to_string());
+        request.method = Some("POST".to_string());
+        request.headers = HashMap::from([
+            ("x-debug".to_string(), "1".to_string()),
+            ("user-agent".to_string(), "curl/8.0".to_string()),
+        ]);
+        request.country = Some("NL".to_string());
+        assert_eq!(decided(&rules, &request), Some("admin-posts"));
+
+        let mut other_country = request.clone();
+        other_country.country = Some("DE".to_string());
+        assert_eq!(decided(&rules, &other_country), None);
+
+        let mut no_header = request.clone();
+        no_header.headers.remove("x-debug");
+        assert_eq!(decided(&rules, &no_header), None);
+
+        let mut unknown_path = request;
+        unknown_path.path = None;
+        assert_eq!(decided(&rules, &unknown_path), None);
+    }
+
+    #[test]
+    fn path_rules_cannot_be_bypassed_with_another_spelling() {
+        let conditions = RuleConditions {
+            paths: vec!["/admin/*".to_string()],
+            ..Default::default()
+        };
+        let rules = vec![rule("admin", 1, RuleAction::Deny, conditions)];
+        for path in [
+            "/admin/x",
+            "/%61dmin/x",
+            "//admin/x",
+            "/x/../admin/",
+            "/./admin//x",
+        ] {
+            let mut request = facts("198.51.100.1");
+            request.path = Some(path.to_string());
+            assert_eq!(decided(&rules, &request), Some("admin"), "{}", path);
+        }
+        let mut other = facts("198.51.100.1");
+        other.path = Some("/administrator".to_string());
+        assert_eq!(decided(&rules, &other), None);
+    }
+
+    #[test]
+    fn cidrs_do_not_match_the_other_family() {
+        let rules = vec![rule("all-v4", 1, RuleAction::Deny, ips(&["0.0.0.0/0"]))];
+        assert_eq!(decided(&rules, &facts("2001:db8::1")), None);
+        assert_eq!(decided(&rules, &facts("192.0.2.1")), Some("all-v4"));
+    }
+}